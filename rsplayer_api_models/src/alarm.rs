@@ -0,0 +1,117 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// Alarm starting the playback at a time of day, with the volume ramped up from `start_volume` to
+/// `target_volume`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct Alarm {
+    pub id: String,
+    pub enabled: bool,
+    /// Local time of day.
+    pub time: NaiveTime,
+    /// Days the alarm rings on, every day when empty.
+    pub weekdays: Vec<Weekday>,
+    pub source: AlarmSource,
+    /// Volume in percent of the volume range of the device.
+    #[validate(range(max = 100))]
+    pub start_volume: u8,
+    /// Volume in percent of the volume range of the device.
+    #[validate(range(max = 100))]
+    pub target_volume: u8,
+    /// Seconds in which the volume is raised from the start to the target volume.
+    #[validate(range(max = 3600))]
+    pub ramp_up_secs: u16,
+}
+
+/// What the alarm loads into the queue before it starts the playback.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlarmSource {
+    Playlist(String),
+    Album(String),
+    RadioStation(String),
+    Directory(String),
+}
+
+impl Alarm {
+    /// Whether the alarm rings after `from`, up to and including `to`.
+    #[must_use]
+    pub fn rings_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        from.date()
+            .iter_days()
+            .take_while(|date| *date <= to.date())
+            .filter(|date| self.weekdays.is_empty() || self.weekdays.contains(&date.weekday()))
+            .map(|date| date.and_time(self.time))
+            .any(|ring| ring > from && ring <= to)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+    use validator::Validate;
+
+    use super::{Alarm, AlarmSource};
+
+    fn alarm(weekdays: Vec<Weekday>) -> Alarm {
+        Alarm {
+            id: "wake up".to_string(),
+            enabled: true,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            weekdays,
+            source: AlarmSource::Directory("morning".to_string()),
+            start_volume: 10,
+            target_volume: 50,
+            ramp_up_secs: 60,
+        }
+    }
+
+    /// Time on Monday, 2024-01-01.
+    fn monday(hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, min, sec)
+            .unwrap()
+    }
+
+    #[test]
+    fn should_ring_once_when_its_time_is_passed() {
+        let alarm = alarm(vec![]);
+        assert!(alarm.rings_between(monday(6, 59, 59), monday(7, 0, 0)));
+        assert!(!alarm.rings_between(monday(7, 0, 0), monday(7, 0, 1)));
+        assert!(!alarm.rings_between(monday(6, 59, 58), monday(6, 59, 59)));
+    }
+
+    #[test]
+    fn should_ring_across_midnight() {
+        let alarm = alarm(vec![]);
+        let sunday_night = monday(23, 0, 0) - chrono::Duration::days(1);
+        assert!(alarm.rings_between(sunday_night, monday(7, 30, 0)));
+        assert!(!alarm.rings_between(sunday_night, monday(6, 0, 0)));
+    }
+
+    #[test]
+    fn should_ring_only_on_its_weekdays() {
+        assert!(alarm(vec![Weekday::Mon, Weekday::Tue]).rings_between(monday(6, 59, 59), monday(7, 0, 0)));
+        assert!(!alarm(vec![Weekday::Sat, Weekday::Sun]).rings_between(monday(6, 59, 59), monday(7, 0, 0)));
+    }
+
+    #[test]
+    fn should_not_ring_when_disabled() {
+        let mut alarm = alarm(vec![]);
+        alarm.enabled = false;
+        assert!(!alarm.rings_between(monday(6, 59, 59), monday(7, 0, 0)));
+    }
+
+    #[test]
+    fn should_reject_volumes_above_100_percent() {
+        let mut alarm = alarm(vec![]);
+        assert!(alarm.validate().is_ok());
+        alarm.target_volume = 101;
+        assert!(alarm.validate().is_err());
+    }
+}
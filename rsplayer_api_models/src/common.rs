@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use crate::{player::Song, state::CurrentQueueQuery};
+use crate::{alarm::Alarm, player::Song, settings::DspSettings, state::CurrentQueueQuery};
 use chrono::{DateTime, Utc};
 use num_derive::ToPrimitive;
 use serde::{Deserialize, Serialize};
@@ -71,6 +71,7 @@ pub struct AudioCard {
 pub enum VolumeCrtlType {
     Dac,
     Alsa,
+    Software,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -81,15 +82,18 @@ pub struct Volume {
     pub current: i64,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum UserCommand {
     Player(PlayerCommand),
     Queue(QueueCommand),
     Playlist(PlaylistCommand),
     Metadata(MetadataCommand),
+    Dsp(DspCommand),
+    Alarm(AlarmCommand),
+    Podcast(PodcastCommand),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum PlayerCommand {
     // Player commands
     Next,
@@ -99,8 +103,71 @@ pub enum PlayerCommand {
     Play,
     PlayItem(String),
     RandomToggle,
-    Seek(u16),
+    SetRepeatMode(RepeatMode),
+    /// Stops once the current song finished, sending it again cancels the request.
+    StopAfterCurrentSong,
+    /// Stops once the last song of the current album finished, sending it again cancels the request.
+    StopAfterCurrentAlbum,
+    Seek(SeekTarget),
     QueryCurrentPlayerInfo,
+    /// Starts or stops sending the audio levels to the client.
+    SubscribeAudioLevels(bool),
+    /// Fades the volume out and stops the playback, replacing the running timer.
+    SetSleepTimer(SleepTimer),
+    CancelSleepTimer,
+    QuerySleepTimer,
+}
+
+/// What plays when the current song finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum RepeatMode {
+    /// Playback stops at the end of the queue.
+    #[default]
+    Off,
+    /// The queue starts over after its last song.
+    Queue,
+    /// The current song plays again.
+    Single,
+}
+
+impl RepeatMode {
+    /// The mode selected by the repeat button after this one.
+    #[must_use]
+    pub const fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Queue,
+            Self::Queue => Self::Single,
+            Self::Single => Self::Off,
+        }
+    }
+}
+
+/// One-shot request to stop the playback, cleared once it is fulfilled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum StopAfter {
+    #[default]
+    Off,
+    CurrentSong,
+    CurrentAlbum,
+}
+
+/// When the sleep timer stops the playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SleepTimer {
+    Minutes(u32),
+    EndOfSong,
+    EndOfAlbum,
+}
+
+/// Position within the current song to seek to.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum SeekTarget {
+    /// Milliseconds from the start of the song.
+    Millis(u64),
+    /// Milliseconds from the current position, negative values seek back.
+    RelativeMillis(i64),
+    /// Percentage of the song duration.
+    Percent(f32),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -143,6 +210,45 @@ pub enum QueueCommand {
     RemoveItem(String),
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum DspCommand {
+    /// Applies the settings to the playing song and saves them.
+    SetDspSettings(DspSettings),
+    QueryDspSettings,
+    SaveDspPreset(String),
+    LoadDspPreset(String),
+    DeleteDspPreset(String),
+    QueryDspPresets,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AlarmCommand {
+    /// Adds the alarm, or replaces the one with the same id. An id is assigned when it is empty.
+    SaveAlarm(Alarm),
+    DeleteAlarm(String),
+    QueryAlarms,
+    /// Stops the ringing alarm and plays it again after the snooze time.
+    Snooze,
+    /// Stops the ringing or snoozed alarm.
+    Dismiss,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PodcastCommand {
+    /// Subscribes to the podcast with the url of its feed.
+    Subscribe(String),
+    Unsubscribe(String),
+    /// Fetches the feeds of all podcasts for new episodes.
+    RefreshPodcasts,
+    QueryPodcasts,
+    QueryEpisodes(String),
+    /// Adds the episode with the feed url and audio url to the queue.
+    AddEpisodeToQueue(String, String),
+    /// Plays the episode with the feed url and audio url from where it was left.
+    PlayEpisode(String, String),
+    SetEpisodePlayed(String, String, bool),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum SystemCommand {
     // System commands
@@ -174,6 +280,66 @@ pub enum GainLevel {
     V375,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum CrossfadeCurve {
+    #[default]
+    Linear,
+    EqualPower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Album gain while an album is played in order, track gain otherwise.
+    Auto,
+}
+
+/// How DSD songs are sent to the DAC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum DsdOutput {
+    /// Converted to PCM, works with every DAC.
+    #[default]
+    Pcm,
+    /// DSD over PCM, packed into 24 bit PCM samples for DACs that unpack it.
+    Dop,
+    /// Raw DSD through ALSA, converted to PCM when the device does not accept it.
+    Native,
+}
+
+/// Audio output implementation used for playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum OutputBackend {
+    /// Portable output that plays in the default format of the device.
+    #[default]
+    Cpal,
+    /// Direct ALSA output at the source rate, bit-perfect on `hw:` devices.
+    Alsa,
+}
+
+/// Whether the played audio is served as a FLAC stream on `/api/stream`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum HttpStreamMode {
+    #[default]
+    Off,
+    /// Streamed while playing on the audio device.
+    WithLocalOutput,
+    /// Streamed only, the audio device is not opened.
+    StreamOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumIter, IntoStaticStr, Default)]
+pub enum EqFilterType {
+    #[default]
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
 #[must_use]
 pub fn dur_to_string(duration: &Duration) -> String {
     let mut result = "00:00:00".to_string();
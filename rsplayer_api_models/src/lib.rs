@@ -1,6 +1,8 @@
+pub mod alarm;
 pub mod common;
 pub mod player;
 pub mod playlist;
+pub mod podcast;
 pub mod settings;
 pub mod stat;
 pub mod state;
@@ -60,9 +60,39 @@ pub struct Song {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub statistics: Option<PlayItemStatistics>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cue_track: Option<CueTrack>,
+
+    /// Why the song failed to play, set on its queue entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub play_error: Option<String>,
+}
+
+/// Part of a single file album image, played as a song of its own as described by a CUE sheet.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CueTrack {
+    /// Key of the image file, relative to the music directory.
+    pub image_file: String,
+    pub start: Duration,
+    /// `None` for the last track, which plays until the end of the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<Duration>,
 }
 
 impl Song {
+    /// File holding the audio of the song, the album image for CUE sheet tracks.
+    #[must_use]
+    pub fn source_file(&self) -> &str {
+        self.cue_track.as_ref().map_or(&self.file, |cue| &cue.image_file)
+    }
+
+    /// Whether the other song is from the same album, songs without an album never are.
+    #[must_use]
+    pub fn is_same_album(&self, other: &Self) -> bool {
+        self.album.is_some() && self.album == other.album && self.album_artist == other.album_artist
+    }
+
     #[must_use]
     pub fn to_json_string_bytes(&self) -> Vec<u8> {
         serde_json::to_vec(self).expect("Song serialization failed!")
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Subscription to the RSS or Atom feed of a podcast, identified by the url of the feed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct Podcast {
+    pub feed_url: String,
+    pub title: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refreshed: Option<DateTime<Utc>>,
+
+    /// Why the last refresh of the feed failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_error: Option<String>,
+}
+
+/// Episode of a podcast, identified by the url of its feed and of its audio, which is played as a
+/// queue item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct Episode {
+    pub audio_url: String,
+    pub feed_url: String,
+    pub title: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Duration>,
+
+    pub played: bool,
+
+    /// Position the playback of the episode resumes from.
+    pub position: Duration,
+}
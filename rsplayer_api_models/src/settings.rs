@@ -3,9 +3,12 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
-use crate::common::{AudioCard, CardMixer, FilterType, GainLevel, PcmOutputDevice, VolumeCrtlType};
+use crate::common::{
+    AudioCard, CardMixer, CrossfadeCurve, DsdOutput, EqFilterType, FilterType, GainLevel, HttpStreamMode,
+    OutputBackend, PcmOutputDevice, ReplayGainMode, VolumeCrtlType,
+};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
 pub struct Settings {
     pub volume_ctrl_settings: VolumeControlSettings,
     pub output_selector_settings: OutputSelectorSettings,
@@ -22,10 +25,22 @@ pub struct Settings {
     pub playlist_settings: PlaylistSetting,
     #[serde(default)]
     #[validate]
+    pub podcast_settings: PodcastSettings,
+    #[serde(default)]
+    #[validate]
     pub rs_player_settings: RsPlayerSettings,
+    #[serde(default)]
+    #[validate]
+    pub dsp_settings: DspSettings,
+    #[serde(default)]
+    pub convolution_settings: ConvolutionSettings,
+    #[serde(default)]
+    #[validate]
+    pub output_channel_settings: OutputChannelSettings,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct RsPlayerSettings {
     pub enabled: bool,
 
@@ -41,6 +56,76 @@ pub struct RsPlayerSettings {
     #[validate(range(min = 1, max = 99))]
     pub player_threads_priority: u8,
     pub alsa_buffer_size: Option<u32>,
+
+    #[serde(default)]
+    pub output_backend: OutputBackend,
+
+    /// Period size in frames of the ALSA output, `None` leaves it to the driver.
+    #[serde(default)]
+    pub alsa_period_size: Option<u32>,
+
+    /// The ALSA output writes straight into the memory mapped device buffer.
+    #[serde(default)]
+    pub alsa_mmap: bool,
+
+    /// Sample rate all songs are converted to before output, `None` plays them at their own rate.
+    #[serde(default)]
+    pub resample_rate: Option<u32>,
+
+    /// Crossfade duration between songs, 0 disables crossfade.
+    #[serde(default)]
+    #[validate(range(max = 12000))]
+    pub crossfade_ms: usize,
+
+    #[serde(default)]
+    pub crossfade_curve: CrossfadeCurve,
+
+    #[serde(default)]
+    pub replay_gain_mode: ReplayGainMode,
+
+    /// Gain in dB added to the replay gain of the song.
+    #[serde(default)]
+    #[validate(range(min = -15, max = 15))]
+    pub replay_gain_preamp_db: i8,
+
+    /// Lowers the replay gain when the song peak would clip.
+    #[serde(default = "replay_gain_prevent_clipping_default_value")]
+    pub replay_gain_prevent_clipping: bool,
+
+    #[serde(default)]
+    pub dsd_output: DsdOutput,
+
+    /// Audio level events sent per second while a client subscribed to them.
+    #[serde(default = "audio_levels_rate_default_value")]
+    #[validate(range(min = 1, max = 60))]
+    pub audio_levels_rate_hz: u32,
+
+    #[serde(default)]
+    pub http_stream_mode: HttpStreamMode,
+
+    /// Seconds before the sleep timer stops the playback during which the volume is faded out.
+    #[serde(default = "sleep_timer_fade_default_value")]
+    #[validate(range(min = 0, max = 600))]
+    pub sleep_timer_fade_secs: u16,
+
+    #[serde(default = "alarm_snooze_default_value")]
+    #[validate(range(min = 1, max = 60))]
+    pub alarm_snooze_minutes: u16,
+
+    /// Failed songs in a row that are skipped before the playback stops, 0 stops at the first one.
+    #[serde(default = "skip_unplayable_songs_default_value")]
+    #[validate(range(max = 100))]
+    pub skip_unplayable_songs: u16,
+
+    /// Files that fail to play are added to the ignored files and removed from the library.
+    #[serde(default)]
+    pub ignore_unplayable_files: bool,
+
+    /// Memory in MB for the current song and the prefetched next one, which are read into it
+    /// before they are played. Bigger files and `None` stream from the disk.
+    #[serde(default)]
+    #[validate(range(min = 16, max = 8192))]
+    pub load_into_ram_mb: Option<usize>,
 }
 const fn thread_priority_default_value() -> u8 {
     1
@@ -51,6 +136,21 @@ const fn ring_buffer_size_default_value() -> usize {
 const fn input_stream_buffer_size_default_value() -> usize {
     10
 }
+const fn replay_gain_prevent_clipping_default_value() -> bool {
+    true
+}
+const fn audio_levels_rate_default_value() -> u32 {
+    20
+}
+const fn sleep_timer_fade_default_value() -> u16 {
+    30
+}
+const fn alarm_snooze_default_value() -> u16 {
+    9
+}
+const fn skip_unplayable_songs_default_value() -> u16 {
+    3
+}
 
 impl Default for RsPlayerSettings {
     fn default() -> Self {
@@ -60,6 +160,104 @@ impl Default for RsPlayerSettings {
             ring_buffer_size_ms: 200,
             player_threads_priority: 1,
             alsa_buffer_size: None,
+            output_backend: OutputBackend::Cpal,
+            alsa_period_size: None,
+            alsa_mmap: false,
+            resample_rate: None,
+            crossfade_ms: 0,
+            crossfade_curve: CrossfadeCurve::Linear,
+            replay_gain_mode: ReplayGainMode::Off,
+            replay_gain_preamp_db: 0,
+            replay_gain_prevent_clipping: true,
+            dsd_output: DsdOutput::Pcm,
+            audio_levels_rate_hz: 20,
+            http_stream_mode: HttpStreamMode::Off,
+            sleep_timer_fade_secs: 30,
+            alarm_snooze_minutes: 9,
+            skip_unplayable_songs: 3,
+            ignore_unplayable_files: false,
+            load_into_ram_mb: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Default)]
+pub struct DspSettings {
+    pub enabled: bool,
+    #[serde(default)]
+    #[validate(range(min = -24.0, max = 24.0))]
+    pub preamp_db: f32,
+    #[serde(default)]
+    #[validate]
+    pub filters: Vec<EqFilter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+pub struct EqFilter {
+    pub filter_type: EqFilterType,
+    #[validate(range(min = 10.0, max = 24000.0))]
+    pub frequency: f32,
+    /// Ignored by low and high pass filters.
+    #[validate(range(min = -24.0, max = 24.0))]
+    pub gain_db: f32,
+    #[validate(range(min = 0.1, max = 20.0))]
+    pub q: f32,
+    /// Channel the filter is applied to, all channels if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct ConvolutionSettings {
+    pub enabled_for_speakers: bool,
+    pub enabled_for_headphones: bool,
+    /// Paths of the impulse response WAV files by sample rate.
+    pub impulse_files: HashMap<u32, String>,
+}
+
+/// Channel processing applied to the audio of the selected output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Default)]
+pub struct OutputChannelSettings {
+    #[validate]
+    pub speakers: ChannelSettings,
+    #[validate]
+    pub headphones: ChannelSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate, Default)]
+pub struct ChannelSettings {
+    #[serde(default)]
+    #[validate]
+    pub crossfeed: Option<Crossfeed>,
+    /// Attenuation in percent of the right channel when negative, of the left one when positive.
+    #[serde(default)]
+    #[validate(range(min = -100, max = 100))]
+    pub balance: i8,
+    #[serde(default)]
+    pub swap_channels: bool,
+    #[serde(default)]
+    pub invert_polarity: bool,
+    /// Both channels play the mix of the left and the right channel.
+    #[serde(default)]
+    pub mono: bool,
+}
+
+/// Bauer stereophonic-to-binaural crossfeed, the low frequencies of each channel are fed to the
+/// other one, as the ears hear speakers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+pub struct Crossfeed {
+    #[validate(range(min = 300, max = 2000))]
+    pub cutoff_hz: u16,
+    /// Level of the low frequencies fed to the other channel, the higher the stronger the crossfeed.
+    #[validate(range(min = 1.0, max = 15.0))]
+    pub feed_db: f32,
+}
+
+impl Default for Crossfeed {
+    fn default() -> Self {
+        Self {
+            cutoff_hz: 700,
+            feed_db: 4.5,
         }
     }
 }
@@ -77,6 +275,13 @@ pub struct VolumeControlSettings {
     pub alsa_mixer: Option<CardMixer>,
     pub rotary_enabled: bool,
     pub rotary_event_device_path: String,
+    /// Range in dB over which the software volume steps are spread, the lowest step mutes.
+    #[serde(default = "software_volume_range_db_default_value")]
+    pub software_volume_range_db: u8,
+}
+
+const fn software_volume_range_db_default_value() -> u8 {
+    60
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
@@ -96,6 +301,14 @@ pub struct PlaylistSetting {
     pub db_path: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+pub struct PodcastSettings {
+    pub db_path: String,
+    /// Minutes between the refreshes of the podcast feeds.
+    #[validate(range(min = 5, max = 10080))]
+    pub refresh_minutes: u32,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
 pub struct AlsaSettings {
     #[serde(default)]
@@ -163,10 +376,12 @@ impl Default for MetadataStoreSettings {
         Self {
             music_directory: "/music".into(),
             follow_links: true,
-            supported_extensions: vec!["flac", "wav", "mp3", "m4a", "aac", "aiff", "alac", "ogg", "wma", "mp4"]
-                .into_iter()
-                .map(std::borrow::ToOwned::to_owned)
-                .collect(),
+            supported_extensions: vec![
+                "flac", "wav", "mp3", "m4a", "aac", "aiff", "alac", "ogg", "wma", "mp4", "dsf", "dff",
+            ]
+            .into_iter()
+            .map(std::borrow::ToOwned::to_owned)
+            .collect(),
             db_path: "ignored_files.db".to_string(),
         }
     }
@@ -185,6 +400,14 @@ impl Default for PlaylistSetting {
         }
     }
 }
+impl Default for PodcastSettings {
+    fn default() -> Self {
+        Self {
+            db_path: "podcast.db".to_string(),
+            refresh_minutes: 60,
+        }
+    }
+}
 pub const DEFAULT_ALSA_PCM_DEVICE: &str = "hw:0";
 pub const DEFAULT_ALSA_MIXER: &str = "0,Master";
 
@@ -199,6 +422,7 @@ impl Default for Settings {
                 volume_step: 2,
                 ctrl_device: VolumeCrtlType::Alsa,
                 rotary_event_device_path: "/dev/input/by-path/platform-rotary@f-event".to_string(),
+                software_volume_range_db: software_volume_range_db_default_value(),
             },
             dac_settings: DacSettings {
                 enabled: false,
@@ -228,7 +452,11 @@ impl Default for Settings {
                 spi_device_path: "/dev/spidev0.0".to_string(),
             },
             playlist_settings: PlaylistSetting::default(),
+            podcast_settings: PodcastSettings::default(),
             rs_player_settings: RsPlayerSettings::default(),
+            dsp_settings: DspSettings::default(),
+            convolution_settings: ConvolutionSettings::default(),
+            output_channel_settings: OutputChannelSettings::default(),
         }
     }
 }
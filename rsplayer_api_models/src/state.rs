@@ -7,11 +7,14 @@ use num_derive::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumProperty;
 
-use crate::common::MetadataLibraryItem;
+use crate::common::{MetadataLibraryItem, RepeatMode, SleepTimer, StopAfter};
 use crate::{
+    alarm::Alarm,
     common::Volume,
     player::Song,
     playlist::{PlaylistPage, Playlists},
+    podcast::{Episode, Podcast},
+    settings::DspSettings,
 };
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq, Deserialize)]
@@ -22,7 +25,7 @@ pub enum CurrentQueueQuery {
 }
 // end todo
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct PlayerInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub audio_format_rate: Option<u32>,
@@ -35,6 +38,18 @@ pub struct PlayerInfo {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub codec: Option<String>,
+
+    /// Replay gain applied to the song in dB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_gain_db: Option<f32>,
+
+    /// DSD is sent to the DAC as is, without conversion to PCM.
+    #[serde(default)]
+    pub native_dsd: bool,
+
+    /// Samples reach the device unchanged, `None` until the output is playing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_perfect: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -43,7 +58,7 @@ pub struct StreamerState {
     pub volume_state: Volume,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, EnumProperty, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, EnumProperty, Serialize, Deserialize)]
 #[strum(serialize_all = "title_case")]
 #[allow(clippy::large_enum_variant)]
 pub enum StateChangeEvent {
@@ -52,6 +67,8 @@ pub enum StateChangeEvent {
     StreamerStateEvent(StreamerState),
     PlayerInfoEvent(PlayerInfo),
     SongTimeEvent(SongProgress),
+    /// Position the playback continues from after a seek.
+    SeekCompletedEvent(SongProgress),
     ErrorEvent(String),
     PlaylistsEvent(Playlists),
     PlaylistItemsEvent(Vec<Song>, usize),
@@ -64,6 +81,54 @@ pub enum StateChangeEvent {
     FavoriteRadioStations(Vec<String>),
     PlaybackStateEvent(PlayerState),
     RandomToggleEvent(bool),
+    RepeatModeEvent(RepeatMode, StopAfter),
+    StreamConnectionEvent(StreamConnectionState),
+    /// Levels of the played audio, sent only while a client subscribed to them.
+    AudioLevelsEvent(AudioLevels),
+    /// The running sleep timer, sent every second, `None` once it is cancelled or finished.
+    SleepTimerEvent(Option<SleepTimerState>),
+    AlarmsEvent(Vec<Alarm>),
+    /// Id of the ringing alarm, `None` once it is snoozed or dismissed.
+    AlarmRingingEvent(Option<String>),
+    /// Progress of reading the song into memory before it is played.
+    SongLoadingEvent(SongLoadProgress),
+    PodcastsEvent(Vec<Podcast>),
+    /// Episodes of the podcast with the feed url, newest first.
+    PodcastEpisodesEvent(String, Vec<Episode>),
+    DspSettingsEvent(DspSettings),
+    DspPresetsEvent(Vec<String>),
+}
+
+/// Levels in dBFS, per channel for the meters and per frequency band for the spectrum.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct AudioLevels {
+    pub peak_db: Vec<f32>,
+    pub rms_db: Vec<f32>,
+    pub spectrum_db: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SleepTimerState {
+    pub timer: SleepTimer,
+    /// Time left until the playback stops, unknown while the length of the song is not known.
+    pub remaining: Option<Duration>,
+}
+
+/// Connection of the internet radio stream being played.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamConnectionState {
+    /// The connection was lost, with the reconnect attempt and the maximum number of attempts.
+    Reconnecting(u32, u32),
+    Reconnected,
+    /// All reconnect attempts failed.
+    Lost,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongLoadProgress {
+    pub file: String,
+    pub loaded_bytes: u64,
+    pub total_bytes: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
@@ -0,0 +1,342 @@
+//! Alarms that load their source into the queue and start the playback with the volume ramped up.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime};
+use log::info;
+use tokio::select;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::time::{interval, Instant};
+
+use api_models::alarm::{Alarm, AlarmSource};
+use api_models::common::Volume;
+use api_models::state::{PlayerState, StateChangeEvent};
+use rsplayer_config::ArcConfiguration;
+use rsplayer_hardware::audio_device::audio_service::ArcAudioInterfaceSvc;
+use rsplayer_metadata::album_repository::AlbumRepository;
+use rsplayer_metadata::playlist_service::PlaylistService;
+use rsplayer_metadata::queue_service::QueueService;
+use rsplayer_metadata::song_repository::SongRepository;
+use rsplayer_playback::rsp::player_service::PlayerService;
+
+pub struct AlarmClock {
+    player_service: Arc<PlayerService>,
+    queue_service: Arc<QueueService>,
+    playlist_service: Arc<PlaylistService>,
+    album_repository: Arc<AlbumRepository>,
+    song_repository: Arc<SongRepository>,
+    ai_service: ArcAudioInterfaceSvc,
+    config: ArcConfiguration,
+    changes_tx: Sender<StateChangeEvent>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Idle,
+    /// The playback of the alarm is started, the stop of the previous one doesn't end it.
+    Starting {
+        alarm: Alarm,
+    },
+    Ringing {
+        alarm: Alarm,
+        started: Instant,
+        ramped_up: bool,
+    },
+    Snoozed {
+        alarm: Alarm,
+        until: Instant,
+    },
+}
+
+impl AlarmClock {
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(
+        player_service: Arc<PlayerService>,
+        queue_service: Arc<QueueService>,
+        playlist_service: Arc<PlaylistService>,
+        album_repository: Arc<AlbumRepository>,
+        song_repository: Arc<SongRepository>,
+        ai_service: ArcAudioInterfaceSvc,
+        config: ArcConfiguration,
+        changes_tx: Sender<StateChangeEvent>,
+    ) -> Self {
+        Self {
+            player_service,
+            queue_service,
+            playlist_service,
+            album_repository,
+            song_repository,
+            ai_service,
+            config,
+            changes_tx,
+            state: Mutex::new(State::Idle),
+        }
+    }
+
+    pub async fn snooze(&self) {
+        let minutes = self.config.get_settings().rs_player_settings.alarm_snooze_minutes;
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        let alarm = match state {
+            State::Starting { alarm } | State::Ringing { alarm, .. } => alarm,
+            other => {
+                *self.state.lock().unwrap() = other;
+                return;
+            }
+        };
+        info!("Alarm {} snoozed for {minutes} minutes", alarm.id);
+        *self.state.lock().unwrap() = State::Snoozed {
+            alarm,
+            until: Instant::now() + Duration::from_secs(u64::from(minutes) * 60),
+        };
+        self.stop_playback().await;
+        self.changes_tx.send(StateChangeEvent::AlarmRingingEvent(None)).ok();
+        self.changes_tx
+            .send(StateChangeEvent::NotificationSuccess(format!(
+                "Alarm snoozed for {minutes} minutes"
+            )))
+            .ok();
+    }
+
+    pub async fn dismiss(&self) {
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        match state {
+            State::Starting { alarm } | State::Ringing { alarm, .. } => {
+                info!("Alarm {} dismissed", alarm.id);
+                self.stop_playback().await;
+            }
+            State::Snoozed { alarm, .. } => info!("Snoozed alarm {} dismissed", alarm.id),
+            State::Idle => return,
+        }
+        self.changes_tx.send(StateChangeEvent::AlarmRingingEvent(None)).ok();
+    }
+
+    /// Ramps up the volume of the ringing alarm, which plays on until it is snoozed, dismissed or
+    /// stopped. Returns the alarm due since the last check, along with whether its source is
+    /// loaded, which it is not when the alarm plays on after a snooze.
+    fn tick(&self, last_check: NaiveDateTime, now: NaiveDateTime) -> Option<(Alarm, bool)> {
+        if let Some(alarm) = self
+            .config
+            .get_alarms()
+            .into_iter()
+            .find(|alarm| alarm.rings_between(last_check, now))
+        {
+            return Some((alarm, true));
+        }
+        match &mut *self.state.lock().unwrap() {
+            State::Snoozed { alarm, until } if *until <= Instant::now() => Some((alarm.clone(), false)),
+            State::Ringing {
+                alarm,
+                started,
+                ramped_up,
+            } if !*ramped_up => {
+                let (volume, done) = ramp_tick(alarm, started.elapsed(), &self.ai_service.get_volume());
+                self.set_volume(volume);
+                *ramped_up = done;
+                None
+            }
+            State::Starting { .. } | State::Ringing { .. } | State::Snoozed { .. } | State::Idle => None,
+        }
+    }
+
+    /// The alarm rings once its playback started and is done once the user stopped it.
+    fn on_event(&self, event: &StateChangeEvent) {
+        let StateChangeEvent::PlaybackStateEvent(player_state) = event else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        match (std::mem::take(&mut *state), player_state) {
+            (State::Starting { alarm }, PlayerState::PLAYING) => {
+                *state = State::Ringing {
+                    alarm,
+                    started: Instant::now(),
+                    ramped_up: false,
+                };
+            }
+            (State::Ringing { alarm, .. }, PlayerState::STOPPED) => {
+                info!("Alarm {} stopped", alarm.id);
+                self.changes_tx.send(StateChangeEvent::AlarmRingingEvent(None)).ok();
+            }
+            (other, _) => *state = other,
+        }
+    }
+
+    /// Starts the playback of the alarm, its source is loaded into the queue unless it plays on
+    /// after a snooze.
+    fn ring(&self, alarm: Alarm, load_source: bool) {
+        info!("Alarm {} rings", alarm.id);
+        *self.state.lock().unwrap() = State::Starting { alarm: alarm.clone() };
+        self.player_service.stop_current_song();
+        if load_source {
+            self.load_source(&alarm.source);
+        }
+        if self.queue_service.get_current_song().is_none() {
+            self.changes_tx
+                .send(StateChangeEvent::NotificationError(format!(
+                    "Nothing to play for the alarm {}",
+                    alarm.id
+                )))
+                .ok();
+            *self.state.lock().unwrap() = State::Idle;
+            return;
+        }
+        self.set_volume(ramp_volume(&alarm, Duration::ZERO, &self.ai_service.get_volume()));
+        self.player_service.play_from_current_queue_song();
+        self.changes_tx
+            .send(StateChangeEvent::AlarmRingingEvent(Some(alarm.id)))
+            .ok();
+    }
+
+    /// Stopping waits for the playback thread, it must not block the runtime.
+    async fn stop_playback(&self) {
+        let player_service = self.player_service.clone();
+        _ = tokio::task::spawn_blocking(move || player_service.stop_current_song()).await;
+    }
+
+    fn load_source(&self, source: &AlarmSource) {
+        match source {
+            AlarmSource::Playlist(name) => {
+                let songs = self.playlist_service.get_playlist_page_by_name(name, 0, 20000).items;
+                self.queue_service.replace_all(songs.into_iter());
+            }
+            AlarmSource::Album(album_id) => {
+                let songs = self
+                    .album_repository
+                    .find_by_id(album_id)
+                    .map(|album| album.song_keys)
+                    .unwrap_or_default();
+                self.queue_service
+                    .replace_all(songs.iter().filter_map(|key| self.song_repository.find_by_id(key)));
+            }
+            AlarmSource::RadioStation(url) => {
+                self.queue_service.clear();
+                self.queue_service.add_song_by_id(url);
+            }
+            AlarmSource::Directory(dir) => self.queue_service.load_songs_from_dir(dir),
+        }
+    }
+
+    fn set_volume(&self, value: i64) {
+        let volume = self.ai_service.set_volume(value);
+        let state = self.config.save_volume_state(volume);
+        self.changes_tx.send(StateChangeEvent::StreamerStateEvent(state)).ok();
+    }
+}
+
+/// Device volume after the time since the alarm started ringing, raised linearly from the start to
+/// the target volume. Both are in percent of the volume range of the device.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn ramp_volume(alarm: &Alarm, elapsed: Duration, device: &Volume) -> i64 {
+    let (start, target) = (f64::from(alarm.start_volume), f64::from(alarm.target_volume));
+    let progress = if alarm.ramp_up_secs == 0 {
+        1.0
+    } else {
+        (elapsed.as_secs_f64() / f64::from(alarm.ramp_up_secs)).min(1.0)
+    };
+    let percent = (start + (target - start) * progress).min(100.0);
+    device.min + ((device.max - device.min) as f64 * percent / 100.0).round() as i64
+}
+
+/// Device volume set at the tick, and whether the ramp is over. The tick that ends the ramp sets
+/// the target volume.
+fn ramp_tick(alarm: &Alarm, elapsed: Duration, device: &Volume) -> (i64, bool) {
+    (
+        ramp_volume(alarm, elapsed, device),
+        elapsed.as_secs_f64() >= f64::from(alarm.ramp_up_secs),
+    )
+}
+
+/// Checks the alarms every second.
+pub async fn run(alarm_clock: Arc<AlarmClock>, mut state_changes_rx: Receiver<StateChangeEvent>) {
+    let mut ticks = interval(Duration::from_secs(1));
+    let mut last_check = Local::now().naive_local();
+    loop {
+        select! {
+            _ = ticks.tick() => {
+                let now = Local::now().naive_local();
+                // Ringing waits for the playback thread and loads the source, it must not block the runtime.
+                if let Some((alarm, load_source)) = alarm_clock.tick(last_check, now) {
+                    let clock = alarm_clock.clone();
+                    _ = tokio::task::spawn_blocking(move || clock.ring(alarm, load_source)).await;
+                }
+                last_check = now;
+            }
+            event = state_changes_rx.recv() => {
+                if let Ok(event) = event {
+                    alarm_clock.on_event(&event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use api_models::alarm::{Alarm, AlarmSource};
+    use api_models::common::Volume;
+    use chrono::NaiveTime;
+
+    use super::{ramp_tick, ramp_volume};
+
+    fn alarm(start_volume: u8, target_volume: u8, ramp_up_secs: u16) -> Alarm {
+        Alarm {
+            id: "wake up".to_string(),
+            enabled: true,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            weekdays: vec![],
+            source: AlarmSource::Directory("morning".to_string()),
+            start_volume,
+            target_volume,
+            ramp_up_secs,
+        }
+    }
+
+    fn device(min: i64, max: i64) -> Volume {
+        Volume {
+            step: 1,
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    #[test]
+    fn should_ramp_the_volume_up_to_the_target() {
+        let alarm = alarm(10, 50, 60);
+        let device = device(0, 100);
+        assert_eq!(ramp_volume(&alarm, Duration::ZERO, &device), 10);
+        assert_eq!(ramp_volume(&alarm, Duration::from_secs(30), &device), 30);
+        assert_eq!(ramp_volume(&alarm, Duration::from_secs(60), &device), 50);
+        assert_eq!(ramp_volume(&alarm, Duration::from_secs(600), &device), 50);
+    }
+
+    #[test]
+    fn should_set_the_target_volume_without_ramp_up() {
+        assert_eq!(ramp_volume(&alarm(10, 50, 0), Duration::ZERO, &device(0, 100)), 50);
+    }
+
+    #[test]
+    fn should_map_the_volume_to_the_range_of_the_device() {
+        let alarm = alarm(0, 50, 60);
+        let device = device(-127, 0);
+        assert_eq!(ramp_volume(&alarm, Duration::ZERO, &device), -127);
+        assert_eq!(ramp_volume(&alarm, Duration::from_secs(60), &device), -63);
+    }
+
+    #[test]
+    fn should_set_the_target_volume_at_the_tick_ending_the_ramp() {
+        let device = device(0, 1000);
+        let ramped = alarm(10, 50, 60);
+        assert_eq!(ramp_tick(&ramped, Duration::from_millis(59_900), &device), (499, false));
+        assert_eq!(ramp_tick(&ramped, Duration::from_millis(60_900), &device), (500, true));
+        assert_eq!(ramp_tick(&alarm(10, 50, 0), Duration::ZERO, &device), (500, true));
+    }
+
+    #[test]
+    fn should_reach_the_maximum_of_the_device_at_100_percent() {
+        assert_eq!(ramp_volume(&alarm(100, 100, 0), Duration::ZERO, &device(0, 255)), 255);
+    }
+}
@@ -6,7 +6,10 @@ use tokio::sync::broadcast::Sender;
 use tokio::sync::mpsc::Receiver;
 
 use api_models::common::MetadataCommand::{QueryLocalFiles, RescanMetadata};
-use api_models::common::PlayerCommand::{Next, Pause, Play, PlayItem, Prev, QueryCurrentPlayerInfo, RandomToggle, Seek, Stop};
+use api_models::common::PlayerCommand::{
+    CancelSleepTimer, Next, Pause, Play, PlayItem, Prev, QueryCurrentPlayerInfo, QuerySleepTimer, RandomToggle, Seek,
+    SetRepeatMode, SetSleepTimer, Stop, StopAfterCurrentAlbum, StopAfterCurrentSong, SubscribeAudioLevels,
+};
 use api_models::common::PlaylistCommand::{QueryAlbumItems, QueryPlaylist, QueryPlaylistItems, SaveQueueAsPlaylist};
 use api_models::common::QueueCommand::{
     self, AddLocalLibDirectory, AddSongToQueue, ClearQueue, LoadAlbumInQueue, LoadArtistInQueue, LoadPlaylistInQueue,
@@ -15,28 +18,39 @@ use api_models::common::QueueCommand::{
 use api_models::common::SystemCommand::{
     ChangeAudioOutput, PowerOff, QueryCurrentStreamerState, RestartRSPlayer, RestartSystem, SetVol, VolDown, VolUp,
 };
-use api_models::common::UserCommand::{Metadata, Player, Playlist, Queue};
-use api_models::common::{MetadataCommand, MetadataLibraryItem, SystemCommand, UserCommand};
+use api_models::common::UserCommand::{Alarm, Dsp, Metadata, Player, Playlist, Podcast, Queue};
+use api_models::common::{
+    AlarmCommand, DspCommand, MetadataCommand, MetadataLibraryItem, PodcastCommand, StopAfter, SystemCommand,
+    UserCommand,
+};
 use api_models::playlist::PlaylistType;
 use api_models::state::StateChangeEvent;
+use api_models::validator::Validate;
 use rsplayer_config::ArcConfiguration;
 use rsplayer_hardware::audio_device::audio_service::ArcAudioInterfaceSvc;
 use rsplayer_metadata::album_repository::AlbumRepository;
 use rsplayer_metadata::metadata_service::MetadataService;
 use rsplayer_metadata::playlist_service::PlaylistService;
+use rsplayer_metadata::podcast_service::PodcastService;
 use rsplayer_metadata::queue_service::QueueService;
 use rsplayer_metadata::song_repository::SongRepository;
 use rsplayer_playback::rsp::player_service::PlayerService;
 
+use crate::alarm_clock::AlarmClock;
+use crate::sleep_timer::SleepTimerService;
+
 #[allow(clippy::too_many_lines, clippy::too_many_arguments)]
 pub async fn handle_user_commands(
     player_service: Arc<PlayerService>,
     metadata_service: Arc<MetadataService>,
     playlist_service: Arc<PlaylistService>,
+    podcast_service: Arc<PodcastService>,
     queue_service: Arc<QueueService>,
     album_repository: Arc<AlbumRepository>,
     song_repository: Arc<SongRepository>,
-    _config_store: ArcConfiguration,
+    sleep_timer: Arc<SleepTimerService>,
+    alarm_clock: Arc<AlarmClock>,
+    config_store: ArcConfiguration,
     mut input_commands_rx: Receiver<UserCommand>,
     state_changes_sender: Sender<StateChangeEvent>,
 ) {
@@ -52,14 +66,21 @@ pub async fn handle_user_commands(
              * Player commands
              */
             Player(Play) => {
-                player_service.stop_current_song();
-                player_service.play_from_current_queue_song();
-                debug!("Play from current song command processed");
+                if player_service.resume_current_song() {
+                    debug!("Paused song resumed");
+                } else {
+                    player_service.stop_current_song();
+                    player_service.play_from_current_queue_song();
+                    debug!("Play from current song command processed");
+                }
             }
             Player(PlayItem(id)) => {
                 player_service.play_song(&id);
             }
-            Player(Pause | Stop) => {
+            Player(Pause) => {
+                player_service.pause_current_song();
+            }
+            Player(Stop) => {
                 player_service.stop_current_song();
             }
             Player(Next) => {
@@ -68,19 +89,57 @@ pub async fn handle_user_commands(
             Player(Prev) => {
                 player_service.play_prev_song();
             }
-            Player(Seek(sec)) => {
-                player_service.seek_current_song(sec);
+            Player(Seek(target)) => {
+                player_service.seek_current_song(target);
             }
             Player(RandomToggle) => {
                 sender
                     .send(StateChangeEvent::RandomToggleEvent(queue_service.toggle_random_next()))
                     .unwrap();
             }
+            Player(SetRepeatMode(mode)) => {
+                queue_service.set_repeat_mode(mode);
+                sender
+                    .send(StateChangeEvent::RepeatModeEvent(mode, queue_service.get_stop_after()))
+                    .unwrap();
+            }
+            Player(StopAfterCurrentSong) => {
+                let stop_after = queue_service.toggle_stop_after(StopAfter::CurrentSong);
+                sender
+                    .send(StateChangeEvent::RepeatModeEvent(queue_service.get_repeat_mode(), stop_after))
+                    .unwrap();
+            }
+            Player(StopAfterCurrentAlbum) => {
+                let stop_after = queue_service.toggle_stop_after(StopAfter::CurrentAlbum);
+                sender
+                    .send(StateChangeEvent::RepeatModeEvent(queue_service.get_repeat_mode(), stop_after))
+                    .unwrap();
+            }
+            // The server forwards whether any client subscribed to the levels, the OLED always shows them.
+            Player(SubscribeAudioLevels(subscribed)) => {
+                player_service
+                    .set_audio_levels_subscribed(subscribed || config_store.get_settings().oled_settings.enabled);
+            }
+            Player(SetSleepTimer(timer)) => {
+                sleep_timer.set(timer);
+            }
+            Player(CancelSleepTimer) => {
+                sleep_timer.cancel();
+            }
+            Player(QuerySleepTimer) => {
+                sleep_timer.query();
+            }
             Player(QueryCurrentPlayerInfo) => {
                 let is_random = queue_service.get_random_next();
                 state_changes_sender
                     .send(StateChangeEvent::RandomToggleEvent(is_random))
                     .unwrap();
+                state_changes_sender
+                    .send(StateChangeEvent::RepeatModeEvent(
+                        queue_service.get_repeat_mode(),
+                        queue_service.get_stop_after(),
+                    ))
+                    .unwrap();
             }
 
             /*
@@ -370,6 +429,182 @@ pub async fn handle_user_commands(
                     .send(StateChangeEvent::FavoriteRadioStations(favorites))
                     .unwrap();
             }
+
+            /*
+             * DSP commands
+             */
+            Dsp(DspCommand::SetDspSettings(dsp_settings)) => {
+                if dsp_settings.validate().is_ok() {
+                    config_store.save_dsp_settings(&dsp_settings);
+                    player_service.set_dsp_settings(dsp_settings.clone());
+                    state_changes_sender
+                        .send(StateChangeEvent::DspSettingsEvent(dsp_settings))
+                        .unwrap();
+                } else {
+                    state_changes_sender
+                        .send(StateChangeEvent::NotificationError("Invalid DSP settings".to_string()))
+                        .unwrap();
+                }
+            }
+            Dsp(DspCommand::QueryDspSettings) => {
+                state_changes_sender
+                    .send(StateChangeEvent::DspSettingsEvent(player_service.get_dsp_settings()))
+                    .unwrap();
+            }
+            Dsp(DspCommand::SaveDspPreset(name)) => {
+                config_store.save_dsp_preset(&name, &player_service.get_dsp_settings());
+                state_changes_sender
+                    .send(StateChangeEvent::DspPresetsEvent(config_store.get_dsp_preset_names()))
+                    .unwrap();
+                state_changes_sender
+                    .send(StateChangeEvent::NotificationSuccess(format!("DSP preset {name} saved")))
+                    .unwrap();
+            }
+            Dsp(DspCommand::LoadDspPreset(name)) => {
+                if let Some(dsp_settings) = config_store.get_dsp_preset(&name) {
+                    config_store.save_dsp_settings(&dsp_settings);
+                    player_service.set_dsp_settings(dsp_settings.clone());
+                    state_changes_sender
+                        .send(StateChangeEvent::DspSettingsEvent(dsp_settings))
+                        .unwrap();
+                } else {
+                    state_changes_sender
+                        .send(StateChangeEvent::NotificationError(format!("DSP preset {name} not found")))
+                        .unwrap();
+                }
+            }
+            Dsp(DspCommand::DeleteDspPreset(name)) => {
+                config_store.delete_dsp_preset(&name);
+                state_changes_sender
+                    .send(StateChangeEvent::DspPresetsEvent(config_store.get_dsp_preset_names()))
+                    .unwrap();
+            }
+            Dsp(DspCommand::QueryDspPresets) => {
+                state_changes_sender
+                    .send(StateChangeEvent::DspPresetsEvent(config_store.get_dsp_preset_names()))
+                    .unwrap();
+            }
+
+            /*
+             * Alarm commands
+             */
+            Alarm(AlarmCommand::SaveAlarm(alarm)) => {
+                if alarm.validate().is_ok() {
+                    config_store.save_alarm(&alarm);
+                    state_changes_sender
+                        .send(StateChangeEvent::AlarmsEvent(config_store.get_alarms()))
+                        .unwrap();
+                } else {
+                    state_changes_sender
+                        .send(StateChangeEvent::NotificationError("Invalid alarm".to_string()))
+                        .unwrap();
+                }
+            }
+            Alarm(AlarmCommand::DeleteAlarm(id)) => {
+                config_store.delete_alarm(&id);
+                state_changes_sender
+                    .send(StateChangeEvent::AlarmsEvent(config_store.get_alarms()))
+                    .unwrap();
+            }
+            Alarm(AlarmCommand::QueryAlarms) => {
+                state_changes_sender
+                    .send(StateChangeEvent::AlarmsEvent(config_store.get_alarms()))
+                    .unwrap();
+            }
+            Alarm(AlarmCommand::Snooze) => {
+                alarm_clock.snooze().await;
+            }
+            Alarm(AlarmCommand::Dismiss) => {
+                alarm_clock.dismiss().await;
+            }
+
+            /*
+             * Podcast commands
+             */
+            Podcast(PodcastCommand::Subscribe(feed_url)) => {
+                let podcast_service = podcast_service.clone();
+                let state_changes_sender = state_changes_sender.clone();
+                std::thread::Builder::new()
+                    .name("podcast_subscribe".to_string())
+                    .spawn(move || {
+                        match podcast_service.subscribe(&feed_url) {
+                            Ok(podcast) => state_changes_sender
+                                .send(StateChangeEvent::NotificationSuccess(format!(
+                                    "Subscribed to {}",
+                                    podcast.title
+                                )))
+                                .ok(),
+                            Err(err) => state_changes_sender
+                                .send(StateChangeEvent::NotificationError(err.to_string()))
+                                .ok(),
+                        };
+                        state_changes_sender
+                            .send(StateChangeEvent::PodcastsEvent(podcast_service.get_podcasts()))
+                            .ok();
+                    })
+                    .expect("Failed to start podcast subscribe thread");
+            }
+            Podcast(PodcastCommand::Unsubscribe(feed_url)) => {
+                podcast_service.unsubscribe(&feed_url);
+                state_changes_sender
+                    .send(StateChangeEvent::PodcastsEvent(podcast_service.get_podcasts()))
+                    .unwrap();
+            }
+            Podcast(PodcastCommand::RefreshPodcasts) => {
+                let podcast_service = podcast_service.clone();
+                let state_changes_sender = state_changes_sender.clone();
+                std::thread::Builder::new()
+                    .name("podcast_refresh".to_string())
+                    .spawn(move || {
+                        let new_episodes = podcast_service.refresh_all();
+                        state_changes_sender
+                            .send(StateChangeEvent::NotificationSuccess(format!(
+                                "Podcasts refreshed, {new_episodes} new episodes"
+                            )))
+                            .ok();
+                        state_changes_sender
+                            .send(StateChangeEvent::PodcastsEvent(podcast_service.get_podcasts()))
+                            .ok();
+                    })
+                    .expect("Failed to start podcast refresh thread");
+            }
+            Podcast(PodcastCommand::QueryPodcasts) => {
+                state_changes_sender
+                    .send(StateChangeEvent::PodcastsEvent(podcast_service.get_podcasts()))
+                    .unwrap();
+            }
+            Podcast(PodcastCommand::QueryEpisodes(feed_url)) => {
+                let episodes = podcast_service.get_episodes(&feed_url);
+                state_changes_sender
+                    .send(StateChangeEvent::PodcastEpisodesEvent(feed_url, episodes))
+                    .unwrap();
+            }
+            Podcast(PodcastCommand::AddEpisodeToQueue(feed_url, audio_url)) => {
+                if let Some(song) = podcast_service.episode_song(&feed_url, &audio_url) {
+                    queue_service.add_song(&song);
+                    state_changes_sender
+                        .send(StateChangeEvent::NotificationSuccess(format!(
+                            "Episode {} added to queue",
+                            song.get_title()
+                        )))
+                        .unwrap();
+                }
+            }
+            Podcast(PodcastCommand::PlayEpisode(feed_url, audio_url)) => {
+                if let Some(song) = podcast_service.episode_song(&feed_url, &audio_url) {
+                    if !queue_service.get_all_songs().iter().any(|queued| queued.file == song.file) {
+                        queue_service.add_song(&song);
+                    }
+                    player_service.play_song(&song.file);
+                }
+            }
+            Podcast(PodcastCommand::SetEpisodePlayed(feed_url, audio_url, played)) => {
+                podcast_service.set_played(&feed_url, &audio_url, played);
+                let episodes = podcast_service.get_episodes(&feed_url);
+                state_changes_sender
+                    .send(StateChangeEvent::PodcastEpisodesEvent(feed_url, episodes))
+                    .unwrap();
+            }
         }
     }
 }
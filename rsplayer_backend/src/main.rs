@@ -15,7 +15,7 @@ use tokio::{select, spawn};
 
 use album_repository::AlbumRepository;
 use rsplayer_config::Configuration;
-use rsplayer_hardware::audio_device::audio_service::AudioInterfaceService;
+use rsplayer_hardware::audio_device::audio_service::{self, AudioInterfaceService};
 use rsplayer_hardware::input::ir_lirc;
 use rsplayer_hardware::input::volume_rotary;
 use rsplayer_hardware::oled::st7920;
@@ -23,12 +23,17 @@ use rsplayer_metadata::album_repository;
 use rsplayer_metadata::metadata_service::MetadataService;
 use rsplayer_metadata::play_statistic_repository::PlayStatisticsRepository;
 use rsplayer_metadata::playlist_service::PlaylistService;
+use rsplayer_metadata::podcast_service::{self, PodcastService};
 use rsplayer_metadata::queue_service::QueueService;
 use rsplayer_metadata::song_repository::SongRepository;
 use rsplayer_playback::rsp::player_service::PlayerService;
+use alarm_clock::AlarmClock;
+use sleep_timer::SleepTimerService;
 
+mod alarm_clock;
 mod command_handler;
 mod server_warp;
+mod sleep_timer;
 
 #[allow(clippy::redundant_pub_crate, clippy::too_many_lines)]
 #[tokio::main(flavor = "current_thread")]
@@ -79,6 +84,8 @@ async fn main() {
 
     let playlist_service = Arc::new(PlaylistService::new(&config.get_settings().playlist_settings));
     info!("Playlist service successfully created.");
+    let podcast_service = Arc::new(PodcastService::new(&config.get_settings().podcast_settings));
+    info!("Podcast service successfully created.");
     let queue_service = Arc::new(QueueService::new(
         &config.get_settings().playback_queue_settings,
         song_repository.clone(),
@@ -104,15 +111,40 @@ async fn main() {
         &config.get_settings(),
         metadata_service.clone(),
         queue_service.clone(),
+        podcast_service.clone(),
         state_changes_tx.clone()
     ));
+    player_service.set_audio_output(config.get_streamer_state().selected_audio_output);
+    player_service.set_volume(&ai_service.get_volume());
+    // The OLED shows the meters, so the levels are measured even without web clients.
+    player_service.set_audio_levels_subscribed(config.get_settings().oled_settings.enabled);
     info!("Player service successfully created.");
 
+    let sleep_timer = Arc::new(SleepTimerService::new(
+        player_service.clone(),
+        queue_service.clone(),
+        ai_service.clone(),
+        config.clone(),
+        state_changes_tx.clone(),
+    ));
+
+    let alarm_clock = Arc::new(AlarmClock::new(
+        player_service.clone(),
+        queue_service.clone(),
+        playlist_service.clone(),
+        album_repository.clone(),
+        song_repository.clone(),
+        ai_service.clone(),
+        config.clone(),
+        state_changes_tx.clone(),
+    ));
+
     let (http_server_future, https_server_future, websocket_future) = server_warp::start(
         state_changes_tx.subscribe(),
         player_commands_tx.clone(),
         system_commands_tx.clone(),
         &config,
+        player_service.get_http_stream(),
     );
 
     if config.get_settings().auto_resume_playback {
@@ -132,13 +164,32 @@ async fn main() {
             error!("Exit from OLED writer thread.");
         }
 
+        _ = spawn(audio_service::follow_dsd_playback(ai_service.clone(), state_changes_tx.subscribe())) => {
+            error!("Exit from DAC DSD mode thread.");
+        }
+
+        _ = spawn(sleep_timer::run(sleep_timer.clone(), state_changes_tx.subscribe())) => {
+            error!("Exit from sleep timer thread.");
+        }
+
+        _ = spawn(alarm_clock::run(alarm_clock.clone(), state_changes_tx.subscribe())) => {
+            error!("Exit from alarm clock thread.");
+        }
+
+        _ = spawn(podcast_service::run(podcast_service.clone(), state_changes_tx.clone())) => {
+            error!("Exit from podcast refresh thread.");
+        }
+
         _ = spawn(command_handler::handle_user_commands(
                 player_service.clone(),
                 metadata_service.clone(),
                 playlist_service.clone(),
+                podcast_service.clone(),
                 queue_service.clone(),
                 album_repository.clone(),
                 song_repository.clone(),
+                sleep_timer.clone(),
+                alarm_clock.clone(),
                 config.clone(),
                 player_commands_rx,
                 state_changes_tx.clone())) => {
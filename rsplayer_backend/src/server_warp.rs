@@ -1,6 +1,6 @@
 use std::env;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::atomic::{AtomicUsize, Ordering},
 };
 use std::{sync::Arc, time::Duration};
@@ -24,11 +24,13 @@ use warp::{
     Filter,
 };
 
+use api_models::common::PlayerCommand;
 use api_models::common::SystemCommand;
 use api_models::common::UserCommand;
 use api_models::serde_json;
 use api_models::state::StateChangeEvent;
 use rsplayer_config::Configuration;
+use rsplayer_playback::rsp::http_stream::HttpStream;
 
 /// Our global unique user id counter.
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
@@ -39,6 +41,8 @@ static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 /// - Value is a sender of `warp::ws::Message`
 type Users = Arc<RwLock<HashMap<usize, mpsc::UnboundedSender<Result<Message, warp::Error>>>>>;
 type Config = Arc<Configuration>;
+/// Ids of the users that subscribed to the audio levels.
+type AudioLevelsSubscribers = Arc<RwLock<HashSet<usize>>>;
 
 type UserCommandSender = mpsc::Sender<UserCommand>;
 type SystemCommandSender = mpsc::Sender<SystemCommand>;
@@ -69,6 +73,7 @@ pub fn start(
     player_commands_tx: UserCommandSender,
     system_commands_tx: SystemCommandSender,
     config: &Config,
+    http_stream: HttpStream,
 ) -> (
     impl Future<Output = ()>,
     impl Future<Output = ()>,
@@ -77,9 +82,12 @@ pub fn start(
     // Keep track of all connected users, key is usize, value
     // is a websocket sender.
     let users = Users::default();
+    let subscribers = AudioLevelsSubscribers::default();
     // Turn our "state" into a new Filter...
     let users_notify = users.clone();
+    let subscribers_notify = subscribers.clone();
     let users_f = warp::any().map(move || users.clone());
+    let subscribers_f = warp::any().map(move || subscribers.clone());
     let player_commands_tx = warp::any().map(move || player_commands_tx.clone());
     let system_commands_tx = warp::any().map(move || system_commands_tx.clone());
     let cors = warp::cors()
@@ -89,11 +97,12 @@ pub fn start(
     let player_ws_path = warp::path!("api" / "ws")
         .and(warp::ws())
         .and(users_f)
+        .and(subscribers_f)
         .and(player_commands_tx)
         .and(system_commands_tx)
-        .map(|ws: warp::ws::Ws, users, player_commands, system_commands| {
+        .map(|ws: warp::ws::Ws, users, subscribers, player_commands, system_commands| {
             // And then our closure will be called when it completes...
-            ws.on_upgrade(|websocket| user_connected(websocket, users, player_commands, system_commands))
+            ws.on_upgrade(|websocket| user_connected(websocket, users, subscribers, player_commands, system_commands))
         });
 
     let mut cache_headers = HeaderMap::new();
@@ -116,6 +125,7 @@ pub fn start(
         .or(filters::get_settings(config.clone()))
         .or(ui_static_content)
         .or(artwork_static_content)
+        .or(filters::http_stream(http_stream))
         .with(cors);
 
     let ws_handle = async move {
@@ -127,7 +137,7 @@ pub fn start(
                 }
                 Ok(ev) => {
                     debug!("Received state changed event {:?}", ev);
-                    notify_users(&users_notify, ev).await;
+                    notify_users(&users_notify, &subscribers_notify, ev).await;
                 }
             }
         }
@@ -149,6 +159,7 @@ mod filters {
     use warp::Filter;
 
     use api_models::settings::Settings;
+    use rsplayer_playback::rsp::http_stream::HttpStream;
 
     use super::{handlers, Config};
 
@@ -177,6 +188,14 @@ mod filters {
             .map(move || error_msg.to_string())
     }
 
+    pub fn http_stream(stream: HttpStream) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::get()
+            .and(warp::path!("api" / "stream"))
+            .and(warp::header::optional::<String>("icy-metadata"))
+            .and(warp::any().map(move || stream.clone()))
+            .and_then(handlers::http_stream)
+    }
+
     fn with_config(config: Config) -> impl Filter<Extract = (Config,), Error = std::convert::Infallible> + Clone {
         warp::any().map(move || config.clone())
     }
@@ -192,11 +211,15 @@ mod filters {
 mod handlers {
     use std::{collections::HashMap, convert::Infallible, process::exit};
 
+    use futures::StreamExt;
     use log::{debug, error};
-    use warp::hyper::StatusCode;
+    use tokio_stream::wrappers::ReceiverStream;
+    use warp::http::Response;
+    use warp::hyper::{Body, StatusCode};
 
     use api_models::settings::Settings;
     use rsplayer_hardware::audio_device::alsa::{self};
+    use rsplayer_playback::rsp::http_stream::HttpStream;
 
     use super::Config;
 
@@ -225,17 +248,54 @@ mod handlers {
 
         Ok(warp::reply::json(settings))
     }
+
+    /// Audio bytes between two ICY metadata blocks, for clients that send `Icy-MetaData: 1`.
+    const ICY_METAINT: usize = 16000;
+
+    pub async fn http_stream(icy_metadata: Option<String>, stream: HttpStream) -> Result<impl warp::Reply, Infallible> {
+        let mut response = Response::builder();
+        if !stream.is_enabled() {
+            return Ok(response.status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap());
+        }
+        let icy_metaint = icy_metadata.filter(|value| value.trim() == "1").map(|_| ICY_METAINT);
+        if let Some(metaint) = icy_metaint {
+            response = response.header("icy-metaint", metaint);
+        }
+        let chunks = ReceiverStream::new(stream.listen(icy_metaint)).map(Ok::<_, Infallible>);
+        Ok(response
+            .header("content-type", "audio/flac")
+            .header("cache-control", "no-cache")
+            .header("icy-name", "rsplayer")
+            .body(Body::wrap_stream(chunks))
+            .unwrap())
+    }
 }
 
-async fn notify_users(users_to_notify: &Users, status_change_event: StateChangeEvent) {
+async fn notify_users(
+    users_to_notify: &Users,
+    subscribers: &AudioLevelsSubscribers,
+    status_change_event: StateChangeEvent,
+) {
     if !users_to_notify.read().await.is_empty() {
+        // Audio levels are sent many times per second, only to the users that asked for them.
+        let subscribers = if matches!(status_change_event, StateChangeEvent::AudioLevelsEvent(_)) {
+            Some(subscribers.read().await.clone())
+        } else {
+            None
+        };
         let json_msg = serde_json::to_string(&status_change_event).unwrap();
         if !json_msg.is_empty() {
             let users = users_to_notify.read().await;
-            users.iter().for_each(|tx| {
-                let send_result = tx.1.send(Ok(Message::text(json_msg.clone())));
-                debug!("Sent message to user: {:?} with result: {:?}", tx.0, send_result);
-            });
+            users
+                .iter()
+                .filter(|tx| match &subscribers {
+                    Some(ids) => ids.contains(tx.0),
+                    None => true,
+                })
+                .for_each(|tx| {
+                    let send_result = tx.1.send(Ok(Message::text(json_msg.clone())));
+                    debug!("Sent message to user: {:?} with result: {:?}", tx.0, send_result);
+                });
         }
     }
 }
@@ -243,6 +303,7 @@ async fn notify_users(users_to_notify: &Users, status_change_event: StateChangeE
 async fn user_connected(
     ws: WebSocket,
     users: Users,
+    subscribers: AudioLevelsSubscribers,
     user_commands_tx: UserCommandSender,
     system_commands_tx: SystemCommandSender,
 ) {
@@ -281,7 +342,22 @@ async fn user_connected(
         info!("Got command from user {:?}", msg);
         if let Ok(cmd) = msg.to_str() {
             let user_command: Option<UserCommand> = serde_json::from_str(cmd).ok();
-            if let Some(pc) = user_command {
+            if let Some(UserCommand::Player(PlayerCommand::SubscribeAudioLevels(subscribed))) = user_command {
+                let any_subscribed = {
+                    let mut subscribers = subscribers.write().await;
+                    if subscribed {
+                        subscribers.insert(user_id);
+                    } else {
+                        subscribers.remove(&user_id);
+                    }
+                    !subscribers.is_empty()
+                };
+                // The player measures the levels while any user subscribed to them.
+                user_commands_tx
+                    .send(UserCommand::Player(PlayerCommand::SubscribeAudioLevels(any_subscribed)))
+                    .await
+                    .expect("failed to send user message");
+            } else if let Some(pc) = user_command {
                 user_commands_tx.send(pc).await.expect("failed to send user message");
             } else {
                 let system_command: Option<SystemCommand> = serde_json::from_str(cmd).ok();
@@ -300,13 +376,27 @@ async fn user_connected(
     // Make an extra clone to give to our disconnection handler...
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
-    user_disconnected(user_id, &users.clone()).await;
+    user_disconnected(user_id, &users.clone(), &subscribers, &user_commands_tx).await;
 }
 
-async fn user_disconnected(my_id: usize, users: &Users) {
+async fn user_disconnected(
+    my_id: usize,
+    users: &Users,
+    subscribers: &AudioLevelsSubscribers,
+    user_commands_tx: &UserCommandSender,
+) {
     info!("good bye user: {}", my_id);
     // Stream closed up, so remove from the user list
     users.write().await.remove(&my_id);
+    let last_subscriber_left = {
+        let mut subscribers = subscribers.write().await;
+        subscribers.remove(&my_id) && subscribers.is_empty()
+    };
+    if last_subscriber_left {
+        _ = user_commands_tx
+            .send(UserCommand::Player(PlayerCommand::SubscribeAudioLevels(false)))
+            .await;
+    }
     info!("Number of active websockets is: {}", users.read().await.len());
 }
 
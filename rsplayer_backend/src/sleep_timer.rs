@@ -0,0 +1,357 @@
+//! Sleep timer that fades the volume out before it stops the playback, the volume is restored afterwards.
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::info;
+use tokio::select;
+use tokio::sync::broadcast::{Receiver, Sender};
+use tokio::time::{interval, Instant};
+
+use api_models::common::{SleepTimer, StopAfter};
+use api_models::player::Song;
+use api_models::state::{PlayerState, SleepTimerState, SongProgress, StateChangeEvent};
+use rsplayer_config::ArcConfiguration;
+use rsplayer_hardware::audio_device::audio_service::ArcAudioInterfaceSvc;
+use rsplayer_metadata::queue_service::QueueService;
+use rsplayer_playback::rsp::player_service::PlayerService;
+
+/// Songs of the queue looked at for the end of the current album.
+const MAX_ALBUM_SONGS: usize = 100;
+
+pub struct SleepTimerService {
+    player_service: Arc<PlayerService>,
+    queue_service: Arc<QueueService>,
+    ai_service: ArcAudioInterfaceSvc,
+    config: ArcConfiguration,
+    changes_tx: Sender<StateChangeEvent>,
+    fade: Duration,
+    running: Mutex<Option<Running>>,
+    /// Progress of the current song, for the timers that stop at its end.
+    progress: Mutex<SongProgress>,
+}
+
+struct Running {
+    timer: SleepTimer,
+    /// Stop time of the `Minutes` timer.
+    deadline: Option<Instant>,
+    /// Volume before the fade started.
+    faded_from: Option<i64>,
+}
+
+impl SleepTimerService {
+    pub fn new(
+        player_service: Arc<PlayerService>,
+        queue_service: Arc<QueueService>,
+        ai_service: ArcAudioInterfaceSvc,
+        config: ArcConfiguration,
+        changes_tx: Sender<StateChangeEvent>,
+    ) -> Self {
+        let fade = Duration::from_secs(config.get_settings().rs_player_settings.sleep_timer_fade_secs.into());
+        Self {
+            player_service,
+            queue_service,
+            ai_service,
+            config,
+            changes_tx,
+            fade,
+            running: Mutex::new(None),
+            progress: Mutex::default(),
+        }
+    }
+
+    /// Starts the timer, a running one is cancelled first. The timers stopping at the end of the
+    /// song or album let the player stop there, as requested with `StopAfter`.
+    pub fn set(&self, timer: SleepTimer) {
+        self.cancel();
+        let deadline = match timer {
+            SleepTimer::Minutes(minutes) => Some(Instant::now() + Duration::from_secs(u64::from(minutes) * 60)),
+            SleepTimer::EndOfSong => {
+                self.set_stop_after(StopAfter::CurrentSong);
+                None
+            }
+            SleepTimer::EndOfAlbum => {
+                self.set_stop_after(StopAfter::CurrentAlbum);
+                None
+            }
+        };
+        info!("Sleep timer set to {timer:?}");
+        let running = Running {
+            timer,
+            deadline,
+            faded_from: None,
+        };
+        self.send_state(Some(&running));
+        *self.running.lock().unwrap() = Some(running);
+    }
+
+    pub fn cancel(&self) {
+        let Some(running) = self.running.lock().unwrap().take() else {
+            return;
+        };
+        let stop = stop_after(running.timer);
+        if stop != StopAfter::Off && self.queue_service.get_stop_after() == stop {
+            self.set_stop_after(StopAfter::Off);
+        }
+        self.finish(&running);
+    }
+
+    pub fn query(&self) {
+        self.send_state(self.running.lock().unwrap().as_ref());
+    }
+
+    /// Sends the countdown and fades the volume, called every second. Returns the timer that has
+    /// to stop the playback now.
+    fn tick(&self) -> Option<Running> {
+        let mut guard = self.running.lock().unwrap();
+        let running = guard.as_mut()?;
+        // The stop request of the timer was fulfilled or cancelled by the user.
+        let stop = stop_after(running.timer);
+        if stop != StopAfter::Off && self.queue_service.get_stop_after() != stop {
+            if let Some(running) = guard.take() {
+                self.finish(&running);
+            }
+            return None;
+        }
+        let remaining = self.remaining(running);
+        self.send_state(Some(running));
+        let remaining = remaining?;
+        if remaining <= self.fade && !self.fade.is_zero() {
+            self.fade_volume(running, remaining);
+        }
+        if remaining.is_zero() && running.deadline.is_some() {
+            return guard.take();
+        }
+        None
+    }
+
+    /// Stops the playback, the volume faded out by the timer is restored afterwards.
+    fn stop_playback(&self, running: &Running) {
+        info!("Sleep timer stops the playback");
+        self.player_service.stop_current_song();
+        self.finish(running);
+    }
+
+    fn on_event(&self, event: &StateChangeEvent) {
+        match event {
+            StateChangeEvent::SongTimeEvent(progress) => *self.progress.lock().unwrap() = progress.clone(),
+            StateChangeEvent::CurrentSongEvent(_) => *self.progress.lock().unwrap() = SongProgress::default(),
+            // The timers stopping at the end of the song are done once the player stopped, the
+            // others when the playback was stopped while the volume was faded out.
+            StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED) => {
+                let mut guard = self.running.lock().unwrap();
+                let done = guard.as_ref().is_some_and(|running| {
+                    running.faded_from.is_some()
+                        || (running.deadline.is_none() && self.queue_service.get_stop_after() == StopAfter::Off)
+                });
+                if done {
+                    if let Some(running) = guard.take() {
+                        self.finish(&running);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn remaining(&self, running: &Running) -> Option<Duration> {
+        let songs = if running.timer == SleepTimer::EndOfAlbum {
+            self.queue_service
+                .get_queue_page_starting_from_current_song(MAX_ALBUM_SONGS)
+        } else {
+            Vec::new()
+        };
+        remaining_time(running, Instant::now(), &self.progress.lock().unwrap(), &songs)
+    }
+
+    /// Lowers the volume in proportion to the remaining time of the fade.
+    fn fade_volume(&self, running: &mut Running, remaining: Duration) {
+        let current = self.ai_service.get_volume();
+        let original = *running.faded_from.get_or_insert(current.current);
+        let faded = faded_volume(current.min, original, remaining, self.fade);
+        if faded != current.current {
+            let volume = self.ai_service.set_volume(faded);
+            let mut state = self.config.get_streamer_state();
+            state.volume_state = volume;
+            self.changes_tx.send(StateChangeEvent::StreamerStateEvent(state)).ok();
+        }
+    }
+
+    /// Restores the volume of the faded out timer and reports that no timer is running.
+    fn finish(&self, running: &Running) {
+        if let Some(original) = running.faded_from {
+            let volume = self.ai_service.set_volume(original);
+            let state = self.config.save_volume_state(volume);
+            self.changes_tx.send(StateChangeEvent::StreamerStateEvent(state)).ok();
+        }
+        info!("Sleep timer finished");
+        self.send_state(None);
+    }
+
+    fn set_stop_after(&self, stop: StopAfter) {
+        if self.queue_service.get_stop_after() != stop {
+            if stop == StopAfter::Off {
+                self.queue_service.clear_stop_after();
+            } else {
+                self.queue_service.toggle_stop_after(stop);
+            }
+        }
+        self.changes_tx
+            .send(StateChangeEvent::RepeatModeEvent(
+                self.queue_service.get_repeat_mode(),
+                self.queue_service.get_stop_after(),
+            ))
+            .ok();
+    }
+
+    fn send_state(&self, running: Option<&Running>) {
+        let state = running.map(|running| SleepTimerState {
+            timer: running.timer,
+            remaining: self.remaining(running),
+        });
+        self.changes_tx.send(StateChangeEvent::SleepTimerEvent(state)).ok();
+    }
+}
+
+/// Stop request of the player that belongs to the timer.
+const fn stop_after(timer: SleepTimer) -> StopAfter {
+    match timer {
+        SleepTimer::Minutes(_) => StopAfter::Off,
+        SleepTimer::EndOfSong => StopAfter::CurrentSong,
+        SleepTimer::EndOfAlbum => StopAfter::CurrentAlbum,
+    }
+}
+
+/// Time left until the playback stops, `None` when the length of a song is unknown. The songs
+/// following the current one count while they are from its album.
+fn remaining_time(running: &Running, now: Instant, progress: &SongProgress, songs: &[Song]) -> Option<Duration> {
+    if let Some(deadline) = running.deadline {
+        return Some(deadline.saturating_duration_since(now));
+    }
+    if progress.total_time.is_zero() {
+        return None;
+    }
+    let mut remaining = progress.total_time.saturating_sub(progress.current_time);
+    for (current, next) in songs.iter().zip(songs.iter().skip(1)) {
+        if !current.is_same_album(next) {
+            break;
+        }
+        remaining += next.time?;
+    }
+    Some(remaining)
+}
+
+/// Volume at the remaining time of the fade, from the original volume down to the minimum.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn faded_volume(min: i64, original: i64, remaining: Duration, fade: Duration) -> i64 {
+    min + ((original - min) as f64 * remaining.as_secs_f64() / fade.as_secs_f64()) as i64
+}
+
+/// Counts the running timer down every second.
+pub async fn run(sleep_timer: Arc<SleepTimerService>, mut state_changes_rx: Receiver<StateChangeEvent>) {
+    let mut ticks = interval(Duration::from_secs(1));
+    loop {
+        select! {
+            _ = ticks.tick() => {
+                // Stopping waits for the playback thread, it must not block the runtime.
+                if let Some(running) = sleep_timer.tick() {
+                    let service = sleep_timer.clone();
+                    _ = tokio::task::spawn_blocking(move || service.stop_playback(&running)).await;
+                }
+            }
+            event = state_changes_rx.recv() => {
+                if let Ok(event) = event {
+                    sleep_timer.on_event(&event);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use api_models::common::{SleepTimer, StopAfter};
+    use api_models::player::Song;
+    use api_models::state::SongProgress;
+    use tokio::time::Instant;
+
+    use super::{faded_volume, remaining_time, stop_after, Running};
+
+    fn running(timer: SleepTimer, deadline: Option<Instant>) -> Running {
+        Running {
+            timer,
+            deadline,
+            faded_from: None,
+        }
+    }
+
+    fn progress(total_secs: u64, current_secs: u64) -> SongProgress {
+        SongProgress {
+            total_time: Duration::from_secs(total_secs),
+            current_time: Duration::from_secs(current_secs),
+        }
+    }
+
+    fn song(album: &str, secs: Option<u64>) -> Song {
+        Song {
+            album: Some(album.to_string()),
+            time: secs.map(Duration::from_secs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_stop_after_song_or_album_only_for_those_timers() {
+        assert_eq!(stop_after(SleepTimer::Minutes(15)), StopAfter::Off);
+        assert_eq!(stop_after(SleepTimer::EndOfSong), StopAfter::CurrentSong);
+        assert_eq!(stop_after(SleepTimer::EndOfAlbum), StopAfter::CurrentAlbum);
+    }
+
+    #[test]
+    fn should_count_down_to_the_deadline() {
+        let now = Instant::now();
+        let timer = running(SleepTimer::Minutes(2), Some(now + Duration::from_secs(90)));
+        assert_eq!(
+            remaining_time(&timer, now, &progress(300, 10), &[]),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            remaining_time(&timer, now + Duration::from_secs(100), &progress(300, 10), &[]),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn should_count_down_to_the_end_of_the_song() {
+        let timer = running(SleepTimer::EndOfSong, None);
+        let now = Instant::now();
+        assert_eq!(
+            remaining_time(&timer, now, &progress(200, 50), &[]),
+            Some(Duration::from_secs(150))
+        );
+        assert_eq!(remaining_time(&timer, now, &progress(0, 0), &[]), None);
+    }
+
+    #[test]
+    fn should_count_down_to_the_end_of_the_album() {
+        let timer = running(SleepTimer::EndOfAlbum, None);
+        let now = Instant::now();
+        let songs = [song("a", Some(200)), song("a", Some(100)), song("b", Some(50))];
+        assert_eq!(
+            remaining_time(&timer, now, &progress(200, 50), &songs),
+            Some(Duration::from_secs(250))
+        );
+        let songs = [song("a", Some(200)), song("a", None)];
+        assert_eq!(remaining_time(&timer, now, &progress(200, 50), &songs), None);
+    }
+
+    #[test]
+    fn should_fade_the_volume_down_to_the_minimum() {
+        let fade = Duration::from_secs(10);
+        assert_eq!(faded_volume(0, 80, fade, fade), 80);
+        assert_eq!(faded_volume(0, 80, Duration::from_secs(5), fade), 40);
+        assert_eq!(faded_volume(-20, 80, Duration::from_secs(5), fade), 30);
+        assert_eq!(faded_volume(-20, 80, Duration::ZERO, fade), -20);
+    }
+}
@@ -1,13 +1,19 @@
 use std::sync::Arc;
 
+use api_models::alarm::Alarm;
 use api_models::common::Volume;
 
-use api_models::settings::Settings;
+use api_models::settings::{DspSettings, Settings};
 use api_models::state::{AudioOut, StreamerState};
 use sled::{Db, IVec};
 
 const SETTINGS_KEY: &str = "settings";
 const STATE_KEY: &str = "state";
+const DSP_PRESETS_TREE: &str = "dsp_presets";
+const ALARMS_TREE: &str = "alarms";
+const SETTINGS_VERSION_KEY: &str = "settings_version";
+/// Version of the saved settings, raised with every migration in `migrate_settings`.
+const SETTINGS_VERSION: u32 = 1;
 
 pub type ArcConfiguration = Arc<Configuration>;
 
@@ -29,7 +35,37 @@ impl Configuration {
             None as Option<IVec>,
             Some(IVec::from(serde_json::to_vec(&StreamerState::default()).unwrap())),
         );
-        Self { db }
+        let configuration = Self { db };
+        configuration.migrate_settings();
+        configuration
+    }
+
+    /// Updates the settings saved by older versions, each migration runs once.
+    fn migrate_settings(&self) {
+        let version = self
+            .db
+            .get(SETTINGS_VERSION_KEY)
+            .ok()
+            .flatten()
+            .and_then(|value| serde_json::from_slice::<u32>(&value).ok())
+            .unwrap_or_default();
+        if version < 1 {
+            // DSD files are scanned only if their extensions are supported.
+            let mut settings = self.get_settings();
+            let extensions = &mut settings.metadata_settings.supported_extensions;
+            for extension in ["dsf", "dff"] {
+                if !extensions.iter().any(|ext| ext == extension) {
+                    extensions.push(extension.to_string());
+                }
+            }
+            self.save_settings(&settings);
+        }
+        if version < SETTINGS_VERSION {
+            _ = self
+                .db
+                .insert(SETTINGS_VERSION_KEY, serde_json::to_vec(&SETTINGS_VERSION).unwrap());
+            _ = self.db.flush();
+        }
     }
 
     pub fn get_settings(&self) -> Settings {
@@ -73,6 +109,81 @@ impl Configuration {
         self.save_streamer_state(&ss);
         ss
     }
+
+    pub fn save_dsp_settings(&self, dsp_settings: &DspSettings) {
+        let mut settings = self.get_settings();
+        settings.dsp_settings = dsp_settings.clone();
+        self.save_settings(&settings);
+    }
+
+    pub fn save_dsp_preset(&self, name: &str, dsp_settings: &DspSettings) {
+        let Ok(presets) = self.db.open_tree(DSP_PRESETS_TREE) else {
+            return;
+        };
+        _ = presets.insert(name, serde_json::to_vec(dsp_settings).unwrap());
+        _ = presets.flush();
+    }
+
+    pub fn get_dsp_preset(&self, name: &str) -> Option<DspSettings> {
+        let presets = self.db.open_tree(DSP_PRESETS_TREE).ok()?;
+        let preset = presets.get(name).ok()??;
+        serde_json::from_slice(&preset).ok()
+    }
+
+    pub fn delete_dsp_preset(&self, name: &str) {
+        if let Ok(presets) = self.db.open_tree(DSP_PRESETS_TREE) {
+            _ = presets.remove(name);
+            _ = presets.flush();
+        }
+    }
+
+    pub fn get_dsp_preset_names(&self) -> Vec<String> {
+        self.db.open_tree(DSP_PRESETS_TREE).map_or_else(
+            |_| vec![],
+            |presets| {
+                presets
+                    .iter()
+                    .keys()
+                    .filter_map(Result::ok)
+                    .map(|key| String::from_utf8_lossy(&key).to_string())
+                    .collect()
+            },
+        )
+    }
+
+    /// Saves the alarm under its id, a new id is assigned when it has none.
+    pub fn save_alarm(&self, alarm: &Alarm) -> Alarm {
+        let mut alarm = alarm.clone();
+        if alarm.id.is_empty() {
+            alarm.id = self.db.generate_id().unwrap_or_default().to_string();
+        }
+        if let Ok(alarms) = self.db.open_tree(ALARMS_TREE) {
+            _ = alarms.insert(&alarm.id, serde_json::to_vec(&alarm).unwrap());
+            _ = alarms.flush();
+        }
+        alarm
+    }
+
+    pub fn delete_alarm(&self, id: &str) {
+        if let Ok(alarms) = self.db.open_tree(ALARMS_TREE) {
+            _ = alarms.remove(id);
+            _ = alarms.flush();
+        }
+    }
+
+    pub fn get_alarms(&self) -> Vec<Alarm> {
+        self.db.open_tree(ALARMS_TREE).map_or_else(
+            |_| vec![],
+            |alarms| {
+                alarms
+                    .iter()
+                    .values()
+                    .filter_map(Result::ok)
+                    .filter_map(|alarm| serde_json::from_slice(&alarm).ok())
+                    .collect()
+            },
+        )
+    }
 }
 
 pub fn get_static_dir_path() -> String {
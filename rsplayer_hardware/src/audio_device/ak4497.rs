@@ -55,6 +55,11 @@ impl VolumeControlDevice for DacAk4497 {
             step: i64::from(self.volume_step),
         }
     }
+
+    fn set_dsd_mode(&self, enabled: bool) {
+        info!("Switching DAC to {} mode", if enabled { "DSD" } else { "PCM" });
+        self.dsd_pcm(enabled);
+    }
 }
 
 #[allow(dead_code)]
@@ -215,7 +220,6 @@ impl DacAk4497 {
         self.i2c_helper.change_bit(0, 0, true);
     }
 
-    #[allow(dead_code)]
     pub fn dsd_pcm(&self, dsd: bool) {
         if dsd {
             // switch to DSD mode
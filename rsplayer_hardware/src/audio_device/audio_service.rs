@@ -3,12 +3,15 @@ use std::sync::Arc;
 use crate::mcu::gpio::{self, GPIO_PIN_OUT_AUDIO_OUT_SELECTOR_RELAY};
 use anyhow::Result;
 use api_models::common::{Volume, VolumeCrtlType};
-use api_models::state::AudioOut;
+use api_models::state::{AudioOut, StateChangeEvent};
 use gpio_cdev::LineHandle;
 use rsplayer_config::ArcConfiguration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
 
 use super::ak4497::DacAk4497;
 use super::alsa::AlsaMixer;
+use super::software::SoftwareVolume;
 use super::VolumeControlDevice;
 
 pub type ArcAudioInterfaceSvc = Arc<AudioInterfaceService>;
@@ -24,6 +27,11 @@ impl AudioInterfaceService {
         let volume_ctrl_device: Box<dyn VolumeControlDevice + Send + Sync> =
             if settings.volume_ctrl_settings.ctrl_device == VolumeCrtlType::Dac && settings.dac_settings.enabled {
                 DacAk4497::new(&config.get_streamer_state().volume_state, &settings.dac_settings)?
+            } else if settings.volume_ctrl_settings.ctrl_device == VolumeCrtlType::Software {
+                SoftwareVolume::new(
+                    settings.volume_ctrl_settings.volume_step,
+                    &config.get_streamer_state().volume_state,
+                )
             } else {
                 AlsaMixer::new(
                     settings.alsa_settings.output_device.card_index,
@@ -52,12 +60,18 @@ impl AudioInterfaceService {
     pub fn set_volume(&self, value: i64) -> Volume {
         self.volume_ctrl_device.set_vol(value)
     }
+    pub fn get_volume(&self) -> Volume {
+        self.volume_ctrl_device.get_vol()
+    }
     pub fn volume_up(&self) -> Volume {
         self.volume_ctrl_device.vol_up()
     }
     pub fn volume_down(&self) -> Volume {
         self.volume_ctrl_device.vol_down()
     }
+    pub fn set_dsd_mode(&self, enabled: bool) {
+        self.volume_ctrl_device.set_dsd_mode(enabled);
+    }
     pub fn toggle_output(&self) -> Option<AudioOut> {
         self.output_selector_pin.as_ref().map(|out_sel_pin| {
             if out_sel_pin.get_value().unwrap() == 0 {
@@ -70,3 +84,18 @@ impl AudioInterfaceService {
         })
     }
 }
+
+/// Keeps the DAC in DSD mode while the player sends native DSD.
+pub async fn follow_dsd_playback(ai_service: ArcAudioInterfaceSvc, mut state_changes_rx: Receiver<StateChangeEvent>) {
+    let mut dsd_mode = false;
+    loop {
+        match state_changes_rx.recv().await {
+            Ok(StateChangeEvent::PlayerInfoEvent(player_info)) if player_info.native_dsd != dsd_mode => {
+                dsd_mode = player_info.native_dsd;
+                ai_service.set_dsd_mode(dsd_mode);
+            }
+            Err(RecvError::Closed) => break,
+            _ => {}
+        }
+    }
+}
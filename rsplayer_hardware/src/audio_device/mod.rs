@@ -3,6 +3,7 @@ use api_models::common::Volume;
 pub mod ak4497;
 pub mod alsa;
 pub mod audio_service;
+pub mod software;
 // pub mod test;
 
 pub trait VolumeControlDevice {
@@ -10,4 +11,6 @@ pub trait VolumeControlDevice {
     fn vol_down(&self) -> Volume;
     fn get_vol(&self) -> Volume;
     fn set_vol(&self, level: i64) -> Volume;
+    /// Switches the DAC between PCM and native DSD input, devices without DSD mode ignore it.
+    fn set_dsd_mode(&self, _enabled: bool) {}
 }
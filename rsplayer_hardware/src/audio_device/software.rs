@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use api_models::common::Volume;
+
+use super::VolumeControlDevice;
+
+const SOFTWARE_VOLUME_MAX: i64 = 100;
+
+/// Volume that is only tracked here, the playback thread scales the samples by it.
+pub struct SoftwareVolume {
+    current: AtomicI64,
+    step: i64,
+}
+
+impl SoftwareVolume {
+    pub fn new(volume_step: u8, volume: &Volume) -> Box<Self> {
+        let software_volume = SoftwareVolume {
+            current: AtomicI64::new(SOFTWARE_VOLUME_MAX),
+            step: i64::from(volume_step.max(1)),
+        };
+        // Persisted state of another device type has a different range.
+        if volume.min == 0 && volume.max == SOFTWARE_VOLUME_MAX {
+            software_volume.set_vol(volume.current);
+        }
+        Box::new(software_volume)
+    }
+}
+
+impl VolumeControlDevice for SoftwareVolume {
+    fn vol_up(&self) -> Volume {
+        let ev = self.get_vol();
+        self.set_vol(ev.current + ev.step)
+    }
+
+    fn vol_down(&self) -> Volume {
+        let ev = self.get_vol();
+        self.set_vol(ev.current - ev.step)
+    }
+
+    fn get_vol(&self) -> Volume {
+        Volume {
+            step: self.step,
+            min: 0,
+            max: SOFTWARE_VOLUME_MAX,
+            current: self.current.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_vol(&self, level: i64) -> Volume {
+        self.current.store(level.clamp(0, SOFTWARE_VOLUME_MAX), Ordering::Relaxed);
+        self.get_vol()
+    }
+}
@@ -14,11 +14,16 @@ pub async fn write(state_changes_rx: Receiver<StateChangeEvent>, config: ArcConf
 mod hw_oled {
     use super::{Receiver, StateChangeEvent};
     use crate::mcu::gpio::{get_output_pin_handle, GPIO_PIN_OUTPUT_LCD_RST};
-    use api_models::{player::Song, settings::OLEDSettings, state::PlayerInfo};
+    use api_models::{
+        player::Song,
+        settings::OLEDSettings,
+        state::{AudioLevels, PlayerInfo, SleepTimerState},
+    };
     use embedded_graphics::{
         mono_font::{ascii::FONT_4X6, ascii::FONT_5X8, ascii::FONT_6X12, MonoTextStyle},
         pixelcolor::BinaryColor,
         prelude::*,
+        primitives::{PrimitiveStyle, Rectangle},
         text::Text,
     };
 
@@ -61,6 +66,12 @@ mod hw_oled {
                     Ok(StateChangeEvent::PlayerInfoEvent(pinfo)) => {
                         draw_player_info(&mut disp, &mut delay, &pinfo);
                     }
+                    Ok(StateChangeEvent::SleepTimerEvent(timer)) => {
+                        draw_sleep_timer(&mut disp, &mut delay, timer.as_ref());
+                    }
+                    Ok(StateChangeEvent::AudioLevelsEvent(levels)) => {
+                        draw_audio_levels(&mut disp, &mut delay, &levels);
+                    }
                     _ => {}
                 }
             }
@@ -121,6 +132,47 @@ mod hw_oled {
         disp.flush_region(1, 12, 120, 40, delay).unwrap();
     }
 
+    /// Countdown of the sleep timer at the right of the player info line.
+    fn draw_sleep_timer(
+        disp: &mut ST7920<SpidevDevice, CdevPin, CdevPin>,
+        delay: &mut Delay,
+        timer: Option<&SleepTimerState>,
+    ) {
+        _ = disp.clear_buffer_region(88, 50, 32, 12);
+        if let Some(remaining) = timer.and_then(|timer| timer.remaining) {
+            let secs = remaining.as_secs();
+            Text::new(
+                format!("Z {:02}:{:02}", secs / 60, secs % 60).as_str(),
+                Point::new(88, 60),
+                MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+            )
+            .draw(disp)
+            .expect("Failed to draw text");
+        }
+        disp.flush_region(88, 50, 32, 12, delay).expect("Failed to flush!");
+    }
+
+    /// Peak meters of the first two channels as bars at the right of the track info, from -60 dB to
+    /// full scale.
+    fn draw_audio_levels(disp: &mut ST7920<SpidevDevice, CdevPin, CdevPin>, delay: &mut Delay, levels: &AudioLevels) {
+        const RANGE_DB: f32 = 60.0;
+        const TOP: u8 = 12;
+        const HEIGHT: u8 = 38;
+        _ = disp.clear_buffer_region(121, TOP, 7, HEIGHT);
+        for (x, peak_db) in [122, 125].into_iter().zip(&levels.peak_db) {
+            let fill = ((peak_db + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0);
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let bar = (fill * f32::from(HEIGHT)) as u8;
+            _ = Rectangle::new(
+                Point::new(x, i32::from(TOP + HEIGHT - bar)),
+                Size::new(2, u32::from(bar)),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(disp);
+        }
+        disp.flush_region(121, TOP, 7, HEIGHT, delay).expect("Failed to flush!");
+    }
+
     fn draw_player_info(
         disp: &mut ST7920<SpidevDevice, CdevPin, CdevPin>,
         delay: &mut Delay,
@@ -0,0 +1,175 @@
+//! CUE sheets describing the tracks of single file album images.
+use std::path::Path;
+use std::time::Duration;
+
+use api_models::player::{CueTrack, Song};
+
+/// Tag holding a CUE sheet embedded in the image file.
+const CUESHEET_TAG: &str = "CUESHEET";
+/// CUE sheet positions are given in CD frames.
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub files: Vec<String>,
+    pub tracks: Vec<CueSheetTrack>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CueSheetTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Position of `INDEX 01`, the pregap before it is played with the previous track.
+    pub start: Option<Duration>,
+}
+
+/// Parses the commands of a CUE sheet, unknown commands are ignored.
+pub fn parse(text: &str) -> CueSheet {
+    let mut sheet = CueSheet::default();
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        match command.to_uppercase().as_str() {
+            "FILE" => sheet.files.push(file_name(args)),
+            "TRACK" => sheet.tracks.push(CueSheetTrack {
+                number: first_word(args).parse().unwrap_or_default(),
+                ..Default::default()
+            }),
+            "TITLE" => match sheet.tracks.last_mut() {
+                Some(track) => track.title = Some(unquote(args)),
+                None => sheet.title = Some(unquote(args)),
+            },
+            "PERFORMER" => match sheet.tracks.last_mut() {
+                Some(track) => track.performer = Some(unquote(args)),
+                None => sheet.performer = Some(unquote(args)),
+            },
+            "INDEX" => {
+                if let (Some(track), Some(("01", position))) =
+                    (sheet.tracks.last_mut(), args.split_once(char::is_whitespace))
+                {
+                    track.start = parse_position(position.trim());
+                }
+            }
+            "REM" if sheet.tracks.is_empty() => match args.split_once(char::is_whitespace) {
+                Some((key, value)) if key.eq_ignore_ascii_case("GENRE") => sheet.genre = Some(unquote(value.trim())),
+                Some((key, value)) if key.eq_ignore_ascii_case("DATE") => sheet.date = Some(unquote(value.trim())),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    sheet
+}
+
+/// The CUE sheet of the image file, read from a `.cue` file next to it or from the `CUESHEET` tag,
+/// which is removed from the song.
+pub fn find_sheet(image_path: &Path, image: &mut Song) -> Option<CueSheet> {
+    let embedded = image
+        .tags
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(CUESHEET_TAG))
+        .cloned()
+        .and_then(|key| image.tags.remove(&key));
+    let file_name = image_path.file_name()?.to_string_lossy();
+    let external = [
+        image_path.with_extension("cue"),
+        image_path.with_extension("CUE"),
+        image_path.with_file_name(format!("{file_name}.cue")),
+    ]
+    .into_iter()
+    .find_map(|path| std::fs::read(path).ok())
+    .map(|bytes| decode(&bytes));
+    external.or(embedded).map(|text| parse(&text))
+}
+
+/// Songs of the tracks of the image file, empty when the sheet does not describe a single image.
+pub fn track_songs(image: &Song, sheet: &CueSheet) -> Vec<Song> {
+    if sheet.files.len() != 1 {
+        return vec![];
+    }
+    let tracks: Vec<(&CueSheetTrack, Duration)> = sheet
+        .tracks
+        .iter()
+        .filter_map(|track| track.start.map(|start| (track, start)))
+        .collect();
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(i, &(track, start))| {
+            let end = tracks.get(i + 1).map(|&(_, next_start)| next_start);
+            Song {
+                title: track.title.clone().or_else(|| image.title.clone()),
+                artist: track
+                    .performer
+                    .clone()
+                    .or_else(|| sheet.performer.clone())
+                    .or_else(|| image.artist.clone()),
+                album: sheet.title.clone().or_else(|| image.album.clone()),
+                album_artist: sheet.performer.clone().or_else(|| image.album_artist.clone()),
+                genre: sheet.genre.clone().or_else(|| image.genre.clone()),
+                date: sheet.date.clone().or_else(|| image.date.clone()),
+                track: Some(track.number.to_string()),
+                time: end.or(image.time).map(|end| end.saturating_sub(start)),
+                file: track_key(&image.file, track.number),
+                cue_track: Some(CueTrack {
+                    image_file: image.file.clone(),
+                    start,
+                    end,
+                }),
+                ..image.clone()
+            }
+        })
+        .collect()
+}
+
+/// Database key of a track of the image file.
+pub fn track_key(image_file: &str, number: u32) -> String {
+    format!("{}{number:02}", track_key_prefix(image_file))
+}
+
+/// Prefix of the database keys of all tracks of the image file.
+pub fn track_key_prefix(image_file: &str) -> String {
+    format!("{image_file}#")
+}
+
+/// CUE files are often written in a legacy encoding, which is read as Latin-1 when it is not UTF-8.
+fn decode(bytes: &[u8]) -> String {
+    String::from_utf8(bytes.to_vec()).unwrap_or_else(|_| bytes.iter().map(|&b| char::from(b)).collect())
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.rsplit_once('"'))
+        .map_or(value, |(quoted, _)| quoted)
+        .to_string()
+}
+
+/// Name of the file in `FILE "name" TYPE`, the name may be unquoted.
+fn file_name(args: &str) -> String {
+    if args.starts_with('"') {
+        unquote(args)
+    } else {
+        args.rsplit_once(char::is_whitespace)
+            .map_or(args, |(name, _)| name.trim_end())
+            .to_string()
+    }
+}
+
+fn first_word(value: &str) -> &str {
+    value.split_whitespace().next().unwrap_or_default()
+}
+
+/// Parses a `mm:ss:ff` position.
+fn parse_position(position: &str) -> Option<Duration> {
+    let mut parts = position.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    let frame_nanos = frames * 1_000_000_000 / FRAMES_PER_SECOND;
+    Some(Duration::from_secs(minutes * 60 + seconds) + Duration::from_nanos(frame_nanos))
+}
@@ -0,0 +1,333 @@
+//! DSF and DSDIFF containers of 1 bit DSD audio, which symphonia can't read.
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{format_err, Result};
+use api_models::player::Song;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsdContainer {
+    /// Sony DSD stream file, channel blocks of LSB first bytes.
+    Dsf,
+    /// Philips DSDIFF, MSB first bytes interleaved by channel.
+    Dff,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsdInfo {
+    pub container: DsdContainer,
+    /// Number of 1 bit samples per second of one channel, 2822400 for DSD64.
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Number of 1 bit samples of one channel.
+    pub samples: u64,
+    pub data_offset: u64,
+    pub data_len: u64,
+    /// Bytes of one channel stored together, 1 when the channels are interleaved byte by byte.
+    pub block_size: usize,
+    pub id3_offset: Option<u64>,
+}
+
+impl DsdInfo {
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.samples * 1000 / u64::from(self.sample_rate.max(1)))
+    }
+}
+
+#[must_use]
+pub fn is_dsd_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("dsf") || ext.eq_ignore_ascii_case("dff"))
+}
+
+pub fn read_info<R: Read + Seek>(reader: &mut R) -> Result<DsdInfo> {
+    reader.seek(SeekFrom::Start(0))?;
+    let id = read_id(reader)?;
+    match &id {
+        b"DSD " => read_dsf_info(reader),
+        b"FRM8" => read_dff_info(reader),
+        _ => Err(format_err!("Not a DSF or DSDIFF file")),
+    }
+}
+
+fn read_dsf_info<R: Read + Seek>(reader: &mut R) -> Result<DsdInfo> {
+    let _header_size = read_u64_le(reader)?;
+    let _file_size = read_u64_le(reader)?;
+    let metadata_offset = read_u64_le(reader)?;
+    if &read_id(reader)? != b"fmt " {
+        return Err(format_err!("DSF fmt chunk is missing"));
+    }
+    let fmt_size = read_u64_le(reader)?;
+    let _version = read_u32_le(reader)?;
+    if read_u32_le(reader)? != 0 {
+        return Err(format_err!("Only raw DSF format is supported"));
+    }
+    let _channel_type = read_u32_le(reader)?;
+    let channels = read_u32_le(reader)? as usize;
+    let sample_rate = read_u32_le(reader)?;
+    let bits_per_sample = read_u32_le(reader)?;
+    if bits_per_sample != 1 {
+        return Err(format_err!("Unsupported DSF bits per sample {bits_per_sample}"));
+    }
+    let samples = read_u64_le(reader)?;
+    let block_size = read_u32_le(reader)? as usize;
+    // The fmt chunk size includes its 12 byte header.
+    reader.seek(SeekFrom::Start(28 + fmt_size))?;
+    if &read_id(reader)? != b"data" {
+        return Err(format_err!("DSF data chunk is missing"));
+    }
+    let data_len = read_u64_le(reader)?.saturating_sub(12);
+    if channels == 0 || block_size == 0 {
+        return Err(format_err!("Invalid DSF format chunk"));
+    }
+    Ok(DsdInfo {
+        container: DsdContainer::Dsf,
+        sample_rate,
+        channels,
+        samples,
+        data_offset: reader.stream_position()?,
+        data_len,
+        block_size,
+        id3_offset: (metadata_offset > 0).then_some(metadata_offset),
+    })
+}
+
+fn read_dff_info<R: Read + Seek>(reader: &mut R) -> Result<DsdInfo> {
+    let form_end = read_u64_be(reader)? + 12;
+    if &read_id(reader)? != b"DSD " {
+        return Err(format_err!("Not a DSD form"));
+    }
+    let mut sample_rate = 0;
+    let mut channels = 0;
+    let mut data = None;
+    let mut id3_offset = None;
+    let mut pos = reader.stream_position()?;
+    while pos + 12 <= form_end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let id = read_id(reader)?;
+        let size = read_u64_be(reader)?;
+        let start = pos + 12;
+        match &id {
+            b"PROP" => {
+                if &read_id(reader)? != b"SND " {
+                    return Err(format_err!("Unsupported DSDIFF property chunk"));
+                }
+                let prop_end = start + size;
+                let mut prop_pos = start + 4;
+                while prop_pos + 12 <= prop_end {
+                    reader.seek(SeekFrom::Start(prop_pos))?;
+                    let prop_id = read_id(reader)?;
+                    let prop_size = read_u64_be(reader)?;
+                    match &prop_id {
+                        b"FS  " => sample_rate = read_u32_be(reader)?,
+                        b"CHNL" => channels = usize::from(read_u16_be(reader)?),
+                        b"CMPR" if &read_id(reader)? != b"DSD " => {
+                            return Err(format_err!("Compressed DST audio is not supported"));
+                        }
+                        _ => {}
+                    }
+                    prop_pos += 12 + prop_size + (prop_size & 1);
+                }
+            }
+            b"DSD " => data = Some((start, size)),
+            b"DST " => return Err(format_err!("Compressed DST audio is not supported")),
+            b"ID3 " => id3_offset = Some(start),
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        pos = start + size + (size & 1);
+    }
+    let Some((data_offset, data_len)) = data else {
+        return Err(format_err!("DSDIFF sound data chunk is missing"));
+    };
+    if channels == 0 || sample_rate == 0 {
+        return Err(format_err!("DSDIFF properties are missing"));
+    }
+    Ok(DsdInfo {
+        container: DsdContainer::Dff,
+        sample_rate,
+        channels,
+        samples: data_len * 8 / channels as u64,
+        data_offset,
+        data_len,
+        block_size: 1,
+        id3_offset,
+    })
+}
+
+/// Builds the song from the container and its ID3 tag, also returns the embedded picture.
+pub fn read_song<R: Read + Seek>(reader: &mut R) -> Result<(Song, Option<Vec<u8>>)> {
+    let info = read_info(reader)?;
+    let mut song = Song {
+        time: Some(info.duration()),
+        ..Default::default()
+    };
+    let picture = match info.id3_offset {
+        Some(offset) => {
+            reader.seek(SeekFrom::Start(offset))?;
+            read_id3(reader, &mut song)?
+        }
+        None => None,
+    };
+    Ok((song, picture))
+}
+
+fn read_id3<R: Read>(reader: &mut R, song: &mut Song) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header)?;
+    if &header[..3] != b"ID3" {
+        return Err(format_err!("ID3v2 tag is missing"));
+    }
+    let version = header[3];
+    if !(3..=4).contains(&version) {
+        return Err(format_err!("Unsupported ID3v2 version {version}"));
+    }
+    let mut tag = vec![0u8; syncsafe(&header[6..10]) as usize];
+    reader.read_exact(&mut tag)?;
+    // Frames start after the extended header, if there is one.
+    let mut pos = match (header[5] & 0x40 != 0 && tag.len() >= 4, version) {
+        (false, _) => 0,
+        (true, 4) => syncsafe(&tag[..4]) as usize,
+        (true, _) => be_u32(&tag[..4]) as usize + 4,
+    };
+    let mut picture = None;
+    while pos + 10 <= tag.len() && tag[pos] != 0 {
+        let id = String::from_utf8_lossy(&tag[pos..pos + 4]).to_string();
+        let size = if version == 4 {
+            syncsafe(&tag[pos + 4..pos + 8])
+        } else {
+            be_u32(&tag[pos + 4..pos + 8])
+        } as usize;
+        let start = pos + 10;
+        let Some(frame) = tag.get(start..start + size) else {
+            break;
+        };
+        pos = start + size;
+        if frame.is_empty() {
+            continue;
+        }
+        if id == "APIC" {
+            picture = picture.or_else(|| picture_data(frame));
+            continue;
+        }
+        if !id.starts_with('T') {
+            continue;
+        }
+        let text = decode_text(frame[0], &frame[1..]);
+        let values = text.split('\0').filter(|v| !v.is_empty()).collect::<Vec<_>>().join("; ");
+        let value = Some(values.clone());
+        match id.as_str() {
+            "TIT2" => song.title = value,
+            "TALB" => song.album = value,
+            "TPE1" => song.artist = value,
+            "TPE2" => song.album_artist = value,
+            "TCOM" => song.composer = value,
+            "TCON" => song.genre = value,
+            "TDRC" | "TYER" => song.date = value,
+            "TRCK" => song.track = value,
+            "TPOS" => song.disc = value,
+            "TPUB" => song.label = value,
+            "TXXX" => {
+                if let Some((key, value)) = text.split_once('\0') {
+                    let key = if key.to_uppercase().starts_with("REPLAYGAIN_") {
+                        key.to_uppercase()
+                    } else {
+                        key.to_string()
+                    };
+                    song.tags.insert(key, value.trim_end_matches('\0').to_string());
+                }
+            }
+            _ => {
+                song.tags.insert(id, values);
+            }
+        }
+    }
+    Ok(picture)
+}
+
+/// Picture data of an APIC frame: encoding, MIME type, picture type, description, data.
+fn picture_data(frame: &[u8]) -> Option<Vec<u8>> {
+    let encoding = frame[0];
+    let mime_end = 1 + frame[1..].iter().position(|b| *b == 0)?;
+    let desc_start = mime_end + 2;
+    let desc_len = if encoding == 1 || encoding == 2 {
+        frame
+            .get(desc_start..)?
+            .chunks(2)
+            .position(|c| c == [0, 0])?
+            * 2
+            + 2
+    } else {
+        frame.get(desc_start..)?.iter().position(|b| *b == 0)? + 1
+    };
+    frame.get(desc_start + desc_len..).map(<[u8]>::to_vec)
+}
+
+fn decode_text(encoding: u8, data: &[u8]) -> String {
+    let text = match encoding {
+        1 | 2 => {
+            let little_endian = encoding == 1 && data.starts_with(&[0xFF, 0xFE]);
+            let units = data
+                .chunks_exact(2)
+                .map(|c| {
+                    if little_endian {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units).replace('\u{feff}', "")
+        }
+        3 => String::from_utf8_lossy(data).to_string(),
+        _ => data.iter().map(|b| char::from(*b)).collect(),
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, b| (acc << 7) | u32::from(b & 0x7F))
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_id<R: Read>(reader: &mut R) -> Result<[u8; 4]> {
+    let mut id = [0u8; 4];
+    reader.read_exact(&mut id)?;
+    Ok(id)
+}
+
+fn read_u16_be<R: Read>(reader: &mut R) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32_be<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u32_le<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64_be<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_u64_le<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -1,7 +1,10 @@
 pub mod album_repository;
+pub mod cue;
+pub mod dsd;
 pub mod metadata_service;
 pub mod play_statistic_repository;
 pub mod playlist_service;
+pub mod podcast_service;
 pub mod queue_service;
 pub mod song_repository;
 #[cfg(test)]
@@ -29,6 +29,8 @@ use api_models::{
     state::StateChangeEvent,
 };
 
+use crate::cue;
+use crate::dsd;
 use crate::song_repository::SongRepository;
 use crate::{album_repository::AlbumRepository, play_statistic_repository::PlayStatisticsRepository};
 
@@ -105,6 +107,14 @@ impl MetadataService {
         };
     }
 
+    /// Adds the file of the song to the ignored files, so it is neither scanned nor queued again.
+    pub fn ignore_unplayable_song(&self, song: &Song, reason: &str) {
+        _ = self.ignored_files_db.insert(song.source_file(), reason.as_bytes());
+        _ = self.ignored_files_db.flush();
+        self.song_repository.delete(&song.file);
+        self.song_repository.flush();
+    }
+
     pub fn search_local_files_by_dir(&self, dir: &str) -> Vec<MetadataLibraryItem> {
         let start_time = std::time::Instant::now();
         let result = self.song_repository.find_by_key_prefix(dir).map(|(key, value)| {
@@ -236,14 +246,21 @@ impl MetadataService {
             })
             .filter(|de| !self.ignored_files_db.contains_key(&de.1).unwrap_or(false))
         {
-            if self.song_repository.find_by_id(&entry.1).is_some() {
+            // Image files described by a CUE sheet are stored as their tracks only.
+            let is_scanned = self.song_repository.find_by_id(&entry.1).is_some()
+                || self
+                    .song_repository
+                    .find_by_key_prefix(&cue::track_key_prefix(&entry.1))
+                    .next()
+                    .is_some();
+            if is_scanned {
                 unchanged_keys.push(entry.1.clone());
             } else {
                 added_files.push(entry.0.clone());
             }
         }
         for song in self.song_repository.get_all_iterator() {
-            if !unchanged_keys.contains(&song.file) {
+            if !unchanged_keys.iter().any(|key| key == song.source_file()) {
                 deleted_keys.push(song.file);
             }
         }
@@ -278,9 +295,25 @@ impl MetadataService {
     fn scan_single_file(&self, file_path: &Path) -> Result<()> {
         info!("Scanning file:\t{:?}", file_path);
 
-        let file = Box::new(File::open(file_path).unwrap());
+        let mut file = Box::new(File::open(file_path).unwrap());
         let file_modification_date: DateTime<Utc> = file.as_ref().metadata()?.modified()?.into();
 
+        if dsd::is_dsd_file(&file_path.to_string_lossy()) {
+            let file_p = self.full_path_to_database_key(file_path.to_str().unwrap());
+            let (mut song, picture) = dsd::read_song(&mut file).map_err(|err| {
+                warn!("Error:{file_p} {err}");
+                Error::msg(format!("Error:{file_p} {err}"))
+            })?;
+            if let Some(picture) = &picture {
+                save_artwork(&mut song, picture);
+            }
+            song.file = file_p;
+            song.file_date = file_modification_date;
+            self.song_repository.save(&song);
+            self.album_repository.update_from_song(song);
+            return Ok(());
+        }
+
         let mss = MediaSourceStream::new(file, MediaSourceStreamOptions::default());
 
         let mut hint = Hint::new();
@@ -288,8 +321,9 @@ impl MetadataService {
             let ext = ext.to_str().unwrap().to_lowercase();
             hint.with_extension(&ext);
         }
+        // Gapless mode makes the reported duration exclude encoder delay and padding.
         let format_opts = FormatOptions {
-            enable_gapless: false,
+            enable_gapless: true,
             ..Default::default()
         };
         // Use the default options for metadata readers.
@@ -302,20 +336,20 @@ impl MetadataService {
                 let (mut song, image_data) = build_song(&mut probed);
 
                 if let Some(image_data) = &image_data {
-                    let image_id = uuid::Uuid::new_v4();
-                    if let Err(e) = std::fs::write(Path::new(ARTWORK_DIR).join(image_id.to_string()), &image_data.data)
-                    {
-                        warn!("Error writing image file: {}", e);
-                    } else {
-                        song.image_id = Some(image_id.to_string());
-                    }
+                    save_artwork(&mut song, &image_data.data);
                 };
 
                 song.file = file_p.to_string();
                 song.file_date = file_modification_date;
-                log::debug!("Add/update song in database: {:?}", song);
-                self.song_repository.save(&song);
-                self.album_repository.update_from_song(song);
+                let cue_songs = cue::find_sheet(file_path, &mut song)
+                    .map(|sheet| cue::track_songs(&song, &sheet))
+                    .unwrap_or_default();
+                let songs = if cue_songs.is_empty() { vec![song] } else { cue_songs };
+                for song in songs {
+                    log::debug!("Add/update song in database: {:?}", song);
+                    self.song_repository.save(&song);
+                    self.album_repository.update_from_song(song);
+                }
 
                 Ok(())
             }
@@ -327,6 +361,15 @@ impl MetadataService {
     }
 }
 
+fn save_artwork(song: &mut Song, data: &[u8]) {
+    let image_id = uuid::Uuid::new_v4();
+    if let Err(e) = std::fs::write(Path::new(ARTWORK_DIR).join(image_id.to_string()), data) {
+        warn!("Error writing image file: {}", e);
+    } else {
+        song.image_id = Some(image_id.to_string());
+    }
+}
+
 fn build_song(probed: &mut ProbeResult) -> (Song, Option<Visual>) {
     let mut song = Song::default();
     let mut image_data: Option<Visual> = None;
@@ -358,6 +401,11 @@ fn build_song(probed: &mut ProbeResult) -> (Song, Option<Visual>) {
                 StandardTagKey::TrackTitle => {
                     song.title = from_tag_value_to_option(known_tag);
                 }
+                // Kept with the other raw tags, playback reads them to apply ReplayGain.
+                StandardTagKey::ReplayGainTrackGain => insert_tag(&mut song, "REPLAYGAIN_TRACK_GAIN", known_tag),
+                StandardTagKey::ReplayGainTrackPeak => insert_tag(&mut song, "REPLAYGAIN_TRACK_PEAK", known_tag),
+                StandardTagKey::ReplayGainAlbumGain => insert_tag(&mut song, "REPLAYGAIN_ALBUM_GAIN", known_tag),
+                StandardTagKey::ReplayGainAlbumPeak => insert_tag(&mut song, "REPLAYGAIN_ALBUM_PEAK", known_tag),
                 _ => {}
             }
         }
@@ -374,6 +422,10 @@ fn build_song(probed: &mut ProbeResult) -> (Song, Option<Visual>) {
     (song, image_data)
 }
 
+fn insert_tag(song: &mut Song, key: &str, tag: &Tag) {
+    song.tags.insert(key.to_string(), tag.value.to_string());
+}
+
 #[allow(clippy::unnecessary_wraps)]
 fn from_tag_value_to_option(tag: &Tag) -> Option<String> {
     Some(tag.value.to_string())
@@ -0,0 +1,302 @@
+//! Podcast subscriptions. The feeds are fetched periodically, their episodes are kept with the
+//! played state and the position their playback resumes from.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use feed_rs::model::{Entry, Feed};
+use log::{info, warn};
+use sled::Tree;
+use tokio::sync::broadcast::Sender;
+use tokio::time::interval;
+use ureq::{Agent, AgentBuilder};
+
+use api_models::{
+    player::Song,
+    podcast::{Episode, Podcast},
+    settings::PodcastSettings,
+    state::StateChangeEvent,
+};
+
+/// Tag of the queue item of an episode, holding the url of its feed.
+pub const FEED_URL_TAG: &str = "PodcastFeedUrl";
+
+const FEED_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_FEED_LEN: u64 = 32 * 1024 * 1024;
+
+pub struct PodcastService {
+    podcasts_db: Tree,
+    /// Episodes by the url of their feed and of their audio, as feeds may share audio files.
+    episodes_db: Tree,
+    agent: Agent,
+    refresh_interval: Duration,
+}
+
+impl PodcastService {
+    #[must_use]
+    pub fn new(settings: &PodcastSettings) -> Self {
+        let db = sled::open(&settings.db_path).expect("Failed to open podcast database");
+        Self {
+            podcasts_db: db.open_tree("podcasts").expect("Failed to open podcasts tree"),
+            episodes_db: db.open_tree("episodes").expect("Failed to open episodes tree"),
+            agent: AgentBuilder::new().timeout(FEED_TIMEOUT).build(),
+            refresh_interval: Duration::from_secs(u64::from(settings.refresh_minutes) * 60),
+        }
+    }
+
+    /// Subscribes to the podcast and fetches its episodes, the subscription fails when the feed
+    /// can't be read.
+    pub fn subscribe(&self, feed_url: &str) -> Result<Podcast> {
+        let feed = self.fetch_feed(feed_url)?;
+        let podcast = self.save_feed(feed_url, feed);
+        info!("Subscribed to podcast {}", podcast.title);
+        Ok(podcast)
+    }
+
+    pub fn unsubscribe(&self, feed_url: &str) {
+        _ = self.podcasts_db.remove(feed_url);
+        for key in self
+            .episodes_db
+            .scan_prefix(feed_prefix(feed_url))
+            .keys()
+            .filter_map(Result::ok)
+        {
+            _ = self.episodes_db.remove(key);
+        }
+    }
+
+    /// Fetches the feeds of all podcasts, a failed refresh is recorded on its podcast. Returns the
+    /// number of new episodes.
+    pub fn refresh_all(&self) -> usize {
+        let mut new_episodes = 0;
+        for mut podcast in self.get_podcasts() {
+            match self.fetch_feed(&podcast.feed_url) {
+                Ok(feed) => {
+                    let known = self.get_episodes(&podcast.feed_url).len();
+                    self.save_feed(&podcast.feed_url, feed);
+                    new_episodes += self.get_episodes(&podcast.feed_url).len().saturating_sub(known);
+                }
+                Err(err) => {
+                    warn!("Failed to refresh podcast {}: {err}", podcast.feed_url);
+                    podcast.refresh_error = Some(err.to_string());
+                    self.save_podcast(&podcast);
+                }
+            }
+        }
+        new_episodes
+    }
+
+    pub fn get_podcasts(&self) -> Vec<Podcast> {
+        self.podcasts_db
+            .iter()
+            .filter_map(Result::ok)
+            .filter_map(|(_, value)| serde_json::from_slice(&value).ok())
+            .collect()
+    }
+
+    /// Episodes of the podcast, newest first.
+    pub fn get_episodes(&self, feed_url: &str) -> Vec<Episode> {
+        let mut episodes: Vec<Episode> = self
+            .episodes_db
+            .scan_prefix(feed_prefix(feed_url))
+            .values()
+            .filter_map(Result::ok)
+            .filter_map(|value| serde_json::from_slice(&value).ok())
+            .collect();
+        episodes.sort_by_key(|episode| Reverse(episode.published));
+        episodes
+    }
+
+    pub fn find_episode(&self, feed_url: &str, audio_url: &str) -> Option<Episode> {
+        self.episodes_db
+            .get(episode_key(feed_url, audio_url))
+            .ok()
+            .flatten()
+            .and_then(|value| serde_json::from_slice(&value).ok())
+    }
+
+    /// Episode the queue item was made of, `None` for other songs.
+    pub fn find_song_episode(&self, song: &Song) -> Option<Episode> {
+        self.find_episode(song.tags.get(FEED_URL_TAG)?, &song.file)
+    }
+
+    /// Queue item of the episode, with the podcast as its album.
+    pub fn episode_song(&self, feed_url: &str, audio_url: &str) -> Option<Song> {
+        let episode = self.find_episode(feed_url, audio_url)?;
+        let podcast = self.find_podcast(&episode.feed_url).unwrap_or_default();
+        Some(Song {
+            title: Some(episode.title),
+            album: Some(podcast.title),
+            artist: podcast.author,
+            date: episode.published.map(|published| published.date_naive().to_string()),
+            time: episode.duration,
+            image_url: podcast.image_url,
+            tags: HashMap::from([(FEED_URL_TAG.to_string(), episode.feed_url)]),
+            file: episode.audio_url,
+            file_date: episode.published.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    /// Marking the episode played or unplayed starts its next playback from the beginning.
+    pub fn set_played(&self, feed_url: &str, audio_url: &str, played: bool) {
+        self.update_episode(feed_url, audio_url, |episode| {
+            episode.played = played;
+            episode.position = Duration::ZERO;
+        });
+    }
+
+    pub fn save_position(&self, feed_url: &str, audio_url: &str, position: Duration) {
+        self.update_episode(feed_url, audio_url, |episode| episode.position = position);
+    }
+
+    /// Position the playback of the queue item resumes from, `None` for other songs and for
+    /// episodes played from the beginning.
+    pub fn resume_position(&self, song: &Song) -> Option<Duration> {
+        self.find_song_episode(song)
+            .map(|episode| episode.position)
+            .filter(|position| !position.is_zero())
+    }
+
+    fn find_podcast(&self, feed_url: &str) -> Option<Podcast> {
+        self.podcasts_db
+            .get(feed_url)
+            .ok()
+            .flatten()
+            .and_then(|value| serde_json::from_slice(&value).ok())
+    }
+
+    fn fetch_feed(&self, feed_url: &str) -> Result<Feed> {
+        let resp = self
+            .agent
+            .get(feed_url)
+            .call()
+            .map_err(|err| format_err!("Failed to get url {feed_url}: {err}"))?;
+        feed_rs::parser::Builder::new()
+            .base_uri(Some(feed_url))
+            .build()
+            .parse(resp.into_reader().take(MAX_FEED_LEN))
+            .map_err(|err| format_err!("Failed to parse feed {feed_url}: {err}"))
+    }
+
+    /// Saves the podcast and its episodes, the known episodes keep their played state and position.
+    fn save_feed(&self, feed_url: &str, feed: Feed) -> Podcast {
+        let podcast = Podcast {
+            feed_url: feed_url.to_string(),
+            title: feed.title.map_or_else(|| feed_url.to_string(), |title| title.content),
+            description: feed.description.map(|description| description.content),
+            author: feed.authors.into_iter().next().map(|author| author.name),
+            image_url: feed.logo.or(feed.icon).map(|image| image.uri),
+            last_refreshed: Some(Utc::now()),
+            refresh_error: None,
+        };
+        self.save_podcast(&podcast);
+        for entry in &feed.entries {
+            let Some(mut episode) = to_episode(feed_url, entry) else {
+                continue;
+            };
+            if let Some(known) = self.find_episode(feed_url, &episode.audio_url) {
+                episode.played = known.played;
+                episode.position = known.position;
+            }
+            self.save_episode(&episode);
+        }
+        podcast
+    }
+
+    fn update_episode(&self, feed_url: &str, audio_url: &str, update: impl FnOnce(&mut Episode)) {
+        if let Some(mut episode) = self.find_episode(feed_url, audio_url) {
+            update(&mut episode);
+            self.save_episode(&episode);
+        }
+    }
+
+    fn save_podcast(&self, podcast: &Podcast) {
+        _ = self.podcasts_db.insert(
+            podcast.feed_url.as_str(),
+            serde_json::to_vec(podcast).expect("Podcast serialization failed!"),
+        );
+    }
+
+    fn save_episode(&self, episode: &Episode) {
+        _ = self.episodes_db.insert(
+            episode_key(&episode.feed_url, &episode.audio_url),
+            serde_json::to_vec(episode).expect("Episode serialization failed!"),
+        );
+    }
+}
+
+/// Prefix of the keys of the episodes of the feed, the url of the feed ended by a zero byte.
+fn feed_prefix(feed_url: &str) -> Vec<u8> {
+    let mut prefix = feed_url.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+fn episode_key(feed_url: &str, audio_url: &str) -> Vec<u8> {
+    let mut key = feed_prefix(feed_url);
+    key.extend_from_slice(audio_url.as_bytes());
+    key
+}
+
+/// Episode of the feed entry, `None` for entries without audio.
+fn to_episode(feed_url: &str, entry: &Entry) -> Option<Episode> {
+    let is_audio = |media_type: &str| media_type.starts_with("audio/");
+    // RSS enclosures and media content, Atom enclosure links.
+    let (audio_url, duration) = entry
+        .media
+        .iter()
+        .flat_map(|media| media.content.iter().map(move |content| (media, content)))
+        .find(|(_, content)| {
+            content.url.is_some()
+                && content
+                    .content_type
+                    .as_ref()
+                    .is_none_or(|content_type| is_audio(content_type.as_ref()))
+        })
+        .and_then(|(media, content)| Some((content.url.as_ref()?.to_string(), media.duration.or(content.duration))))
+        .or_else(|| {
+            entry
+                .links
+                .iter()
+                .find(|link| {
+                    link.rel.as_deref() == Some("enclosure") && link.media_type.as_deref().is_none_or(is_audio)
+                })
+                .map(|link| (link.href.clone(), None))
+        })?;
+    Some(Episode {
+        audio_url,
+        feed_url: feed_url.to_string(),
+        title: entry
+            .title
+            .as_ref()
+            .map_or_else(|| entry.id.clone(), |title| title.content.clone()),
+        description: entry.summary.as_ref().map(|summary| summary.content.clone()),
+        published: entry.published.or(entry.updated),
+        duration,
+        played: false,
+        position: Duration::ZERO,
+    })
+}
+
+/// Refreshes the podcasts periodically and sends them once new episodes were found.
+pub async fn run(podcast_service: Arc<PodcastService>, changes_tx: Sender<StateChangeEvent>) {
+    let mut ticks = interval(podcast_service.refresh_interval);
+    loop {
+        ticks.tick().await;
+        let service = podcast_service.clone();
+        let Ok(new_episodes) = tokio::task::spawn_blocking(move || service.refresh_all()).await else {
+            continue;
+        };
+        if new_episodes > 0 {
+            info!("Podcasts refreshed with {new_episodes} new episodes");
+            changes_tx
+                .send(StateChangeEvent::PodcastsEvent(podcast_service.get_podcasts()))
+                .ok();
+        }
+    }
+}
@@ -1,12 +1,18 @@
 use std::sync::{
     atomic::{AtomicBool, AtomicU16, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
 use rand::Rng;
 use sled::{Db, IVec, Tree};
 
-use api_models::{player::Song, playlist::PlaylistPage, settings::PlaybackQueueSetting, state::CurrentQueueQuery};
+use api_models::{
+    common::{RepeatMode, StopAfter},
+    player::Song,
+    playlist::PlaylistPage,
+    settings::PlaybackQueueSetting,
+    state::CurrentQueueQuery,
+};
 
 use crate::{play_statistic_repository::PlayStatisticsRepository, song_repository::SongRepository};
 
@@ -14,13 +20,22 @@ pub struct QueueService {
     queue_db: Db,
     status_db: Tree,
     random_flag: AtomicBool,
+    repeat_mode: Mutex<RepeatMode>,
+    stop_after: Mutex<StopAfter>,
     random_history_db: Tree,
     random_history_index: AtomicU16,
     song_repository: Arc<SongRepository>,
     statistics_repository: Arc<PlayStatisticsRepository>,
 }
 
+/// Song of the queue along with its entry.
+pub struct QueueEntry {
+    key: IVec,
+    pub song: Song,
+}
+
 const CURRENT_SONG_KEY: &str = "current_song_key";
+const REPEAT_MODE_KEY: &str = "repeat_mode";
 
 impl QueueService {
     #[must_use]
@@ -37,10 +52,18 @@ impl QueueService {
         random_history_db.clear().expect("Failed to clear random history");
         random_history_db.flush().expect("Failed to flush random history");
         let random_flag = status_db.contains_key("random_next").unwrap_or(false);
+        let repeat_mode = status_db
+            .get(REPEAT_MODE_KEY)
+            .ok()
+            .flatten()
+            .and_then(|value| serde_json::from_slice(&value).ok())
+            .unwrap_or_default();
         Self {
             queue_db: db,
             status_db,
             random_flag: AtomicBool::new(random_flag),
+            repeat_mode: Mutex::new(repeat_mode),
+            stop_after: Mutex::new(StopAfter::Off),
             random_history_db,
             random_history_index: AtomicU16::new(0),
             song_repository,
@@ -67,6 +90,36 @@ impl QueueService {
         self.random_flag.load(Ordering::Relaxed)
     }
 
+    pub fn set_repeat_mode(&self, mode: RepeatMode) {
+        *self.repeat_mode.lock().unwrap() = mode;
+        if let Ok(value) = serde_json::to_vec(&mode) {
+            _ = self.status_db.insert(REPEAT_MODE_KEY, value);
+        }
+    }
+
+    pub fn get_repeat_mode(&self) -> RepeatMode {
+        *self.repeat_mode.lock().unwrap()
+    }
+
+    /// Requests a stop, or cancels it when the same stop is already requested. The request is not persisted.
+    pub fn toggle_stop_after(&self, stop_after: StopAfter) -> StopAfter {
+        let mut current = self.stop_after.lock().unwrap();
+        *current = if *current == stop_after {
+            StopAfter::Off
+        } else {
+            stop_after
+        };
+        *current
+    }
+
+    pub fn get_stop_after(&self) -> StopAfter {
+        *self.stop_after.lock().unwrap()
+    }
+
+    pub fn clear_stop_after(&self) {
+        *self.stop_after.lock().unwrap() = StopAfter::Off;
+    }
+
     pub fn get_current_song(&self) -> Option<Song> {
         if let Some(current_key) = self.get_current_or_first_song_key() {
             if let Ok(Some(value)) = self.queue_db.get(current_key) {
@@ -78,33 +131,98 @@ impl QueueService {
         None
     }
 
-    #[allow(clippy::branches_sharing_code)]
+    /// Moves to the song that plays once the current one finished, which is the current song itself when
+    /// repeating a single song.
+    pub fn move_current_to_song_after_finished(&self) -> bool {
+        if self.get_repeat_mode() == RepeatMode::Single {
+            return self.get_current_or_first_song_key().is_some();
+        }
+        self.move_current_to_next_song()
+    }
+
+    /// Moves to the next song, starting the queue over after its last song when repeat is on.
     pub fn move_current_to_next_song(&self) -> bool {
+        self.next_song_key().is_some_and(|key| {
+            self.move_current_to_key(key);
+            true
+        })
+    }
+
+    /// Entry the queue moves to next, while it stays on the current song until it is moved with
+    /// `move_current_to_entry`.
+    pub fn get_next_entry(&self) -> Option<QueueEntry> {
+        let key = self.next_song_key()?;
+        let value = self.queue_db.get(&key).ok()??;
+        let mut song = Song::bytes_to_song(&value)?;
+        song.statistics = self.statistics_repository.find_by_id(song.file.as_str());
+        Some(QueueEntry { key, song })
+    }
+
+    /// Moves to the entry, unless it was removed from the queue meanwhile.
+    pub fn move_current_to_entry(&self, entry: QueueEntry) -> bool {
+        if !self.queue_db.contains_key(&entry.key).unwrap_or_default() {
+            return false;
+        }
+        self.move_current_to_key(entry.key);
+        true
+    }
+
+    /// Key of the song the queue moves to next, random in random play.
+    fn next_song_key(&self) -> Option<IVec> {
         let queue_len = self.queue_db.len();
+        let repeat = self.get_repeat_mode() != RepeatMode::Off;
         if queue_len < 2 {
-            return false;
+            // The only song in the queue is its own next song.
+            return if repeat {
+                self.get_current_or_first_song_key()
+            } else {
+                None
+            };
         }
         if self.get_random_next() {
             let mut rnd = rand::thread_rng();
             let rand_position = rnd.gen_range(0..queue_len - 1);
             let Some(Ok(rand_key)) = self.queue_db.iter().nth(rand_position) else {
-                return false;
+                return None;
             };
-            _ = self.status_db.insert(CURRENT_SONG_KEY, &rand_key.0);
-            let ridx = self.random_history_index.fetch_add(1, Ordering::Relaxed) + 1;
-            _ = self.random_history_db.insert(ridx.to_ne_bytes(), rand_key.0);
-            true
+            Some(rand_key.0)
         } else {
-            let Some(current_key) = self.get_current_or_first_song_key() else {
-                return false;
-            };
+            let current_key = self.get_current_or_first_song_key()?;
+            match self.queue_db.get_gt(current_key) {
+                Ok(Some(next)) => Some(next.0),
+                _ if repeat => self.queue_db.first().ok().flatten().map(|first| first.0),
+                _ => None,
+            }
+        }
+    }
 
-            let Ok(Some(next)) = self.queue_db.get_gt(current_key) else {
-                return false;
-            };
-            _ = self.status_db.insert(CURRENT_SONG_KEY, next.0);
-            true
+    fn move_current_to_key(&self, key: IVec) {
+        if self.get_random_next() {
+            let ridx = self.random_history_index.fetch_add(1, Ordering::Relaxed) + 1;
+            _ = self.random_history_db.insert(ridx.to_ne_bytes(), &key);
         }
+        _ = self.status_db.insert(CURRENT_SONG_KEY, key);
+    }
+
+    /// Songs before and after the current one in queue order, regardless of random play.
+    pub fn get_current_song_neighbours(&self) -> (Option<Song>, Option<Song>) {
+        let Some(current_key) = self.get_current_or_first_song_key() else {
+            return (None, None);
+        };
+        self.get_neighbours(&current_key)
+    }
+
+    /// Songs before and after the entry in queue order, regardless of random play.
+    pub fn get_entry_neighbours(&self, entry: &QueueEntry) -> (Option<Song>, Option<Song>) {
+        self.get_neighbours(&entry.key)
+    }
+
+    fn get_neighbours(&self, key: &IVec) -> (Option<Song>, Option<Song>) {
+        let to_song = |entry: Option<(IVec, IVec)>| entry.and_then(|e| Song::bytes_to_song(&e.1));
+        (
+            to_song(self.queue_db.get_lt(key).ok().flatten()),
+            to_song(self.queue_db.get_gt(key).ok().flatten()),
+        )
     }
 
     pub fn move_current_to_previous_song(&self) -> bool {
@@ -150,6 +268,16 @@ impl QueueService {
         })
     }
 
+    /// Records why the song failed to play on its queue entry.
+    pub fn mark_song_failed(&self, song_id: &str, reason: &str) {
+        if let Some((key, value)) = self.find_entry_by_song_id(song_id) {
+            if let Some(mut song) = Song::bytes_to_song(&value) {
+                song.play_error = Some(reason.to_string());
+                _ = self.queue_db.insert(key, song.to_json_string_bytes());
+            }
+        }
+    }
+
     pub fn add_song(&self, song: &Song) {
         let key = self.queue_db.generate_id().unwrap().to_be_bytes();
         self.queue_db
@@ -2,6 +2,7 @@
 mod queue {
     use std::sync::Arc;
 
+    use api_models::common::{RepeatMode, StopAfter};
     use api_models::settings::PlaybackQueueSetting;
 
     use crate::play_statistic_repository::PlayStatisticsRepository;
@@ -56,6 +57,22 @@ mod queue {
         assert_eq!(queue.get_current_song().unwrap().file, "assets/music.aac");
     }
 
+    #[test]
+    fn should_get_current_song_neighbours() {
+        let queue = create_queue();
+        assert_eq!(queue.get_current_song_neighbours(), (None, None));
+        queue.add_song(&create_song("mp3"));
+        queue.add_song(&create_song("flac"));
+        queue.add_song(&create_song("wav"));
+        let (prev, next) = queue.get_current_song_neighbours();
+        assert!(prev.is_none());
+        assert_eq!(next.unwrap().file, "assets/music.flac");
+        assert!(queue.move_current_to_next_song());
+        let (prev, next) = queue.get_current_song_neighbours();
+        assert_eq!(prev.unwrap().file, "assets/music.mp3");
+        assert_eq!(next.unwrap().file, "assets/music.wav");
+    }
+
     #[test]
     fn should_move_current_to_prev_by_one() {
         let queue = create_queue();
@@ -129,6 +146,17 @@ mod queue {
         assert_eq!(all_songs[0].file, "assets/music.flac");
     }
 
+    #[test]
+    fn should_mark_failed_song_with_reason() {
+        let queue = create_queue();
+        queue.add_song(&create_song("aac"));
+        queue.add_song(&create_song("flac"));
+        queue.mark_song_failed("assets/music.flac", "File not found");
+        let all_songs = queue.get_all_songs();
+        assert_eq!(all_songs[0].play_error, None);
+        assert_eq!(all_songs[1].play_error, Some("File not found".to_string()));
+    }
+
     #[test]
     fn should_return_false_move_at_the_end() {
         let queue = create_queue();
@@ -247,6 +275,81 @@ mod queue {
         assert!(!queue.move_current_to_previous_song());
     }
 
+    #[test]
+    fn should_start_queue_over_when_repeating_queue() {
+        let queue = create_queue();
+        queue.add_song(&create_song("mp3"));
+        queue.add_song(&create_song("flac"));
+        assert!(queue.move_current_to_next_song());
+        assert!(!queue.move_current_to_next_song());
+
+        queue.set_repeat_mode(RepeatMode::Queue);
+        assert!(queue.move_current_to_song_after_finished());
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.mp3");
+    }
+
+    #[test]
+    fn should_move_to_next_entry_only_when_asked() {
+        let queue = create_queue();
+        queue.add_song(&create_song("mp3"));
+        queue.add_song(&create_song("flac"));
+        queue.add_song(&create_song("wav"));
+
+        let next = queue.get_next_entry().unwrap();
+        assert_eq!(next.song.file, "assets/music.flac");
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.mp3");
+        let (prev, after) = queue.get_entry_neighbours(&next);
+        assert_eq!(prev.unwrap().file, "assets/music.mp3");
+        assert_eq!(after.unwrap().file, "assets/music.wav");
+        assert!(queue.move_current_to_entry(next));
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.flac");
+
+        let removed = queue.get_next_entry().unwrap();
+        queue.remove_song("assets/music.wav");
+        assert!(!queue.move_current_to_entry(removed));
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.flac");
+        assert!(queue.get_next_entry().is_none());
+
+        queue.set_repeat_mode(RepeatMode::Queue);
+        assert_eq!(queue.get_next_entry().unwrap().song.file, "assets/music.mp3");
+    }
+
+    #[test]
+    fn should_repeat_current_song_only_after_it_finished() {
+        let queue = create_queue();
+        queue.add_song(&create_song("mp3"));
+        queue.add_song(&create_song("flac"));
+        queue.set_repeat_mode(RepeatMode::Single);
+
+        assert!(queue.move_current_to_song_after_finished());
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.mp3");
+        assert!(queue.move_current_to_next_song());
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.flac");
+        assert!(queue.move_current_to_next_song());
+        assert_eq!(queue.get_current_song().unwrap().file, "assets/music.mp3");
+    }
+
+    #[test]
+    fn should_persist_repeat_mode_but_not_stop_request() {
+        let ctx = Context::default();
+        let queue = create_queue_with_ctx(&ctx);
+        queue.set_repeat_mode(RepeatMode::Single);
+        assert_eq!(queue.toggle_stop_after(StopAfter::CurrentSong), StopAfter::CurrentSong);
+        drop(queue);
+
+        let queue = create_queue_with_ctx(&ctx);
+        assert_eq!(queue.get_repeat_mode(), RepeatMode::Single);
+        assert_eq!(queue.get_stop_after(), StopAfter::Off);
+    }
+
+    #[test]
+    fn should_cancel_stop_request_when_toggled_again() {
+        let queue = create_queue();
+        assert_eq!(queue.toggle_stop_after(StopAfter::CurrentAlbum), StopAfter::CurrentAlbum);
+        assert_eq!(queue.toggle_stop_after(StopAfter::CurrentSong), StopAfter::CurrentSong);
+        assert_eq!(queue.toggle_stop_after(StopAfter::CurrentSong), StopAfter::Off);
+    }
+
     #[test]
     fn should_clear_queue() {
         let ctx = Context::default();
@@ -369,6 +472,18 @@ mod metadata {
         assert_eq!(song.unwrap().file, "aa/music.m4a");
     }
 
+    #[test]
+    fn should_not_scan_ignored_unplayable_song_again() {
+        let ctx = TestContext::new();
+        ctx.metadata_service.scan_music_dir(true, &ctx.sender);
+        let song = ctx.song_repository.find_by_id("aa/music.m4a").unwrap();
+        ctx.metadata_service.ignore_unplayable_song(&song, "Corrupt frame");
+        assert!(ctx.song_repository.find_by_id("aa/music.m4a").is_none());
+        ctx.metadata_service.scan_music_dir(false, &ctx.sender);
+        assert!(ctx.song_repository.find_by_id("aa/music.m4a").is_none());
+        assert_eq!(ctx.song_repository.get_all_iterator().count(), 5);
+    }
+
     #[test]
     fn test_like_media_item() {
         let ctx = TestContext::new();
@@ -411,6 +526,175 @@ mod metadata {
     }
 }
 
+#[cfg(test)]
+mod cue {
+    use std::{fs, process::Command, time::Duration};
+
+    use api_models::player::Song;
+
+    use crate::cue::{parse, track_songs};
+    use crate::test::test_shared::TestContext;
+
+    const SHEET: &str = r#"REM GENRE Jazz
+REM DATE 1959
+PERFORMER "Album Artist"
+TITLE "Album"
+FILE "image.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "First"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second"
+    PERFORMER "Guest"
+    INDEX 00 00:04:00
+    INDEX 01 00:05:30
+"#;
+
+    #[test]
+    fn should_parse_cue_sheet() {
+        let sheet = parse(SHEET);
+        assert_eq!(sheet.title.as_deref(), Some("Album"));
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.date.as_deref(), Some("1959"));
+        assert_eq!(sheet.files, ["image.flac"]);
+        assert_eq!(sheet.tracks.len(), 2);
+        assert_eq!(sheet.tracks[1].title.as_deref(), Some("Second"));
+        assert_eq!(sheet.tracks[1].performer.as_deref(), Some("Guest"));
+        assert_eq!(sheet.tracks[1].start, Some(Duration::from_millis(5400)));
+    }
+
+    #[test]
+    fn should_create_track_songs_spanning_the_image() {
+        let image = Song {
+            file: "dir/image.flac".to_string(),
+            time: Some(Duration::from_secs(70)),
+            ..Default::default()
+        };
+        let songs = track_songs(&image, &parse(SHEET));
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs[0].file, "dir/image.flac#01");
+        assert_eq!(songs[0].artist.as_deref(), Some("Album Artist"));
+        assert_eq!(songs[0].time, Some(Duration::from_millis(5400)));
+        assert_eq!(songs[1].artist.as_deref(), Some("Guest"));
+        assert_eq!(songs[1].album_artist.as_deref(), Some("Album Artist"));
+        assert_eq!(songs[1].track.as_deref(), Some("2"));
+        let cue_track = songs[1].cue_track.as_ref().unwrap();
+        assert_eq!(cue_track.image_file, "dir/image.flac");
+        assert_eq!(cue_track.end, None);
+        assert_eq!(songs[1].source_file(), "dir/image.flac");
+    }
+
+    #[test]
+    fn should_scan_image_with_cue_sheet_as_tracks() {
+        let mut context = TestContext::new();
+        fs::create_dir_all(&context.db_dir).expect("failed to create dir");
+        context.music_dir.clone_from(&context.db_dir);
+        context
+            .metadata_service
+            .settings
+            .music_directory
+            .clone_from(&context.db_dir);
+        Command::new("cp")
+            .arg("-r")
+            .arg("assets")
+            .arg(&context.music_dir)
+            .spawn()
+            .expect("failed to execute process")
+            .wait()
+            .expect("failed to wait");
+        fs::write(format!("{}/assets/music.cue", &context.music_dir), SHEET).expect("Failed to write file");
+
+        context.metadata_service.scan_music_dir(true, &context.sender);
+        assert_eq!(context.song_repository.get_all_iterator().count(), 7);
+        assert!(context.song_repository.find_by_id("assets/music.wav").is_none());
+        let second = context.song_repository.find_by_id("assets/music.wav#02").unwrap();
+        assert_eq!(second.title.as_deref(), Some("Second"));
+
+        // The tracks are kept by the incremental scan while the image is there.
+        context.metadata_service.scan_music_dir(false, &context.sender);
+        assert_eq!(context.song_repository.get_all_iterator().count(), 7);
+        fs::remove_file(format!("{}/assets/music.wav", &context.music_dir)).expect("Failed to delete file");
+        context.metadata_service.scan_music_dir(false, &context.sender);
+        assert_eq!(context.song_repository.get_all_iterator().count(), 5);
+    }
+}
+
+mod dsd {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use crate::dsd::{is_dsd_file, read_info, read_song, DsdContainer};
+
+    fn text_frame(id: &str, text: &str) -> Vec<u8> {
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&u32::try_from(text.len() + 1).unwrap().to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 3]);
+        frame.extend_from_slice(text.as_bytes());
+        frame
+    }
+
+    /// One second of stereo DSD64 with an ID3v2.3 tag.
+    fn create_dsf() -> Vec<u8> {
+        let block = 4096u64;
+        let samples = 2_822_400u64;
+        let blocks = (samples / 8).div_ceil(block);
+        let data_size = 12 + blocks * block * 2;
+        let mut frames = text_frame("TIT2", "Title");
+        frames.extend(text_frame("TPE1", "Artist"));
+        frames.extend(text_frame("TXXX", "replaygain_track_gain\0-3.5 dB"));
+        let mut id3 = b"ID3\x03\x00\x00".to_vec();
+        let size = u32::try_from(frames.len()).unwrap();
+        id3.extend((0..4).rev().map(|i| u8::try_from((size >> (7 * i)) & 0x7F).unwrap()));
+        id3.extend(frames);
+
+        let mut dsf = b"DSD ".to_vec();
+        dsf.extend(28u64.to_le_bytes());
+        dsf.extend((80 + data_size + id3.len() as u64).to_le_bytes());
+        dsf.extend((80 + data_size).to_le_bytes());
+        dsf.extend(b"fmt ");
+        dsf.extend(52u64.to_le_bytes());
+        for value in [1u32, 0, 2, 2, 2_822_400, 1] {
+            dsf.extend(value.to_le_bytes());
+        }
+        dsf.extend(samples.to_le_bytes());
+        dsf.extend(4096u32.to_le_bytes());
+        dsf.extend(0u32.to_le_bytes());
+        dsf.extend(b"data");
+        dsf.extend(data_size.to_le_bytes());
+        dsf.resize(dsf.len() + usize::try_from(data_size - 12).unwrap(), 0x69);
+        dsf.extend(id3);
+        dsf
+    }
+
+    #[test]
+    fn should_read_dsf_info() {
+        let info = read_info(&mut Cursor::new(create_dsf())).unwrap();
+        assert_eq!(info.container, DsdContainer::Dsf);
+        assert_eq!(info.sample_rate, 2_822_400);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.data_offset, 92);
+        assert_eq!(info.block_size, 4096);
+        assert_eq!(info.duration(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_read_dsf_tags() {
+        let (song, picture) = read_song(&mut Cursor::new(create_dsf())).unwrap();
+        assert_eq!(song.title.as_deref(), Some("Title"));
+        assert_eq!(song.artist.as_deref(), Some("Artist"));
+        assert_eq!(song.time, Some(Duration::from_secs(1)));
+        assert_eq!(song.tags.get("REPLAYGAIN_TRACK_GAIN").map(String::as_str), Some("-3.5 dB"));
+        assert!(picture.is_none());
+    }
+
+    #[test]
+    fn should_recognize_dsd_files() {
+        assert!(is_dsd_file("album/track.DSF"));
+        assert!(is_dsd_file("album/track.dff"));
+        assert!(!is_dsd_file("album/track.flac"));
+    }
+}
+
 #[cfg(test)]
 mod playlist {
     use std::vec;
@@ -465,6 +749,175 @@ mod playlist {
     }
 }
 
+#[cfg(test)]
+mod podcast {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use api_models::player::Song;
+    use api_models::settings::PodcastSettings;
+
+    use crate::podcast_service::{PodcastService, FEED_URL_TAG};
+
+    use super::test_shared::Context;
+
+    #[test]
+    fn should_subscribe_to_rss_feed_with_audio_episodes_newest_first() {
+        let ctx = Context::default();
+        let svc = create_podcast_service(&ctx);
+        let server = serve_feeds(vec!["podcast.rss"]);
+        let feed_url = format!("{server}/podcast.rss");
+
+        let podcast = svc.subscribe(&feed_url).unwrap();
+        assert_eq!(podcast.title, "Test Podcast");
+        assert_eq!(podcast.author.as_deref(), Some("Test Author"));
+        assert_eq!(podcast.image_url.as_deref(), Some("http://example.com/podcast.jpg"));
+        assert_eq!(svc.get_podcasts(), vec![podcast]);
+
+        let episodes = svc.get_episodes(&feed_url);
+        let titles: Vec<&str> = episodes.iter().map(|episode| episode.title.as_str()).collect();
+        assert_eq!(titles, vec!["Episode 2", "Episode 1"]);
+        // Relative enclosures are resolved against the feed url.
+        assert_eq!(episodes[0].audio_url, format!("{server}/episodes/episode2.mp3"));
+        assert_eq!(episodes[0].duration, Some(Duration::from_secs(2712)));
+        assert_eq!(episodes[1].duration, Some(Duration::from_secs(1815)));
+        assert!(episodes.iter().all(|episode| !episode.played && episode.position.is_zero()));
+
+        let song = svc.episode_song(&feed_url, "http://example.com/episode1.mp3").unwrap();
+        assert_eq!(song.file, "http://example.com/episode1.mp3");
+        assert_eq!(song.tags.get(FEED_URL_TAG), Some(&feed_url));
+        assert_eq!(svc.find_song_episode(&song), Some(episodes[1].clone()));
+        assert_eq!(song.title.as_deref(), Some("Episode 1"));
+        assert_eq!(song.album.as_deref(), Some("Test Podcast"));
+        assert_eq!(song.date.as_deref(), Some("2024-01-01"));
+
+        svc.unsubscribe(&feed_url);
+        assert!(svc.get_podcasts().is_empty());
+        assert!(svc.find_episode(&feed_url, "http://example.com/episode1.mp3").is_none());
+    }
+
+    #[test]
+    fn should_keep_played_state_and_resume_position_when_feed_is_refreshed() {
+        let ctx = Context::default();
+        let svc = create_podcast_service(&ctx);
+        let server = serve_feeds(vec!["podcast.rss", "podcast_updated.rss"]);
+        let feed_url = format!("{server}/podcast.rss");
+        svc.subscribe(&feed_url).unwrap();
+        let (episode1, episode2) = ("http://example.com/episode1.mp3", format!("{server}/episodes/episode2.mp3"));
+        svc.set_played(&feed_url, episode1, true);
+        svc.save_position(&feed_url, &episode2, Duration::from_secs(754));
+
+        assert_eq!(svc.refresh_all(), 1);
+        let episodes = svc.get_episodes(&feed_url);
+        assert_eq!(episodes.len(), 3);
+        assert_eq!(episodes[0].title, "Episode 3");
+        assert!(svc.find_episode(&feed_url, episode1).unwrap().played);
+        let (song1, song2) = (
+            svc.episode_song(&feed_url, episode1).unwrap(),
+            svc.episode_song(&feed_url, &episode2).unwrap(),
+        );
+        assert_eq!(svc.resume_position(&song2), Some(Duration::from_secs(754)));
+        assert_eq!(svc.resume_position(&song1), None);
+        let radio = Song {
+            file: "http://example.com/radio".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(svc.resume_position(&radio), None);
+
+        // Marking the episode unplayed starts it from the beginning.
+        svc.set_played(&feed_url, &episode2, false);
+        assert_eq!(svc.resume_position(&song2), None);
+
+        // The server is gone, the failed refresh keeps the episodes.
+        assert_eq!(svc.refresh_all(), 0);
+        assert!(svc.get_podcasts()[0].refresh_error.is_some());
+        assert_eq!(svc.get_episodes(&feed_url).len(), 3);
+    }
+
+    #[test]
+    fn should_keep_episodes_of_feeds_sharing_audio_apart() {
+        let ctx = Context::default();
+        let svc = create_podcast_service(&ctx);
+        let server = serve_feeds(vec!["podcast.rss", "podcast_updated.rss"]);
+        let (feed_a, feed_b) = (format!("{server}/a.rss"), format!("{server}/b.rss"));
+        svc.subscribe(&feed_a).unwrap();
+        svc.subscribe(&feed_b).unwrap();
+        let shared = "http://example.com/episode1.mp3";
+
+        svc.set_played(&feed_a, shared, true);
+        svc.save_position(&feed_b, shared, Duration::from_secs(75));
+        assert!(svc.find_episode(&feed_a, shared).unwrap().played);
+        assert!(!svc.find_episode(&feed_b, shared).unwrap().played);
+        assert_eq!(svc.find_episode(&feed_b, shared).unwrap().feed_url, feed_b);
+
+        svc.unsubscribe(&feed_a);
+        assert!(svc.get_episodes(&feed_a).is_empty());
+        assert_eq!(svc.get_episodes(&feed_b).len(), 3);
+        let song = svc.episode_song(&feed_b, shared).unwrap();
+        assert_eq!(svc.resume_position(&song), Some(Duration::from_secs(75)));
+    }
+
+    #[test]
+    fn should_read_episodes_of_atom_feed_from_enclosure_links() {
+        let ctx = Context::default();
+        let svc = create_podcast_service(&ctx);
+        let server = serve_feeds(vec!["podcast.atom"]);
+        let feed_url = format!("{server}/podcast.atom");
+
+        let podcast = svc.subscribe(&feed_url).unwrap();
+        assert_eq!(podcast.title, "Atom Podcast");
+        assert_eq!(podcast.author.as_deref(), Some("Atom Author"));
+        let episodes = svc.get_episodes(&feed_url);
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].title, "Atom Episode");
+        assert_eq!(episodes[0].audio_url, "http://example.com/atom-episode.ogg");
+    }
+
+    #[test]
+    fn should_not_subscribe_to_invalid_feed() {
+        let ctx = Context::default();
+        let svc = create_podcast_service(&ctx);
+        let server = serve_feeds(vec!["../assets/music.wav"]);
+
+        assert!(svc.subscribe(&format!("{server}/music.wav")).is_err());
+        assert!(svc.get_podcasts().is_empty());
+    }
+
+    fn create_podcast_service(ctx: &Context) -> PodcastService {
+        PodcastService::new(&PodcastSettings {
+            db_path: ctx.db_dir.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Serves the fixture feeds to the requests in order, returns the server URL.
+    fn serve_feeds(feeds: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for feed in feeds {
+                let body = std::fs::read(format!("test_feeds/{feed}")).unwrap();
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        url
+    }
+}
+
 pub mod test_shared {
     use std::{path::Path, sync::Arc};
 
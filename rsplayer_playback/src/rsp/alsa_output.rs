@@ -0,0 +1,255 @@
+//! Direct ALSA output, plays the samples at the source rate in the best format the device accepts.
+use alsa::pcm::{Access, Format, Frames, HwParams, State, PCM};
+use alsa::{Direction, ValueOr};
+use anyhow::{format_err, Result};
+use api_models::settings::RsPlayerSettings;
+use log::{debug, info};
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+
+use super::output::AudioOutput;
+
+/// Sample formats in order of preference, the widest one keeps every source bit.
+const FORMATS: [Format; 3] = [Format::S32LE, Format::S243LE, Format::S16LE];
+
+pub struct AlsaAudioOutput {
+    pcm: PCM,
+    spec: SignalSpec,
+    format: Format,
+    mmap: bool,
+    /// The device is opened without the ALSA plugin layer that may convert the samples.
+    hw_device: bool,
+    bit_perfect: bool,
+    sample_buf: SampleBuffer<i32>,
+    bytes: Vec<u8>,
+}
+
+impl AlsaAudioOutput {
+    pub fn try_open(
+        spec: SignalSpec,
+        duration: u64,
+        audio_device: &str,
+        rsp_settings: &RsPlayerSettings,
+    ) -> Result<Box<dyn AudioOutput>> {
+        let pcm = PCM::new(audio_device, Direction::Playback, false)?;
+        let format = {
+            let hwp = HwParams::any(&pcm)?;
+            hwp.set_access(if rsp_settings.alsa_mmap {
+                Access::MMapInterleaved
+            } else {
+                Access::RWInterleaved
+            })?;
+            hwp.set_channels(u32::try_from(spec.channels.count())?)?;
+            // The ALSA resampler would alter the samples, the rate has to match exactly.
+            hwp.set_rate_resample(false)?;
+            hwp.set_rate(spec.rate, ValueOr::Nearest)?;
+            let rate = hwp.get_rate()?;
+            if rate != spec.rate {
+                return Err(format_err!("{audio_device} does not support {} Hz", spec.rate));
+            }
+            let format = FORMATS
+                .into_iter()
+                .find(|format| hwp.test_format(*format).is_ok())
+                .ok_or_else(|| format_err!("{audio_device} does not support any of {FORMATS:?}"))?;
+            hwp.set_format(format)?;
+            if let Some(period_size) = rsp_settings.alsa_period_size {
+                hwp.set_period_size_near(Frames::from(period_size), ValueOr::Nearest)?;
+            }
+            if let Some(buffer_size) = rsp_settings.alsa_buffer_size {
+                hwp.set_buffer_size_near(Frames::from(buffer_size))?;
+            }
+            pcm.hw_params(&hwp)?;
+            format
+        };
+        {
+            // Start once the buffer is half full, so the first period does not underrun.
+            let hwp = pcm.hw_params_current()?;
+            let buffer_size = hwp.get_buffer_size()?;
+            let swp = pcm.sw_params_current()?;
+            swp.set_start_threshold(buffer_size / 2)?;
+            pcm.sw_params(&swp)?;
+            debug!("ALSA period size {}, buffer size {buffer_size}", hwp.get_period_size()?);
+        }
+        pcm.prepare()?;
+        info!(
+            "Opened {audio_device} at {} Hz as {format}{}",
+            spec.rate,
+            if rsp_settings.alsa_mmap { " with mmap" } else { "" }
+        );
+        Ok(Box::new(Self {
+            pcm,
+            spec,
+            format,
+            mmap: rsp_settings.alsa_mmap,
+            hw_device: audio_device.starts_with("hw:"),
+            bit_perfect: false,
+            sample_buf: SampleBuffer::new(duration, spec),
+            bytes: vec![],
+        }))
+    }
+
+    fn write_rw(&self, frame_len: usize) -> Result<()> {
+        let io = self.pcm.io_bytes();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            match io.writei(&self.bytes[offset..]) {
+                Ok(frames) => offset += frames * frame_len,
+                Err(err) => self.pcm.try_recover(err, true)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_mmap(&self, frame_len: usize) -> Result<()> {
+        let io = self.pcm.io_bytes();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let avail = match self.pcm.avail_update() {
+                Ok(avail) => usize::try_from(avail).unwrap_or_default(),
+                Err(err) => {
+                    self.pcm.try_recover(err, true)?;
+                    continue;
+                }
+            };
+            if avail == 0 {
+                // The buffer is full, the stream has to be running to make room.
+                if self.pcm.state() == State::Prepared {
+                    self.pcm.start()?;
+                }
+                if let Err(err) = self.pcm.wait(Some(1000)) {
+                    self.pcm.try_recover(err, true)?;
+                }
+                continue;
+            }
+            let remaining = &self.bytes[offset..];
+            let frames = avail.min(remaining.len() / frame_len);
+            let written = io.mmap(frames, |area| {
+                let len = area.len().min(remaining.len());
+                area[..len].copy_from_slice(&remaining[..len]);
+                len / frame_len
+            })?;
+            offset += written * frame_len;
+        }
+        Ok(())
+    }
+}
+
+impl AudioOutput for AlsaAudioOutput {
+    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+        if decoded.frames() == 0 {
+            return Ok(());
+        }
+        // Samples never lose bits when the format is at least as wide as the source samples.
+        self.bit_perfect = self.hw_device && source_bits(&decoded).is_some_and(|bits| bits <= format_bits(self.format));
+        if decoded.capacity() * self.spec.channels.count() > self.sample_buf.capacity() {
+            self.sample_buf = SampleBuffer::new(decoded.capacity() as u64, self.spec);
+        }
+        self.sample_buf.copy_interleaved_ref(decoded);
+        self.bytes.clear();
+        for sample in self.sample_buf.samples() {
+            pack_sample(*sample, self.format, &mut self.bytes);
+        }
+        // A drained stream has to be prepared again before it accepts samples.
+        if self.pcm.state() == State::Setup {
+            self.pcm.prepare()?;
+        }
+        let frame_len = format_bits(self.format) as usize / 8 * self.spec.channels.count();
+        if self.mmap {
+            self.write_mmap(frame_len)
+        } else {
+            self.write_rw(frame_len)
+        }
+    }
+
+    fn flush(&mut self) {
+        // A short song may not have reached the start threshold yet.
+        if self.pcm.state() == State::Prepared {
+            _ = self.pcm.start();
+        }
+        _ = self.pcm.drain();
+    }
+
+    fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+
+    fn is_bit_perfect(&self) -> bool {
+        self.bit_perfect
+    }
+
+    /// Devices other than `hw:` may convert the samples in the ALSA plugin layer.
+    fn integer_bits(&self) -> Option<u32> {
+        self.hw_device.then(|| format_bits(self.format))
+    }
+
+    fn pause(&mut self) {
+        if !pause(&self.pcm) {
+            self.flush();
+        }
+    }
+
+    fn resume(&mut self) {
+        resume(&self.pcm);
+    }
+}
+
+/// Pauses the stream keeping the buffered samples, returns false when the device can't pause.
+pub fn pause(pcm: &PCM) -> bool {
+    let can_pause = pcm.hw_params_current().is_ok_and(|hwp| hwp.can_pause());
+    can_pause && pcm.state() == State::Running && pcm.pause(true).is_ok()
+}
+
+pub fn resume(pcm: &PCM) {
+    if pcm.state() == State::Paused {
+        _ = pcm.pause(false);
+    }
+}
+
+/// Bits of the decoded samples, `None` for floating point samples.
+const fn source_bits(decoded: &AudioBufferRef<'_>) -> Option<u32> {
+    match decoded {
+        AudioBufferRef::U8(_) | AudioBufferRef::S8(_) => Some(8),
+        AudioBufferRef::U16(_) | AudioBufferRef::S16(_) => Some(16),
+        AudioBufferRef::U24(_) | AudioBufferRef::S24(_) => Some(24),
+        AudioBufferRef::U32(_) | AudioBufferRef::S32(_) => Some(32),
+        AudioBufferRef::F32(_) | AudioBufferRef::F64(_) => None,
+    }
+}
+
+const fn format_bits(format: Format) -> u32 {
+    match format {
+        Format::S16LE => 16,
+        Format::S243LE => 24,
+        _ => 32,
+    }
+}
+
+/// Appends the full scale sample in the device format, keeping its most significant bits.
+fn pack_sample(sample: i32, format: Format, bytes: &mut Vec<u8>) {
+    let le = sample.to_le_bytes();
+    match format_bits(format) {
+        16 => bytes.extend_from_slice(&le[2..]),
+        24 => bytes.extend_from_slice(&le[1..]),
+        _ => bytes.extend_from_slice(&le),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alsa::pcm::Format;
+
+    use super::pack_sample;
+
+    #[test]
+    fn should_pack_samples_without_losing_source_bits() {
+        let sample = 0x1234_5600;
+        let mut bytes = vec![];
+        pack_sample(sample, Format::S32LE, &mut bytes);
+        assert_eq!(bytes, [0x00, 0x56, 0x34, 0x12]);
+        bytes.clear();
+        pack_sample(sample, Format::S243LE, &mut bytes);
+        assert_eq!(bytes, [0x56, 0x34, 0x12]);
+        bytes.clear();
+        pack_sample(-0x0001_0000, Format::S16LE, &mut bytes);
+        assert_eq!(bytes, [0xFF, 0xFF]);
+    }
+}
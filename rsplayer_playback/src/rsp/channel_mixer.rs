@@ -0,0 +1,202 @@
+//! Channel processing of stereo audio: crossfeed, balance, channel swap, polarity and mono downmix.
+use api_models::settings::{ChannelSettings, Crossfeed};
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+pub struct ChannelMixer {
+    settings: ChannelSettings,
+    crossfeed: Option<CrossfeedFilter>,
+}
+
+impl ChannelMixer {
+    pub const fn new(settings: ChannelSettings) -> Self {
+        Self {
+            settings,
+            crossfeed: None,
+        }
+    }
+
+    /// The crossfeed starts over with the new settings.
+    pub const fn set_settings(&mut self, settings: ChannelSettings) {
+        self.settings = settings;
+        self.crossfeed = None;
+    }
+
+    /// Whether the settings alter the audio.
+    pub const fn is_active(&self) -> bool {
+        let settings = &self.settings;
+        settings.crossfeed.is_some()
+            || settings.balance != 0
+            || settings.swap_channels
+            || settings.invert_polarity
+            || settings.mono
+    }
+
+    /// Everything but the polarity applies to stereo audio only.
+    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        if self.settings.invert_polarity {
+            for ch in 0..buffer.spec().channels.count() {
+                for sample in buffer.chan_mut(ch) {
+                    *sample = -*sample;
+                }
+            }
+        }
+        if buffer.spec().channels.count() != 2 {
+            return;
+        }
+        let rate = buffer.spec().rate;
+        let (left, right) = buffer.chan_pair_mut(0, 1);
+        if self.settings.mono {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                let mid = (*l + *r) * 0.5;
+                (*l, *r) = (mid, mid);
+            }
+        }
+        if self.settings.swap_channels {
+            left.swap_with_slice(right);
+        }
+        if let Some(crossfeed) = self.settings.crossfeed.as_ref() {
+            let filter = match self.crossfeed.take() {
+                Some(filter) if filter.rate == rate => self.crossfeed.insert(filter),
+                _ => self.crossfeed.insert(CrossfeedFilter::new(crossfeed, rate)),
+            };
+            filter.process(left, right);
+        }
+        if self.settings.balance != 0 {
+            let balance = f32::from(self.settings.balance) / 100.0;
+            let (left_gain, right_gain) = ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0));
+            for sample in left.iter_mut() {
+                *sample *= left_gain;
+            }
+            for sample in right.iter_mut() {
+                *sample *= right_gain;
+            }
+        }
+    }
+}
+
+/// Crossfeed of the Bauer stereophonic-to-binaural DSP: each channel gets the low pass filtered
+/// other channel, its own signal passes a high shelf that keeps the overall level.
+struct CrossfeedFilter {
+    rate: u32,
+    a0_lo: f64,
+    b1_lo: f64,
+    a0_hi: f64,
+    a1_hi: f64,
+    b1_hi: f64,
+    gain: f64,
+    /// Filter states of the left and the right channel.
+    lo: [f64; 2],
+    hi: [f64; 2],
+    last_input: [f64; 2],
+}
+
+impl CrossfeedFilter {
+    fn new(crossfeed: &Crossfeed, rate: u32) -> Self {
+        let feed_db = f64::from(crossfeed.feed_db);
+        let cutoff = f64::from(crossfeed.cutoff_hz);
+        let rate_f = f64::from(rate);
+        // Levels in dB of the low pass filtered other channel and of the high shelf of the channel.
+        let level_lo = (-5.0 / 6.0f64).mul_add(feed_db, -3.0);
+        let level_hi = feed_db / 6.0 - 3.0;
+        let g_lo = 10f64.powf(level_lo / 20.0);
+        let g_hi = 1.0 - 10f64.powf(level_hi / 20.0);
+        let cutoff_hi = cutoff * (20.0f64.mul_add(-g_hi.log10(), level_lo) / 12.0).exp2();
+        let x_lo = (-2.0 * std::f64::consts::PI * cutoff / rate_f).exp();
+        let x_hi = (-2.0 * std::f64::consts::PI * cutoff_hi / rate_f).exp();
+        Self {
+            rate,
+            a0_lo: g_lo * (1.0 - x_lo),
+            b1_lo: x_lo,
+            a0_hi: g_hi.mul_add(-(1.0 - x_hi), 1.0),
+            a1_hi: -x_hi,
+            b1_hi: x_hi,
+            gain: 1.0 / (1.0 - g_hi + g_lo),
+            lo: [0.0; 2],
+            hi: [0.0; 2],
+            last_input: [0.0; 2],
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let input = [f64::from(*l), f64::from(*r)];
+            for (ch, &sample) in input.iter().enumerate() {
+                self.lo[ch] = self.a0_lo.mul_add(sample, self.b1_lo * self.lo[ch]);
+                self.hi[ch] = self.a0_hi.mul_add(
+                    sample,
+                    self.a1_hi.mul_add(self.last_input[ch], self.b1_hi * self.hi[ch]),
+                );
+            }
+            self.last_input = input;
+            *l = ((self.hi[0] + self.lo[1]) * self.gain) as f32;
+            *r = ((self.hi[1] + self.lo[0]) * self.gain) as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use api_models::settings::{ChannelSettings, Crossfeed};
+    use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+
+    use super::ChannelMixer;
+
+    fn process(settings: ChannelSettings, left: f32, right: f32) -> (f32, f32) {
+        let mut buffer = AudioBuffer::<f32>::new(
+            48000,
+            SignalSpec::new(48000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+        );
+        buffer.render_reserved(None);
+        buffer.chan_mut(0).fill(left);
+        buffer.chan_mut(1).fill(right);
+        let mut mixer = ChannelMixer::new(settings);
+        assert!(mixer.is_active());
+        mixer.process(&mut buffer);
+        (buffer.chan(0)[47999], buffer.chan(1)[47999])
+    }
+
+    #[test]
+    fn should_keep_level_of_centered_signal_with_crossfeed() {
+        let settings = ChannelSettings {
+            crossfeed: Some(Crossfeed::default()),
+            ..Default::default()
+        };
+        let (left, right) = process(settings, 0.5, 0.5);
+        assert!((left - 0.5).abs() < 1e-3 && (right - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn should_feed_low_frequencies_to_other_channel() {
+        let settings = ChannelSettings {
+            crossfeed: Some(Crossfeed {
+                cutoff_hz: 700,
+                feed_db: 6.0,
+            }),
+            ..Default::default()
+        };
+        let (left, right) = process(settings, 1.0, 0.0);
+        // The level difference of the channels is the feed level at low frequencies.
+        assert!(20.0f32.mul_add((left / right).log10(), -6.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn should_swap_downmix_invert_and_balance_channels() {
+        let swap = ChannelSettings {
+            swap_channels: true,
+            ..Default::default()
+        };
+        assert_eq!(process(swap, 0.25, 0.75), (0.75, 0.25));
+        let mono = ChannelSettings {
+            mono: true,
+            invert_polarity: true,
+            ..Default::default()
+        };
+        assert_eq!(process(mono, 0.25, 0.75), (-0.5, -0.5));
+        let balance = ChannelSettings {
+            balance: -50,
+            ..Default::default()
+        };
+        assert_eq!(process(balance, 0.5, 0.5), (0.5, 0.25));
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::sync::Arc;
+
+use anyhow::{format_err, Result};
+use api_models::settings::ConvolutionSettings;
+use log::{info, warn};
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use symphonia::core::audio::{AudioBuffer, Signal, SignalSpec};
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::{get_codecs, get_probe};
+
+use super::symphonia::to_f32_buffer;
+
+/// Number of frames in one partition of the impulse response. It is also the latency of the
+/// convolution.
+const BLOCK_SIZE: usize = 1024;
+
+/// FIR filter for room correction, using the impulse response that matches the sample rate of
+/// the playing song.
+pub struct Convolver {
+    settings: ConvolutionSettings,
+    rate: u32,
+    channels: usize,
+    engines: Vec<PartitionedConvolution>,
+}
+
+impl Convolver {
+    pub const fn new(settings: ConvolutionSettings) -> Self {
+        Self {
+            settings,
+            rate: 0,
+            channels: 0,
+            engines: vec![],
+        }
+    }
+
+    /// Loads the impulse response for the signal spec if it is not loaded yet.
+    /// Returns false if there is no impulse response for the sample rate.
+    pub fn prepare(&mut self, spec: SignalSpec) -> bool {
+        let channels = spec.channels.count();
+        if spec.rate != self.rate || channels != self.channels {
+            self.rate = spec.rate;
+            self.channels = channels;
+            self.engines = self
+                .settings
+                .impulse_files
+                .get(&spec.rate)
+                .map(|path| match load_impulse_response(path, spec.rate) {
+                    Ok(ir) => {
+                        info!("Loaded impulse response {path} for {} Hz", spec.rate);
+                        (0..channels)
+                            .map(|ch| PartitionedConvolution::new(&ir[ch % ir.len()]))
+                            .collect()
+                    }
+                    Err(err) => {
+                        warn!("Failed to load impulse response {path}: {err}");
+                        vec![]
+                    }
+                })
+                .unwrap_or_default();
+        }
+        !self.engines.is_empty()
+    }
+
+    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (ch, engine) in self.engines.iter_mut().enumerate() {
+            engine.process(buffer.chan_mut(ch));
+        }
+    }
+}
+
+/// Reads all channels of the WAV file.
+fn load_impulse_response(path: &str, rate: u32) -> Result<Vec<Vec<f32>>> {
+    let mut hint = Hint::new();
+    hint.with_extension("wav");
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), MediaSourceStreamOptions::default());
+    let probed = get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut reader = probed.format;
+    let track = reader
+        .default_track()
+        .ok_or_else(|| format_err!("No track in impulse response file"))?;
+    if track.codec_params.sample_rate != Some(rate) {
+        return Err(format_err!(
+            "Impulse response sample rate {:?} does not match {rate}",
+            track.codec_params.sample_rate
+        ));
+    }
+    let track_id = track.id;
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    let mut channels: Vec<Vec<f32>> = vec![];
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let buffer = to_f32_buffer(&decoder.decode(&packet)?);
+        channels.resize_with(buffer.spec().channels.count(), Vec::new);
+        for (ch, samples) in channels.iter_mut().enumerate() {
+            samples.extend_from_slice(buffer.chan(ch));
+        }
+    }
+    if channels.iter().all(Vec::is_empty) {
+        return Err(format_err!("Impulse response is empty"));
+    }
+    Ok(channels)
+}
+
+/// Uniformly partitioned overlap-save convolution of one channel.
+struct PartitionedConvolution {
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    /// Spectra of the impulse response partitions, scaled for the inverse FFT.
+    partitions: Vec<Vec<Complex<f32>>>,
+    /// Spectra of the latest input blocks, newest at `fdl_pos`.
+    fdl: Vec<Vec<Complex<f32>>>,
+    fdl_pos: usize,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    output: VecDeque<f32>,
+    time_buf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+}
+
+impl PartitionedConvolution {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(impulse_response: &[f32]) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(2 * BLOCK_SIZE);
+        let ifft = planner.plan_fft_inverse(2 * BLOCK_SIZE);
+        let scale = 1.0 / (2 * BLOCK_SIZE) as f32;
+        let partitions = impulse_response
+            .chunks(BLOCK_SIZE)
+            .map(|chunk| {
+                let mut time_buf = fft.make_input_vec();
+                time_buf[..chunk.len()].copy_from_slice(chunk);
+                let mut spectrum = fft.make_output_vec();
+                _ = fft.process(&mut time_buf, &mut spectrum);
+                for bin in &mut spectrum {
+                    *bin *= scale;
+                }
+                spectrum
+            })
+            .collect::<Vec<_>>();
+        let fdl = vec![fft.make_output_vec(); partitions.len()];
+        Self {
+            time_buf: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            window: vec![0.0; 2 * BLOCK_SIZE],
+            input: Vec::with_capacity(BLOCK_SIZE),
+            // One block of silence keeps the number of output samples equal to the input.
+            output: VecDeque::from(vec![0.0; BLOCK_SIZE]),
+            fft,
+            ifft,
+            partitions,
+            fdl,
+            fdl_pos: 0,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            self.input.push(*sample);
+            if self.input.len() == BLOCK_SIZE {
+                self.process_block();
+            }
+            *sample = self.output.pop_front().unwrap_or_default();
+        }
+    }
+
+    fn process_block(&mut self) {
+        self.window.copy_within(BLOCK_SIZE.., 0);
+        self.window[BLOCK_SIZE..].copy_from_slice(&self.input);
+        self.input.clear();
+
+        let partitions = self.partitions.len();
+        self.fdl_pos = (self.fdl_pos + 1) % partitions;
+        self.time_buf.copy_from_slice(&self.window);
+        _ = self.fft.process(&mut self.time_buf, &mut self.fdl[self.fdl_pos]);
+
+        self.spectrum.fill(Complex::default());
+        for (delay, partition) in self.partitions.iter().enumerate() {
+            let input = &self.fdl[(self.fdl_pos + partitions - delay) % partitions];
+            for ((acc, x), h) in self.spectrum.iter_mut().zip(input).zip(partition) {
+                *acc += x * h;
+            }
+        }
+        _ = self.ifft.process(&mut self.spectrum, &mut self.time_buf);
+        // The first half is aliased by the circular convolution, only the second half is valid.
+        self.output.extend(&self.time_buf[BLOCK_SIZE..]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PartitionedConvolution, BLOCK_SIZE};
+
+    fn convolve(impulse_response: &[f32], len: usize) -> Vec<f32> {
+        let mut engine = PartitionedConvolution::new(impulse_response);
+        let mut samples: Vec<f32> = (0..len).map(input).collect();
+        // Uneven chunks, like decoded packets.
+        for chunk in samples.chunks_mut(700) {
+            engine.process(chunk);
+        }
+        samples
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn input(i: usize) -> f32 {
+        ((i % 100) as f32 / 100.0) - 0.5
+    }
+
+    #[test]
+    fn should_delay_signal_by_one_block_with_unit_impulse() {
+        let output = convolve(&[1.0], 5 * BLOCK_SIZE);
+        assert!(output[..BLOCK_SIZE].iter().all(|s| s.abs() < 1e-6));
+        for (i, sample) in output.iter().enumerate().skip(BLOCK_SIZE) {
+            assert!((sample - input(i - BLOCK_SIZE)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn should_convolve_impulse_longer_than_block() {
+        let mut impulse_response = vec![0.0; 3 * BLOCK_SIZE];
+        impulse_response[0] = 0.5;
+        impulse_response[2 * BLOCK_SIZE + 10] = 0.25;
+        let output = convolve(&impulse_response, 8 * BLOCK_SIZE);
+        for (i, sample) in output.iter().enumerate().skip(4 * BLOCK_SIZE) {
+            let n = i - BLOCK_SIZE;
+            let expected = 0.5f32.mul_add(input(n), 0.25 * input(n - 2 * BLOCK_SIZE - 10));
+            assert!((sample - expected).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::f32::consts::FRAC_PI_2;
+
+use api_models::common::CrossfadeCurve;
+use api_models::player::Song;
+use api_models::settings::RsPlayerSettings;
+use api_models::state::PlayerInfo;
+use log::{debug, warn};
+use symphonia::core::audio::{AudioBuffer, Signal, SignalSpec};
+
+use super::radio::StreamControl;
+use super::ram_loader::RamLoader;
+use super::replay_gain;
+use super::symphonia::{to_f32_buffer, TrackDecoder};
+
+/// Next song that is started while the current one is fading out.
+pub struct CrossfadeTrack {
+    track: TrackDecoder,
+    fifo: Vec<Vec<f32>>,
+    spec: Option<SignalSpec>,
+    eof: bool,
+    fade_frames: u64,
+}
+
+/// Number of frames the crossfade lasts for the given sample rate, 0 if crossfade is disabled.
+pub fn frames(rsp_settings: &RsPlayerSettings, rate: Option<u32>) -> u64 {
+    rate.map_or(0, |rate| rsp_settings.crossfade_ms as u64 * u64::from(rate) / 1000)
+}
+
+/// Songs from the same album played in order are not crossfaded, as their transitions are part
+/// of the recording.
+pub fn is_same_album_sequence(current: &Song, next: &Song) -> bool {
+    if !current.is_same_album(next) || current.disc != next.disc {
+        return false;
+    }
+    match (track_number(current), track_number(next)) {
+        (Some(current_no), Some(next_no)) => next_no == current_no + 1,
+        _ => true,
+    }
+}
+
+fn track_number(song: &Song) -> Option<u32> {
+    song.track
+        .as_ref()
+        .and_then(|track| track.split('/').next())
+        .and_then(|no| no.trim().parse().ok())
+}
+
+/// Fade out and fade in gains at the given crossfade progress (0.0 - 1.0).
+pub fn gains(curve: CrossfadeCurve, progress: f32) -> (f32, f32) {
+    let progress = progress.clamp(0.0, 1.0);
+    match curve {
+        CrossfadeCurve::Linear => (1.0 - progress, progress),
+        CrossfadeCurve::EqualPower => ((progress * FRAC_PI_2).cos(), (progress * FRAC_PI_2).sin()),
+    }
+}
+
+impl CrossfadeTrack {
+    /// Opens the next song for crossfading. Returns `None` if it can't be mixed with the current
+    /// one, in which case the next song is played after the current one as usual.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start(
+        next: &Song,
+        replay_gain_db: Option<f32>,
+        current_info: &PlayerInfo,
+        music_dir: &str,
+        rsp_settings: &RsPlayerSettings,
+        fade_frames: u64,
+        control: &StreamControl,
+        ram_loader: &RamLoader,
+    ) -> Option<Self> {
+        let mut track = match TrackDecoder::open(next, music_dir, rsp_settings, control, ram_loader) {
+            Ok(track) => track,
+            Err(err) => {
+                warn!("Crossfade disabled, failed to open {}: {err}", next.file);
+                return None;
+            }
+        };
+        let next_info = track.player_info();
+        if next_info.audio_format_rate != current_info.audio_format_rate
+            || next_info.audio_format_channels != current_info.audio_format_channels
+        {
+            debug!("Crossfade disabled, {} has different audio format", next.file);
+            return None;
+        }
+        track.set_replay_gain(replay_gain_db);
+        debug!("Crossfade to {} started", next.file);
+        Some(Self {
+            track,
+            fifo: Vec::new(),
+            spec: None,
+            eof: false,
+            fade_frames,
+        })
+    }
+
+    /// Decodes the incoming song until at least `frames` frames are buffered or it ends.
+    fn fill(&mut self, frames: usize) {
+        while !self.eof && self.fifo.first().map_or(0, Vec::len) < frames {
+            let packet = match self.track.next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => {
+                    self.eof = true;
+                    break;
+                }
+                Err(err) => {
+                    warn!("Crossfade decoding failed: {err}");
+                    self.eof = true;
+                    break;
+                }
+            };
+            let Ok(Some(decoded)) = self.track.decode(&packet) else {
+                continue;
+            };
+            let spec = *decoded.spec();
+            if self.spec.is_some_and(|s| s != spec) {
+                self.eof = true;
+                break;
+            }
+            self.spec = Some(spec);
+            let mut buffer = to_f32_buffer(&decoded);
+            if let Some(factor) = self.track.gain_factor() {
+                replay_gain::apply(&mut buffer, factor);
+            }
+            self.fifo.resize_with(spec.channels.count(), Vec::new);
+            for (ch, samples) in self.fifo.iter_mut().enumerate() {
+                samples.extend_from_slice(buffer.chan(ch));
+            }
+        }
+    }
+
+    /// Mixes the beginning of the incoming song into the fading out buffer. `remaining_frames` is
+    /// the number of frames the current song has left at the start of `mixed`.
+    /// The buffer is left as is if the songs can't be mixed.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mix(&mut self, mixed: &mut AudioBuffer<f32>, remaining_frames: u64, curve: CrossfadeCurve) {
+        let frames = mixed.frames();
+        self.fill(frames);
+        if self.spec.is_some_and(|spec| spec != *mixed.spec()) {
+            return;
+        }
+        let fade_frames = self.fade_frames as f32;
+        for (ch, incoming) in self.fifo.iter_mut().enumerate() {
+            let out = mixed.chan_mut(ch);
+            for (i, sample) in out.iter_mut().enumerate() {
+                let remaining = remaining_frames.saturating_sub(i as u64) as f32;
+                let (fade_out, fade_in) = gains(curve, 1.0 - remaining / fade_frames);
+                *sample = (*sample).mul_add(fade_out, incoming.get(i).copied().unwrap_or_default() * fade_in);
+            }
+            incoming.drain(..frames.min(incoming.len()));
+        }
+    }
+
+    /// Decoder of the incoming song and the already decoded samples that were not mixed yet.
+    pub fn into_parts(self) -> (TrackDecoder, Option<AudioBuffer<f32>>) {
+        let pending_frames = self.fifo.first().map_or(0, Vec::len);
+        let pending = self.spec.filter(|_| pending_frames > 0).map(|spec| {
+            let mut buffer = AudioBuffer::<f32>::new(pending_frames as u64, spec);
+            buffer.render_reserved(Some(pending_frames));
+            for (ch, samples) in self.fifo.iter().enumerate() {
+                buffer.chan_mut(ch).copy_from_slice(samples);
+            }
+            buffer
+        });
+        (self.track, pending)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use api_models::common::CrossfadeCurve;
+    use api_models::player::Song;
+
+    use super::{gains, is_same_album_sequence};
+
+    fn song(album: &str, track: &str) -> Song {
+        Song {
+            album: Some(album.to_string()),
+            track: Some(track.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_detect_same_album_sequence() {
+        assert!(is_same_album_sequence(&song("a", "1"), &song("a", "2/10")));
+        assert!(!is_same_album_sequence(&song("a", "1"), &song("a", "3")));
+        assert!(!is_same_album_sequence(&song("a", "1"), &song("b", "2")));
+        assert!(!is_same_album_sequence(&Song::default(), &Song::default()));
+    }
+
+    #[test]
+    fn should_calculate_curve_gains() {
+        assert_eq!(gains(CrossfadeCurve::Linear, 0.25), (0.75, 0.25));
+        let (fade_out, fade_in) = gains(CrossfadeCurve::EqualPower, 0.5);
+        assert!((fade_out.mul_add(fade_out, fade_in * fade_in) - 1.0).abs() < 1e-6);
+        assert!((gains(CrossfadeCurve::EqualPower, 1.0).1 - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,557 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use alsa::pcm::{Access, Format, HwParams, State, PCM};
+use alsa::{Direction, ValueOr};
+use anyhow::Result;
+use api_models::common::DsdOutput;
+use api_models::settings::RsPlayerSettings;
+use api_models::state::{PlayerInfo, SongProgress, StateChangeEvent};
+use log::{debug, info, warn};
+use rsplayer_metadata::dsd::{self, DsdContainer, DsdInfo};
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal, SignalSpec};
+use tokio::sync::broadcast::Sender;
+
+use super::alsa_output;
+use super::dsp::DspChain;
+use super::http_stream::HttpStream;
+use super::level_meter::LevelMeter;
+use super::output::{self, try_open_dop, AudioOutput, OutputError};
+use super::replay_gain;
+use super::symphonia::{
+    park_while_paused, seek_position, send_seek_error, take_seek_request, write_buffer, PlaybackResult, SeekRequest,
+};
+
+/// Bytes of one channel read at once from DSDIFF files, DSF files are read by blocks.
+const CHUNK_SIZE: usize = 4096;
+/// DSD idle pattern, silence for DSD DACs.
+const DSD_SILENCE: u8 = 0x69;
+/// Markers of DSD over PCM samples, alternating from frame to frame.
+const DOP_MARKERS: [u8; 2] = [0x05, 0xFA];
+/// Cutoff of the lowpass filter used for the conversion to PCM, DSD noise rises above it.
+const PCM_CUTOFF_HZ: f64 = 30_000.0;
+/// Length of the conversion filter in bytes per decimated byte.
+const FILTER_BYTES_PER_DECIMATION: usize = 32;
+
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+pub fn play_dsd_file(
+    path_str: &str,
+    stop_signal: &Arc<AtomicBool>,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
+    audio_device: &str,
+    rsp_settings: &RsPlayerSettings,
+    music_dir: &str,
+    changes_tx: &Sender<StateChangeEvent>,
+    audio_output: &mut Option<Box<dyn AudioOutput>>,
+    replay_gain_db: Option<f32>,
+    dsp: &mut DspChain,
+    level_meter: &mut LevelMeter,
+    http_stream: &HttpStream,
+) -> Result<PlaybackResult> {
+    let mut reader = DsdReader::open(&Path::new(music_dir).join(path_str))?;
+    let info = reader.info.clone();
+    let mut sink = DsdSink::open(rsp_settings, &info, audio_device, audio_output);
+    let gain_db = if matches!(sink, DsdSink::Pcm(_)) { replay_gain_db } else { None };
+    let player_info = PlayerInfo {
+        audio_format_rate: Some(info.sample_rate),
+        audio_format_bit: Some(1),
+        audio_format_channels: Some(info.channels),
+        codec: Some(format!("DSD{} ({})", info.sample_rate / 44100, sink.name())),
+        replay_gain_db: gain_db,
+        native_dsd: matches!(sink, DsdSink::Native(_)),
+        bit_perfect: Some(matches!(sink, DsdSink::Native(_))),
+    };
+    changes_tx
+        .send(StateChangeEvent::PlayerInfoEvent(player_info))
+        .expect("msg send failed");
+    let gain_factor = gain_db.map(replay_gain::db_to_factor);
+    // Native and DoP output is converted to PCM only for the meters and the HTTP stream, while
+    // they are used.
+    let mut monitor = (!matches!(sink, DsdSink::Pcm(_))).then(|| DsdToPcm::new(&info));
+    let total_time = info.duration();
+    let mut last_current_time = u64::MAX;
+    let result = loop {
+        if stop_signal.load(Ordering::Relaxed) {
+            debug!("Exit from play thread due to running flag change");
+            break Ok(PlaybackResult::PlaybackStopped);
+        }
+        if pause_signal.load(Ordering::Relaxed) {
+            park_while_paused(pause_signal, stop_signal, changes_tx, &mut |paused| {
+                sink.set_paused(audio_output, paused);
+            });
+            continue;
+        }
+        if let Some(target) = take_seek_request(seek_request) {
+            match seek_position(target, reader.current_time(), Some(total_time)) {
+                Ok(position) => {
+                    reader.seek(position)?;
+                    changes_tx
+                        .send(StateChangeEvent::SeekCompletedEvent(SongProgress {
+                            total_time: Duration::from_secs(total_time.as_secs()),
+                            current_time: reader.current_time(),
+                        }))
+                        .ok();
+                }
+                Err(err) => send_seek_error(changes_tx, &err),
+            }
+        }
+        let current_time = reader.current_time().as_secs();
+        let Some(chunk) = reader.read()? else {
+            break Ok(PlaybackResult::SongFinished);
+        };
+        if current_time != last_current_time {
+            last_current_time = current_time;
+            changes_tx
+                .send(StateChangeEvent::SongTimeEvent(SongProgress {
+                    total_time: Duration::from_secs(total_time.as_secs()),
+                    current_time: Duration::from_secs(current_time),
+                }))
+                .expect("msg send failed");
+        }
+        if let Some(monitor) = monitor
+            .as_mut()
+            .filter(|_| level_meter.is_active() || http_stream.has_listeners())
+        {
+            let buffer = monitor.convert(&chunk);
+            level_meter.measure(&buffer.as_audio_buffer_ref());
+            http_stream.write(&buffer.as_audio_buffer_ref());
+        }
+        let write_result = match &mut sink {
+            DsdSink::Native(output) => output
+                .write(&chunk)
+                .map_err(|err| OutputError(format!("Failed to write DSD to {audio_device}: {err}")).into()),
+            DsdSink::Dop { output, packer } => output
+                .write(packer.pack(&chunk).as_audio_buffer_ref())
+                .map_err(|err| OutputError(format!("Failed to write DoP to {audio_device}: {err}")).into()),
+            DsdSink::Pcm(converter) => {
+                let mut buffer = converter.convert(&chunk);
+                if let Some(factor) = gain_factor {
+                    replay_gain::apply(&mut buffer, factor);
+                }
+                if dsp.is_active(buffer.spec()) {
+                    dsp.process(&mut buffer);
+                }
+                level_meter.measure(&buffer.as_audio_buffer_ref());
+                http_stream.write(&buffer.as_audio_buffer_ref());
+                write_buffer(buffer.as_audio_buffer_ref(), audio_output, audio_device, rsp_settings)
+            }
+        };
+        if let Err(err) = write_result {
+            break Err(err);
+        }
+    };
+    // DSD outputs hold the device exclusively, so they are closed at the end of every song.
+    // Converted PCM stays in the shared output like any other song.
+    if result.as_ref().is_ok_and(|r| *r == PlaybackResult::SongFinished) {
+        match &mut sink {
+            DsdSink::Native(output) => output.drain(),
+            DsdSink::Dop { output, .. } => output.flush(),
+            DsdSink::Pcm(_) => {}
+        }
+    }
+    result
+}
+
+enum DsdSink {
+    Native(NativeDsdOutput),
+    Dop { output: Box<dyn AudioOutput>, packer: DopPacker },
+    Pcm(DsdToPcm),
+}
+
+impl DsdSink {
+    fn open(
+        rsp_settings: &RsPlayerSettings,
+        info: &DsdInfo,
+        audio_device: &str,
+        audio_output: &mut Option<Box<dyn AudioOutput>>,
+    ) -> Self {
+        if rsp_settings.dsd_output == DsdOutput::Pcm {
+            return Self::Pcm(DsdToPcm::new(info));
+        }
+        // The shared PCM output has to release the device.
+        if let Some(mut out) = audio_output.take() {
+            out.flush();
+        }
+        if rsp_settings.dsd_output == DsdOutput::Dop {
+            let spec = SignalSpec::new(info.sample_rate / 16, channels(info.channels));
+            return match try_open_dop(spec, (CHUNK_SIZE / 2) as u64, audio_device, rsp_settings) {
+                Ok(output) => Self::Dop {
+                    output,
+                    packer: DopPacker::new(spec),
+                },
+                Err(err) => {
+                    warn!("DoP is not supported by {audio_device}, converting to PCM: {err}");
+                    Self::Pcm(DsdToPcm::new(info))
+                }
+            };
+        }
+        match NativeDsdOutput::open(audio_device, info) {
+            Ok(output) => Self::Native(output),
+            Err(err) => {
+                warn!("Native DSD is not supported by {audio_device}, converting to PCM: {err}");
+                Self::Pcm(DsdToPcm::new(info))
+            }
+        }
+    }
+
+    fn set_paused(&mut self, audio_output: &mut Option<Box<dyn AudioOutput>>, paused: bool) {
+        match self {
+            Self::Native(output) if paused => {
+                if !alsa_output::pause(&output.pcm) {
+                    output.drain();
+                }
+            }
+            Self::Native(output) => alsa_output::resume(&output.pcm),
+            Self::Dop { output, .. } if paused => output.pause(),
+            Self::Dop { output, .. } => output.resume(),
+            Self::Pcm(_) => output::set_paused(audio_output, paused),
+        }
+    }
+
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Native(_) => "native",
+            Self::Dop { .. } => "DoP",
+            Self::Pcm(_) => "PCM",
+        }
+    }
+}
+
+const fn channels(count: usize) -> Channels {
+    Channels::from_bits_truncate((1 << count) - 1)
+}
+
+/// Reads the DSD samples as MSB first bytes per channel.
+struct DsdReader {
+    file: BufReader<File>,
+    info: DsdInfo,
+    /// Bytes of one channel read so far.
+    position: u64,
+    /// Bytes of one channel holding samples, without the padding of the last block.
+    total: u64,
+    raw: Vec<u8>,
+}
+
+impl DsdReader {
+    fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let info = dsd::read_info(&mut file)?;
+        file.seek(SeekFrom::Start(info.data_offset))?;
+        let total = match info.container {
+            DsdContainer::Dsf => info.samples.div_ceil(8),
+            DsdContainer::Dff => info.data_len / info.channels as u64,
+        };
+        Ok(Self {
+            file,
+            info,
+            position: 0,
+            total,
+            raw: vec![],
+        })
+    }
+
+    fn read(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+        if self.position >= self.total {
+            return Ok(None);
+        }
+        let channels = self.info.channels;
+        let remaining = usize::try_from(self.total - self.position).unwrap_or(usize::MAX);
+        let chunk = match self.info.container {
+            DsdContainer::Dsf => {
+                let block = self.info.block_size;
+                self.raw.resize(block * channels, 0);
+                self.file.read_exact(&mut self.raw)?;
+                let len = block.min(remaining);
+                self.raw
+                    .chunks(block)
+                    .map(|data| data[..len].iter().map(|b| b.reverse_bits()).collect())
+                    .collect::<Vec<Vec<u8>>>()
+            }
+            DsdContainer::Dff => {
+                let len = CHUNK_SIZE.min(remaining);
+                self.raw.resize(len * channels, 0);
+                self.file.read_exact(&mut self.raw)?;
+                (0..channels)
+                    .map(|ch| self.raw.iter().skip(ch).step_by(channels).copied().collect())
+                    .collect()
+            }
+        };
+        self.position += chunk[0].len() as u64;
+        Ok(Some(chunk))
+    }
+
+    fn seek(&mut self, position: Duration) -> Result<()> {
+        debug!("Seeking to {position:?}");
+        let bytes_per_sec = u64::from(self.info.sample_rate / 8);
+        let target = (position.as_secs() * bytes_per_sec + u64::from(position.subsec_millis()) * bytes_per_sec / 1000)
+            .min(self.total);
+        let channels = self.info.channels as u64;
+        let (position, offset) = match self.info.container {
+            DsdContainer::Dsf => {
+                let block = self.info.block_size as u64;
+                let position = target / block * block;
+                (position, position * channels)
+            }
+            DsdContainer::Dff => {
+                let position = target / 4 * 4;
+                (position, position * channels)
+            }
+        };
+        self.file.seek(SeekFrom::Start(self.info.data_offset + offset))?;
+        self.position = position;
+        Ok(())
+    }
+
+    fn current_time(&self) -> Duration {
+        Duration::from_millis(self.position * 8000 / u64::from(self.info.sample_rate))
+    }
+}
+
+/// Packs 16 DSD bits of each channel with a marker byte into a 24 bit PCM sample.
+struct DopPacker {
+    spec: SignalSpec,
+    marker: usize,
+}
+
+impl DopPacker {
+    const fn new(spec: SignalSpec) -> Self {
+        Self { spec, marker: 0 }
+    }
+
+    fn pack(&mut self, chunk: &[Vec<u8>]) -> AudioBuffer<i32> {
+        let frames = chunk[0].len().div_ceil(2);
+        let mut buffer = AudioBuffer::<i32>::new(frames as u64, self.spec);
+        buffer.render_reserved(Some(frames));
+        let first_marker = self.marker;
+        for (ch, bytes) in chunk.iter().enumerate() {
+            let mut marker = first_marker;
+            for (sample, pair) in buffer.chan_mut(ch).iter_mut().zip(bytes.chunks(2)) {
+                let second = pair.get(1).copied().unwrap_or(DSD_SILENCE);
+                // The sample is left aligned in 32 bits, the lowest byte stays empty.
+                *sample = i32::from_be_bytes([DOP_MARKERS[marker], pair[0], second, 0]);
+                marker ^= 1;
+            }
+        }
+        self.marker = (first_marker + frames) % 2;
+        buffer
+    }
+}
+
+/// Raw DSD written to ALSA as `DSD_U32_BE`, four bytes of one channel per sample.
+struct NativeDsdOutput {
+    pcm: PCM,
+    buffer: Vec<u8>,
+}
+
+impl NativeDsdOutput {
+    fn open(audio_device: &str, info: &DsdInfo) -> Result<Self> {
+        let pcm = PCM::new(audio_device, Direction::Playback, false)?;
+        {
+            let hwp = HwParams::any(&pcm)?;
+            #[allow(clippy::cast_possible_truncation)]
+            hwp.set_channels(info.channels as u32)?;
+            hwp.set_rate(info.sample_rate / 32, ValueOr::Nearest)?;
+            hwp.set_format(Format::DSDU32BE)?;
+            hwp.set_access(Access::RWInterleaved)?;
+            pcm.hw_params(&hwp)?;
+        }
+        pcm.prepare()?;
+        info!("Opened {audio_device} for native DSD at {} Hz", info.sample_rate);
+        Ok(Self { pcm, buffer: vec![] })
+    }
+
+    fn write(&mut self, chunk: &[Vec<u8>]) -> Result<()> {
+        let frames = chunk[0].len().div_ceil(4);
+        self.buffer.clear();
+        for frame in 0..frames {
+            for bytes in chunk {
+                for i in 4 * frame..4 * frame + 4 {
+                    self.buffer.push(bytes.get(i).copied().unwrap_or(DSD_SILENCE));
+                }
+            }
+        }
+        // A drained stream has to be prepared again before it accepts samples.
+        if self.pcm.state() == State::Setup {
+            self.pcm.prepare()?;
+        }
+        let io = self.pcm.io_bytes();
+        let mut written = 0;
+        let frame_len = 4 * chunk.len();
+        while written < frames {
+            match io.writei(&self.buffer[written * frame_len..]) {
+                Ok(n) => written += n,
+                Err(err) => self.pcm.try_recover(err, true)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn drain(&self) {
+        _ = self.pcm.drain();
+    }
+}
+
+/// Converts DSD to PCM with a lowpass FIR filter evaluated for whole bytes through lookup
+/// tables, decimating to 176.4 or 192 kHz.
+struct DsdToPcm {
+    spec: SignalSpec,
+    /// Input bytes per output sample.
+    decimation: usize,
+    /// Filter response for every value of every byte in the filter window.
+    tables: Vec<[f32; 256]>,
+    /// Latest input bytes per channel, oldest first.
+    history: Vec<Vec<u8>>,
+    /// Input bytes per channel not converted yet.
+    pending: Vec<Vec<u8>>,
+}
+
+impl DsdToPcm {
+    fn new(info: &DsdInfo) -> Self {
+        let byte_rate = info.sample_rate / 8;
+        let decimation = (byte_rate / 176_400).max(1);
+        let spec = SignalSpec::new(byte_rate / decimation, channels(info.channels));
+        let decimation = decimation as usize;
+        let taps = filter_taps(8 * FILTER_BYTES_PER_DECIMATION * decimation, f64::from(info.sample_rate));
+        let tables = taps
+            .chunks(8)
+            .map(|byte_taps| {
+                let mut table = [0f32; 256];
+                for (value, out) in table.iter_mut().enumerate() {
+                    let sum: f64 = byte_taps
+                        .iter()
+                        .enumerate()
+                        .map(|(bit, tap)| if value & (0x80 >> bit) == 0 { -tap } else { *tap })
+                        .sum();
+                    #[allow(clippy::cast_possible_truncation)]
+                    let sum = sum as f32;
+                    *out = sum;
+                }
+                table
+            })
+            .collect::<Vec<_>>();
+        Self {
+            spec,
+            decimation,
+            history: vec![vec![DSD_SILENCE; tables.len()]; info.channels],
+            pending: vec![vec![]; info.channels],
+            tables,
+        }
+    }
+
+    fn convert(&mut self, chunk: &[Vec<u8>]) -> AudioBuffer<f32> {
+        for (pending, bytes) in self.pending.iter_mut().zip(chunk) {
+            pending.extend_from_slice(bytes);
+        }
+        let frames = self.pending[0].len() / self.decimation;
+        let mut buffer = AudioBuffer::<f32>::new(frames as u64, self.spec);
+        buffer.render_reserved(Some(frames));
+        let len = self.tables.len();
+        for (ch, (pending, history)) in self.pending.iter_mut().zip(&mut self.history).enumerate() {
+            for (sample, input) in buffer.chan_mut(ch).iter_mut().zip(pending.chunks_exact(self.decimation)) {
+                history.copy_within(self.decimation.., 0);
+                history[len - self.decimation..].copy_from_slice(input);
+                *sample = self
+                    .tables
+                    .iter()
+                    .zip(history.iter())
+                    .map(|(table, byte)| table[usize::from(*byte)])
+                    .sum::<f32>()
+                    .clamp(-1.0, 1.0);
+            }
+            pending.drain(..frames * self.decimation);
+        }
+        buffer
+    }
+}
+
+/// Blackman windowed sinc lowpass normalized to unity gain at DC.
+#[allow(clippy::cast_precision_loss)]
+fn filter_taps(len: usize, rate: f64) -> Vec<f64> {
+    let fc = PCM_CUTOFF_HZ / rate;
+    let middle = (len - 1) as f64 / 2.0;
+    let taps = (0..len)
+        .map(|i| {
+            let x = i as f64 - middle;
+            let sinc = if x.abs() < f64::EPSILON {
+                2.0 * fc
+            } else {
+                (2.0 * PI * fc * x).sin() / (PI * x)
+            };
+            let phase = 2.0 * PI * i as f64 / (len - 1) as f64;
+            let window = 0.08f64.mul_add((2.0 * phase).cos(), 0.5f64.mul_add(-phase.cos(), 0.42));
+            sinc * window
+        })
+        .collect::<Vec<_>>();
+    let sum: f64 = taps.iter().sum();
+    taps.into_iter().map(|tap| tap / sum).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use api_models::common::DsdOutput;
+    use api_models::settings::RsPlayerSettings;
+    use rsplayer_metadata::dsd::{DsdContainer, DsdInfo};
+    use symphonia::core::audio::{Channels, Signal, SignalSpec};
+
+    use super::{DopPacker, DsdSink, DsdToPcm, DSD_SILENCE};
+
+    fn info(channels: usize) -> DsdInfo {
+        DsdInfo {
+            container: DsdContainer::Dff,
+            sample_rate: 2_822_400,
+            channels,
+            samples: 0,
+            data_offset: 0,
+            data_len: 0,
+            block_size: 1,
+            id3_offset: None,
+        }
+    }
+
+    #[test]
+    fn should_pack_dop_with_alternating_markers() {
+        let spec = SignalSpec::new(176_400, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut packer = DopPacker::new(spec);
+        let buffer = packer.pack(&[vec![0x12, 0x34, 0x56], vec![0xAB, 0xCD, 0xEF]]);
+        assert_eq!(buffer.frames(), 2);
+        assert_eq!(buffer.chan(0), [0x0512_3400, i32::from_be_bytes([0xFA, 0x56, DSD_SILENCE, 0])]);
+        assert_eq!(buffer.chan(1)[0], 0x05AB_CD00);
+        // The marker continues from the previous chunk.
+        let buffer = packer.pack(&[vec![0, 0], vec![0, 0]]);
+        assert_eq!(buffer.chan(0)[0], 0x0500_0000);
+    }
+
+    #[test]
+    fn should_convert_dsd_to_pcm() {
+        let mut converter = DsdToPcm::new(&info(1));
+        assert_eq!(converter.spec.rate, 176_400);
+        // All ones is the positive full scale, the idle pattern is silence.
+        let pcm = converter.convert(&[vec![0xFF; 8192]]);
+        assert_eq!(pcm.frames(), 4096);
+        assert!(pcm.chan(0)[4000..].iter().all(|s| (s - 1.0).abs() < 1e-3));
+        let pcm = converter.convert(&[vec![DSD_SILENCE; 8192]]);
+        assert!(pcm.chan(0)[4000..].iter().all(|s| s.abs() < 1e-3));
+    }
+
+    #[test]
+    fn should_convert_to_pcm_when_output_does_not_play_integer_samples() {
+        let rsp_settings = RsPlayerSettings {
+            dsd_output: DsdOutput::Dop,
+            ..Default::default()
+        };
+        let sink = DsdSink::open(&rsp_settings, &info(2), "null:", &mut None);
+        assert!(matches!(sink, DsdSink::Pcm(_)));
+
+        let path = std::env::temp_dir().join(format!("rsp_test_dop_{}.wav", std::process::id()));
+        let sink = DsdSink::open(&rsp_settings, &info(2), &format!("file:{}", path.display()), &mut None);
+        assert!(matches!(sink, DsdSink::Dop { .. }));
+        drop(sink);
+        _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,344 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use api_models::common::{EqFilterType, Volume};
+use api_models::settings::{ChannelSettings, ConvolutionSettings, DspSettings, EqFilter, OutputChannelSettings};
+use api_models::state::AudioOut;
+use log::debug;
+use symphonia::core::audio::{AudioBuffer, Signal, SignalSpec};
+
+use super::channel_mixer::ChannelMixer;
+use super::convolution::Convolver;
+use super::volume::{VolumeControl, VolumeScaler};
+
+/// DSP settings shared between the player service and the playback thread, so changes are
+/// applied to the song that is playing.
+#[derive(Clone)]
+pub struct DspControl {
+    settings: Arc<Mutex<DspSettings>>,
+    changed: Arc<AtomicBool>,
+    convolution_settings: ConvolutionSettings,
+    convolution_enabled: Arc<AtomicBool>,
+    output_channel_settings: OutputChannelSettings,
+    /// Channel settings of the selected output.
+    channel_settings: Arc<Mutex<ChannelSettings>>,
+    channel_settings_changed: Arc<AtomicBool>,
+    volume: VolumeControl,
+}
+
+impl DspControl {
+    pub fn new(
+        settings: DspSettings,
+        convolution_settings: ConvolutionSettings,
+        output_channel_settings: OutputChannelSettings,
+        volume: VolumeControl,
+    ) -> Self {
+        Self {
+            settings: Arc::new(Mutex::new(settings)),
+            changed: Arc::new(AtomicBool::new(false)),
+            convolution_enabled: Arc::new(AtomicBool::new(convolution_settings.enabled_for_speakers)),
+            convolution_settings,
+            channel_settings: Arc::new(Mutex::new(output_channel_settings.speakers.clone())),
+            channel_settings_changed: Arc::new(AtomicBool::new(false)),
+            output_channel_settings,
+            volume,
+        }
+    }
+
+    /// Room correction and the channel processing are set up separately for speakers and headphones.
+    pub fn set_audio_output(&self, audio_out: AudioOut) {
+        let (enabled, channel_settings) = match audio_out {
+            AudioOut::SPKR => (
+                self.convolution_settings.enabled_for_speakers,
+                &self.output_channel_settings.speakers,
+            ),
+            AudioOut::HEAD => (
+                self.convolution_settings.enabled_for_headphones,
+                &self.output_channel_settings.headphones,
+            ),
+        };
+        self.convolution_enabled.store(enabled, Ordering::Relaxed);
+        let mut current = self.channel_settings.lock().unwrap();
+        if *current != *channel_settings {
+            current.clone_from(channel_settings);
+            self.channel_settings_changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Has effect only if the volume is controlled in software.
+    pub fn set_volume(&self, volume: &Volume) {
+        self.volume.set_volume(volume);
+    }
+
+    pub fn set_settings(&self, settings: DspSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get_settings(&self) -> DspSettings {
+        self.settings.lock().unwrap().clone()
+    }
+}
+
+/// Preamp and biquad filters followed by the channel processing, the room correction convolution
+/// and the software volume, applied to decoded samples before they reach the audio output.
+#[allow(clippy::struct_excessive_bools)]
+pub struct DspChain {
+    control: DspControl,
+    settings: DspSettings,
+    rate: u32,
+    channels: usize,
+    filters: Vec<Vec<Biquad>>,
+    channel_mixer: ChannelMixer,
+    convolver: Convolver,
+    volume: VolumeScaler,
+    eq_active: bool,
+    channels_active: bool,
+    convolution_active: bool,
+    volume_active: bool,
+}
+
+impl DspChain {
+    pub fn new(control: DspControl) -> Self {
+        let settings = control.get_settings();
+        let channel_mixer = ChannelMixer::new(control.channel_settings.lock().unwrap().clone());
+        let convolver = Convolver::new(control.convolution_settings.clone());
+        let volume = VolumeScaler::new(control.volume.clone());
+        Self {
+            control,
+            settings,
+            rate: 0,
+            channels: 0,
+            filters: vec![],
+            channel_mixer,
+            convolver,
+            volume,
+            eq_active: false,
+            channels_active: false,
+            convolution_active: false,
+            volume_active: false,
+        }
+    }
+
+    /// Picks up settings changed by the user, returns true if the samples have to be processed.
+    pub fn is_active(&mut self, spec: &SignalSpec) -> bool {
+        if self.control.changed.swap(false, Ordering::Relaxed) {
+            self.settings = self.control.get_settings();
+            // Filters are rebuilt on the next processed buffer.
+            self.rate = 0;
+            debug!("DSP settings changed to {:?}", self.settings);
+        }
+        if self.control.channel_settings_changed.swap(false, Ordering::Relaxed) {
+            let channel_settings = self.control.channel_settings.lock().unwrap().clone();
+            debug!("Channel settings changed to {channel_settings:?}");
+            self.channel_mixer.set_settings(channel_settings);
+        }
+        self.eq_active =
+            self.settings.enabled && (self.settings.preamp_db != 0.0 || !self.settings.filters.is_empty());
+        self.channels_active = self.channel_mixer.is_active();
+        self.convolution_active =
+            self.control.convolution_enabled.load(Ordering::Relaxed) && self.convolver.prepare(*spec);
+        self.volume_active = self.volume.is_active();
+        self.eq_active || self.channels_active || self.convolution_active || self.volume_active
+    }
+
+    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        if self.eq_active {
+            self.process_eq(buffer);
+        }
+        if self.channels_active {
+            self.channel_mixer.process(buffer);
+        }
+        if self.convolution_active {
+            self.convolver.process(buffer);
+        }
+        if self.volume_active {
+            self.volume.process(buffer);
+        }
+    }
+
+    fn process_eq(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let spec = *buffer.spec();
+        let channels = spec.channels.count();
+        if spec.rate != self.rate || channels != self.channels {
+            self.configure(spec.rate, channels);
+        }
+        let preamp = 10f64.powf(f64::from(self.settings.preamp_db) / 20.0);
+        for (ch, filters) in self.filters.iter_mut().enumerate() {
+            for sample in buffer.chan_mut(ch) {
+                let mut value = f64::from(*sample) * preamp;
+                for filter in filters.iter_mut() {
+                    value = filter.process(value);
+                }
+                #[allow(clippy::cast_possible_truncation)]
+                let value = value as f32;
+                *sample = value;
+            }
+        }
+    }
+
+    fn configure(&mut self, rate: u32, channels: usize) {
+        self.rate = rate;
+        self.channels = channels;
+        self.filters = (0..channels)
+            .map(|ch| {
+                self.settings
+                    .filters
+                    .iter()
+                    .filter(|filter| filter.channel.is_none() || filter.channel == Some(ch))
+                    .map(|filter| Biquad::new(filter, rate))
+                    .collect()
+            })
+            .collect();
+    }
+}
+
+/// Second order IIR filter with coefficients from the RBJ audio EQ cookbook.
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(filter: &EqFilter, rate: u32) -> Self {
+        let rate = f64::from(rate);
+        let frequency = f64::from(filter.frequency).min(rate * 0.49);
+        let a = 10f64.powf(f64::from(filter.gain_db) / 40.0);
+        let w0 = 2.0 * PI * frequency / rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * f64::from(filter.q));
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+        let (b0, b1, b2, a0, a1, a2) = match filter.filter_type {
+            EqFilterType::Peaking => (
+                alpha.mul_add(a, 1.0),
+                -2.0 * cos,
+                alpha.mul_add(-a, 1.0),
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            EqFilterType::LowShelf => (
+                a * ((a - 1.0).mul_add(-cos, a + 1.0) + sqrt_a_alpha),
+                2.0 * a * (a + 1.0).mul_add(-cos, a - 1.0),
+                a * ((a - 1.0).mul_add(-cos, a + 1.0) - sqrt_a_alpha),
+                (a - 1.0).mul_add(cos, a + 1.0) + sqrt_a_alpha,
+                -2.0 * (a + 1.0).mul_add(cos, a - 1.0),
+                (a - 1.0).mul_add(cos, a + 1.0) - sqrt_a_alpha,
+            ),
+            EqFilterType::HighShelf => (
+                a * ((a - 1.0).mul_add(cos, a + 1.0) + sqrt_a_alpha),
+                -2.0 * a * (a + 1.0).mul_add(cos, a - 1.0),
+                a * ((a - 1.0).mul_add(cos, a + 1.0) - sqrt_a_alpha),
+                (a - 1.0).mul_add(-cos, a + 1.0) + sqrt_a_alpha,
+                2.0 * (a + 1.0).mul_add(-cos, a - 1.0),
+                (a - 1.0).mul_add(-cos, a + 1.0) - sqrt_a_alpha,
+            ),
+            EqFilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            EqFilterType::HighPass => (
+                cos.mul_add(0.5, 0.5),
+                -(1.0 + cos),
+                cos.mul_add(0.5, 0.5),
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0.mul_add(
+            x,
+            self.b1.mul_add(
+                self.x1,
+                self.b2
+                    .mul_add(self.x2, (-self.a1).mul_add(self.y1, -self.a2 * self.y2)),
+            ),
+        );
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use api_models::common::EqFilterType;
+    use api_models::settings::EqFilter;
+
+    use super::Biquad;
+
+    fn filter(filter_type: EqFilterType, frequency: f32, gain_db: f32) -> EqFilter {
+        EqFilter {
+            filter_type,
+            frequency,
+            gain_db,
+            q: 0.707,
+            channel: None,
+        }
+    }
+
+    /// Peak amplitude of a unit sine after it passes through the filter.
+    fn sine_response(filter: &EqFilter, frequency: f64) -> f64 {
+        let mut biquad = Biquad::new(filter, 48000);
+        (0..48000)
+            .map(|i| biquad.process((2.0 * PI * frequency * f64::from(i) / 48000.0).sin()))
+            .skip(24000)
+            .fold(0.0, |max, y| y.abs().max(max))
+    }
+
+    #[test]
+    fn should_keep_signal_with_zero_gain_peaking_filter() {
+        let response = sine_response(&filter(EqFilterType::Peaking, 1000.0, 0.0), 1000.0);
+        assert!((response - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn should_boost_peaking_filter_center_frequency() {
+        let response = sine_response(&filter(EqFilterType::Peaking, 1000.0, 6.0), 1000.0);
+        assert!(20.0f64.mul_add(response.log10(), -6.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn should_attenuate_above_low_pass_cutoff() {
+        let lp = filter(EqFilterType::LowPass, 1000.0, 0.0);
+        assert!(sine_response(&lp, 100.0) > 0.99);
+        assert!(sine_response(&lp, 10000.0) < 0.02);
+    }
+
+    #[test]
+    fn should_shelve_low_frequencies() {
+        let shelf = filter(EqFilterType::LowShelf, 200.0, -6.0);
+        assert!(20.0f64.mul_add(sine_response(&shelf, 20.0).log10(), 6.0).abs() < 0.2);
+        assert!((sine_response(&shelf, 10000.0) - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,257 @@
+//! Streamable FLAC encoder with fixed predictors, good enough to send the played audio over the
+//! network losslessly.
+use symphonia::core::audio::{AudioBufferRef, Signal, SignalSpec};
+
+/// Frames of one FLAC frame.
+pub const BLOCK_LEN: usize = 4096;
+const MIN_BLOCK_LEN: u16 = 16;
+/// Samples are encoded with 24 bits, the unused low bits of 16 bit sources cost nothing.
+const BITS_PER_SAMPLE: u32 = 24;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_RICE_PARAMETER: u32 = 14;
+
+pub struct FlacEncoder {
+    spec: SignalSpec,
+    /// Samples waiting for a full block, per channel.
+    pending: Vec<Vec<i32>>,
+    /// Frames encoded so far, the position of the next frame in the stream.
+    encoded_frames: u64,
+}
+
+impl FlacEncoder {
+    pub fn new(spec: SignalSpec) -> Self {
+        Self {
+            spec,
+            pending: vec![vec![]; spec.channels.count()],
+            encoded_frames: 0,
+        }
+    }
+
+    pub const fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+
+    /// The `fLaC` marker and the stream info, sent before the first frame.
+    pub fn header(&self) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        writer.write_bytes(b"fLaC");
+        // The last metadata block, stream info, 34 bytes long.
+        writer.write(1, 1);
+        writer.write(0, 7);
+        writer.write(34, 24);
+        writer.write(u64::from(MIN_BLOCK_LEN), 16);
+        writer.write(BLOCK_LEN as u64, 16);
+        // Frame sizes, the total number of samples and the MD5 are unknown for a stream.
+        writer.write(0, 24);
+        writer.write(0, 24);
+        writer.write(u64::from(self.spec.rate), 20);
+        writer.write(self.spec.channels.count() as u64 - 1, 3);
+        writer.write(u64::from(BITS_PER_SAMPLE) - 1, 5);
+        writer.write(0, 36);
+        writer.write_bytes(&[0; 16]);
+        writer.into_bytes()
+    }
+
+    /// Encodes the full blocks of the buffered samples, returns the encoded frames.
+    pub fn encode(&mut self, decoded: &AudioBufferRef<'_>) -> Vec<u8> {
+        let mut buffer = decoded.make_equivalent::<i32>();
+        decoded.convert(&mut buffer);
+        for (channel, pending) in self.pending.iter_mut().enumerate() {
+            pending.extend(
+                buffer
+                    .chan(channel)
+                    .iter()
+                    .map(|sample| sample >> (32 - BITS_PER_SAMPLE)),
+            );
+        }
+        let mut frames = vec![];
+        while self.pending[0].len() >= BLOCK_LEN {
+            frames.extend(self.encode_block(BLOCK_LEN));
+        }
+        frames
+    }
+
+    fn encode_block(&mut self, len: usize) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        // Sync code and the variable block size strategy, the position is given in samples.
+        writer.write(0b1111_1111_1111_1001, 16);
+        // The block size follows the header, sample rate and sample size are the stream ones.
+        writer.write(0b0111, 4);
+        writer.write(0b0000, 4);
+        writer.write(self.spec.channels.count() as u64 - 1, 4);
+        writer.write(0b000, 3);
+        writer.write(0, 1);
+        writer.write_bytes(&utf8_number(self.encoded_frames));
+        writer.write(len as u64 - 1, 16);
+        let header_crc = crc8(writer.bytes());
+        writer.write(u64::from(header_crc), 8);
+        for pending in &mut self.pending {
+            let block: Vec<i32> = pending.drain(..len).collect();
+            write_subframe(&mut writer, &block);
+        }
+        writer.align();
+        let frame_crc = crc16(writer.bytes());
+        writer.write(u64::from(frame_crc), 16);
+        self.encoded_frames += len as u64;
+        writer.into_bytes()
+    }
+}
+
+fn write_subframe(writer: &mut BitWriter, block: &[i32]) {
+    // Subframe type and the wasted bits flag follow the zero padding bit.
+    if block.iter().all(|&sample| sample == block[0]) {
+        writer.write(0, 8);
+        writer.write_signed(i64::from(block[0]), BITS_PER_SAMPLE);
+        return;
+    }
+    let wasted = block
+        .iter()
+        .map(|sample| sample.trailing_zeros())
+        .min()
+        .unwrap_or_default();
+    let samples: Vec<i64> = block.iter().map(|&sample| i64::from(sample >> wasted)).collect();
+    let bits = BITS_PER_SAMPLE - wasted;
+    let order = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .min_by_key(|&order| fixed_residual(&samples, order).map(i64::unsigned_abs).sum::<u64>())
+        .unwrap_or_default();
+    writer.write(0b0000_1000 | order as u64, 7);
+    if wasted > 0 {
+        writer.write(1, 1);
+        writer.write(0, wasted - 1);
+        writer.write(1, 1);
+    } else {
+        writer.write(0, 1);
+    }
+    for &sample in &samples[..order] {
+        writer.write_signed(sample, bits);
+    }
+    let residual: Vec<u64> = fixed_residual(&samples, order).map(zigzag).collect();
+    let parameter = rice_parameter(&residual);
+    // Rice coding with 4 bit parameters and a single partition.
+    writer.write(0b00, 2);
+    writer.write(0, 4);
+    writer.write(u64::from(parameter), 4);
+    for value in residual {
+        let quotient = value >> parameter;
+        for _ in 0..quotient / 32 {
+            writer.write(0, 32);
+        }
+        writer.write(0, (quotient % 32) as u32);
+        writer.write(1, 1);
+        writer.write(value & ((1 << parameter) - 1), parameter);
+    }
+}
+
+/// Residual of the fixed polynomial predictor of the order.
+fn fixed_residual(samples: &[i64], order: usize) -> impl Iterator<Item = i64> + '_ {
+    (order..samples.len()).map(move |i| {
+        let s = |back: usize| samples[i - back];
+        match order {
+            0 => s(0),
+            1 => s(0) - s(1),
+            2 => s(0) - 2 * s(1) + s(2),
+            3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+            _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+        }
+    })
+}
+
+#[allow(clippy::cast_sign_loss)]
+const fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Rice parameter close to the optimum for the mean of the values.
+fn rice_parameter(values: &[u64]) -> u32 {
+    let mean = values.iter().sum::<u64>() / values.len().max(1) as u64;
+    (u64::BITS - mean.leading_zeros()).min(MAX_RICE_PARAMETER)
+}
+
+/// Sample number coded like UTF-8, as in the frame header.
+#[allow(clippy::cast_possible_truncation)]
+fn utf8_number(value: u64) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    let continuation_bytes = (1..=6).find(|&bytes| value < 1 << (5 * bytes + 6)).unwrap_or(6);
+    let mut bytes = vec![0; continuation_bytes + 1];
+    let mut rest = value;
+    for byte in bytes[1..].iter_mut().rev() {
+        *byte = 0x80 | (rest & 0x3F) as u8;
+        rest >>= 6;
+    }
+    bytes[0] = (0xFF00_u16 >> (continuation_bytes + 1)) as u8 | rest as u8;
+    bytes
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(
+            crc ^ byte,
+            |crc, _| if crc & 0x80 == 0 { crc << 1 } else { (crc << 1) ^ 0x07 },
+        )
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x8005
+            }
+        })
+    })
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    acc_bits: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `bits` bits of the value, at most 36 of them at once.
+    #[allow(clippy::cast_possible_truncation)]
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.acc_bits += bits;
+        while self.acc_bits >= 8 {
+            self.acc_bits -= 8;
+            self.bytes.push((self.acc >> self.acc_bits) as u8);
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write(u64::from(byte), 8);
+        }
+    }
+
+    /// Pads the last byte with zero bits.
+    fn align(&mut self) {
+        if self.acc_bits > 0 {
+            self.write(0, 8 - self.acc_bits);
+        }
+    }
+
+    /// The complete bytes written so far.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
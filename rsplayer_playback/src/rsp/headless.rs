@@ -0,0 +1,141 @@
+//! Outputs that don't need a sound card, selected by the device name `null:`, `null:fast` or
+//! `file:/path/to/file.wav`.
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{info, warn};
+use symphonia::core::audio::{AudioBufferRef, SampleBuffer, SignalSpec};
+
+use super::output::AudioOutput;
+
+/// Bytes of one sample written to the WAV file.
+const WAV_SAMPLE_BYTES: u16 = 4;
+
+/// Discards the samples, at the speed they would be played or as fast as they are written.
+pub struct NullOutput {
+    spec: SignalSpec,
+    realtime: bool,
+    /// Start of the playback and the frames written since then.
+    clock: Option<(Instant, u64)>,
+}
+
+impl NullOutput {
+    pub const fn new(spec: SignalSpec, realtime: bool) -> Self {
+        Self {
+            spec,
+            realtime,
+            clock: None,
+        }
+    }
+}
+
+impl AudioOutput for NullOutput {
+    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+        if !self.realtime {
+            return Ok(());
+        }
+        let (start, frames) = self.clock.get_or_insert_with(|| (Instant::now(), 0));
+        *frames += decoded.frames() as u64;
+        let played = Duration::from_micros(*frames * 1_000_000 / u64::from(self.spec.rate));
+        if let Some(ahead) = played.checked_sub(start.elapsed()) {
+            thread::sleep(ahead);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        self.clock = None;
+    }
+
+    fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+}
+
+/// Writes the samples to a 32 bit PCM WAV file, reopening the output starts the file over.
+pub struct WavFileOutput {
+    file: BufWriter<File>,
+    spec: SignalSpec,
+    sample_buf: SampleBuffer<i32>,
+    data_len: u32,
+}
+
+impl WavFileOutput {
+    pub fn try_open(spec: SignalSpec, duration: u64, path: &str) -> Result<Box<dyn AudioOutput>> {
+        let mut output = Self {
+            file: BufWriter::new(File::create(path)?),
+            spec,
+            sample_buf: SampleBuffer::new(duration, spec),
+            data_len: 0,
+        };
+        output.write_header()?;
+        info!("Writing audio output to {path}");
+        Ok(Box::new(output))
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        #[allow(clippy::cast_possible_truncation)]
+        let channels = self.spec.channels.count() as u16;
+        let block_align = channels * WAV_SAMPLE_BYTES;
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(b"RIFF")?;
+        file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // Integer PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&self.spec.rate.to_le_bytes())?;
+        file.write_all(&(self.spec.rate * u32::from(block_align)).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&(WAV_SAMPLE_BYTES * 8).to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&self.data_len.to_le_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioOutput for WavFileOutput {
+    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+        if decoded.frames() == 0 {
+            return Ok(());
+        }
+        if decoded.capacity() * self.spec.channels.count() > self.sample_buf.capacity() {
+            self.sample_buf = SampleBuffer::new(decoded.capacity() as u64, self.spec);
+        }
+        self.sample_buf.copy_interleaved_ref(decoded);
+        for sample in self.sample_buf.samples() {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (self.sample_buf.len() * usize::from(WAV_SAMPLE_BYTES)) as u32;
+        self.data_len = self.data_len.saturating_add(len);
+        Ok(())
+    }
+
+    /// Updates the sizes in the header, so the file is valid while the output stays open.
+    fn flush(&mut self) {
+        if let Err(err) = self.write_header().and_then(|()| Ok(self.file.flush()?)) {
+            warn!("Failed to finish the WAV file: {err}");
+        }
+    }
+
+    fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+
+    fn integer_bits(&self) -> Option<u32> {
+        Some(u32::from(WAV_SAMPLE_BYTES) * 8)
+    }
+}
+
+impl Drop for WavFileOutput {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
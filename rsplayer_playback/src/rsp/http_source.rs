@@ -0,0 +1,128 @@
+//! Remote files read in parts with HTTP range requests, which makes them seekable.
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::{format_err, Result};
+use log::{debug, warn};
+use symphonia::core::io::MediaSource;
+use ureq::{Agent, Response};
+
+use super::radio::StreamControl;
+
+/// Bytes requested at once and kept as the read-ahead cache.
+const CHUNK_LEN: u64 = 1024 * 1024;
+
+pub struct HttpRangeSource {
+    agent: Agent,
+    url: String,
+    len: u64,
+    pos: u64,
+    /// Bytes of the file starting at `cache_start`.
+    cache: Vec<u8>,
+    cache_start: u64,
+    control: StreamControl,
+}
+
+impl HttpRangeSource {
+    /// Length of the file in the response when the server can send it in parts.
+    pub fn range_len(resp: &Response) -> Option<u64> {
+        let accepts_ranges = resp
+            .header("accept-ranges")
+            .is_some_and(|ranges| ranges.eq_ignore_ascii_case("bytes"));
+        let is_stream = resp.header("icy-metaint").is_some();
+        if !accepts_ranges || is_stream || resp.status() != 200 {
+            return None;
+        }
+        resp.header("content-length")?.parse().ok().filter(|len| *len > 0)
+    }
+
+    /// Starts reading the file with the response to the request of the whole file, which is used
+    /// for the first chunk.
+    pub fn new(agent: Agent, url: &str, len: u64, resp: Response, control: StreamControl) -> Self {
+        let mut cache = vec![];
+        if let Err(err) = resp.into_reader().take(CHUNK_LEN).read_to_end(&mut cache) {
+            warn!("Failed to read the start of {url}: {err}");
+            cache.clear();
+        }
+        Self {
+            agent,
+            url: url.to_string(),
+            len,
+            pos: 0,
+            cache,
+            cache_start: 0,
+            control,
+        }
+    }
+
+    fn cached(&self) -> &[u8] {
+        self.pos
+            .checked_sub(self.cache_start)
+            .and_then(|offset| usize::try_from(offset).ok())
+            .and_then(|offset| self.cache.get(offset..))
+            .unwrap_or_default()
+    }
+
+    /// Downloads the chunk starting at the current position.
+    fn fill_cache(&mut self) -> io::Result<()> {
+        let (start, end) = (self.pos, (self.pos + CHUNK_LEN).min(self.len) - 1);
+        debug!("Reading bytes {start}-{end} of {}", self.url);
+        let download = || -> Result<Vec<u8>> {
+            let resp = self
+                .agent
+                .get(&self.url)
+                .set("Range", &format!("bytes={start}-{end}"))
+                .call()
+                .map_err(|err| format_err!("Failed to get url {}: {err}", self.url))?;
+            if resp.status() != 206 {
+                return Err(format_err!("Range request not supported for {}", self.url));
+            }
+            let mut data = vec![];
+            resp.into_reader().take(end - start + 1).read_to_end(&mut data)?;
+            Ok(data)
+        };
+        self.cache = download().or_else(|err| {
+            warn!("Failed to download {}: {err}", self.url);
+            self.control.reconnect(&self.url, download)
+        })?;
+        self.cache_start = start;
+        Ok(())
+    }
+}
+
+impl Read for HttpRangeSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        if self.cached().is_empty() {
+            self.fill_cache()?;
+        }
+        let cached = self.cached();
+        let read = buf.len().min(cached.len());
+        buf[..read].copy_from_slice(&cached[..read]);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for HttpRangeSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the file start"))?;
+        Ok(self.pos)
+    }
+}
+
+impl MediaSource for HttpRangeSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
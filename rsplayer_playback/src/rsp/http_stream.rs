@@ -0,0 +1,224 @@
+//! The played audio encoded to FLAC for the clients listening on the HTTP stream endpoint.
+use std::sync::{Arc, Mutex};
+
+use api_models::common::HttpStreamMode;
+use api_models::player::Song;
+use log::{debug, info};
+use symphonia::core::audio::AudioBufferRef;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+use super::flac_encoder::FlacEncoder;
+use super::icy::IcyMetadataWriter;
+
+/// Encoded chunks queued for a listener, about 90 seconds of CD audio.
+const LISTENER_QUEUE_LEN: usize = 1000;
+/// Listeners that can't keep up are disconnected after missing that many chunks in a row.
+const MAX_DROPPED_CHUNKS: usize = 100;
+
+#[derive(Clone)]
+pub struct HttpStream {
+    enabled: bool,
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    encoder: Option<FlacEncoder>,
+    /// Stream header of the current encoder, sent to every new listener first.
+    header: Vec<u8>,
+    listeners: Vec<Listener>,
+    stream_title: String,
+}
+
+struct Listener {
+    tx: Sender<Vec<u8>>,
+    icy: Option<IcyMetadataWriter>,
+    has_header: bool,
+    dropped_chunks: usize,
+}
+
+impl Listener {
+    /// Queues the data without waiting, returns false when the listener is gone.
+    fn send(&mut self, data: &[u8], stream_title: &str) -> bool {
+        let data = self
+            .icy
+            .as_mut()
+            .map_or_else(|| data.to_vec(), |icy| icy.write(data, stream_title));
+        match self.tx.try_send(data) {
+            Ok(()) => {
+                self.dropped_chunks = 0;
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.dropped_chunks += 1;
+                self.dropped_chunks < MAX_DROPPED_CHUNKS
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+impl HttpStream {
+    pub fn new(mode: HttpStreamMode) -> Self {
+        Self {
+            enabled: mode != HttpStreamMode::Off,
+            shared: Arc::default(),
+        }
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether anybody listens to the stream, the played audio is only encoded then.
+    pub fn has_listeners(&self) -> bool {
+        self.enabled
+            && !self
+                .shared
+                .lock()
+                .expect("Failed to lock the http stream")
+                .listeners
+                .is_empty()
+    }
+
+    /// Adds a listener, the stream starts with the header of the current encoder. A metadata block
+    /// with the stream title is inserted after every `icy_metaint` bytes when it is given.
+    pub fn listen(&self, icy_metaint: Option<usize>) -> Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(LISTENER_QUEUE_LEN);
+        let mut shared = self.shared.lock().expect("Failed to lock the http stream");
+        let mut listener = Listener {
+            tx,
+            icy: icy_metaint.map(IcyMetadataWriter::new),
+            has_header: false,
+            dropped_chunks: 0,
+        };
+        if !shared.header.is_empty() {
+            listener.has_header = listener.send(&shared.header, &shared.stream_title);
+        }
+        shared.listeners.push(listener);
+        info!("Http stream listener connected, {} listening", shared.listeners.len());
+        rx
+    }
+
+    /// Sets the ICY stream title to the artist and title of the song.
+    pub fn set_song(&self, song: &Song) {
+        let stream_title = song
+            .artist
+            .as_ref()
+            .map_or_else(|| song.get_title(), |artist| format!("{artist} - {}", song.get_title()));
+        self.shared.lock().expect("Failed to lock the http stream").stream_title = stream_title;
+    }
+
+    /// Encodes the buffer for the listeners, it is skipped while nobody listens. Listeners that
+    /// received the header of another signal spec are disconnected, clients reconnect to get the
+    /// new one.
+    pub fn write(&self, buffer: &AudioBufferRef<'_>) {
+        if !self.enabled {
+            return;
+        }
+        let mut shared = self.shared.lock().expect("Failed to lock the http stream");
+        if shared.listeners.is_empty() {
+            return;
+        }
+        let shared = &mut *shared;
+        let spec = *buffer.spec();
+        if shared.encoder.as_ref().map(FlacEncoder::spec) != Some(spec) {
+            debug!("Http stream format changed to {spec:?}");
+            let encoder = FlacEncoder::new(spec);
+            shared.header = encoder.header();
+            shared.listeners.retain(|listener| !listener.has_header);
+            shared.encoder = Some(encoder);
+        }
+        let frames = shared
+            .encoder
+            .as_mut()
+            .map(|encoder| encoder.encode(buffer))
+            .unwrap_or_default();
+        let (header, stream_title) = (&shared.header, &shared.stream_title);
+        shared.listeners.retain_mut(|listener| {
+            if !listener.has_header {
+                if !listener.send(header, stream_title) {
+                    return false;
+                }
+                listener.has_header = true;
+            }
+            frames.is_empty() || listener.send(&frames, stream_title)
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::io::Cursor;
+    use std::sync::mpsc;
+
+    use api_models::common::HttpStreamMode;
+    use api_models::player::Song;
+    use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, SampleBuffer, Signal, SignalSpec};
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions, ReadOnlySource};
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+    use symphonia::default::{get_codecs, get_probe};
+
+    use super::super::icy::IcyMetadataSource;
+    use super::HttpStream;
+
+    #[test]
+    fn should_stream_lossless_flac_with_stream_title() {
+        let stream = HttpStream::new(HttpStreamMode::WithLocalOutput);
+        stream.set_song(&Song {
+            title: Some("Title".to_string()),
+            artist: Some("Artist".to_string()),
+            ..Default::default()
+        });
+        let mut rx = stream.listen(Some(1000));
+        let mut buffer = AudioBuffer::<i16>::new(
+            10_000,
+            SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+        );
+        buffer.render_reserved(None);
+        for (i, sample) in (0i16..).zip(buffer.chan_mut(0)) {
+            *sample = i.wrapping_mul(7919);
+        }
+        buffer.chan_mut(1).fill(-3);
+        stream.write(&AudioBufferRef::S16(Cow::Borrowed(&buffer)));
+
+        let mut received = vec![];
+        while let Ok(data) = rx.try_recv() {
+            received.extend(data);
+        }
+        let (titles_tx, titles_rx) = mpsc::channel();
+        let source = IcyMetadataSource::new(Cursor::new(received), 1000, titles_tx);
+        let mss = MediaSourceStream::new(
+            Box::new(ReadOnlySource::new(source)),
+            MediaSourceStreamOptions::default(),
+        );
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .unwrap()
+            .format;
+        let track = format.default_track().unwrap();
+        let mut decoder = get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .unwrap();
+        let mut decoded: Vec<i16> = vec![];
+        while let Ok(packet) = format.next_packet() {
+            let frames = decoder.decode(&packet).unwrap();
+            let mut samples = SampleBuffer::<i16>::new(frames.capacity() as u64, *frames.spec());
+            samples.copy_interleaved_ref(frames);
+            decoded.extend(samples.samples());
+        }
+
+        // Two full blocks are encoded, the rest waits for more samples.
+        assert_eq!(decoded.len(), 2 * 2 * super::super::flac_encoder::BLOCK_LEN);
+        let left: Vec<i16> = decoded.iter().step_by(2).copied().collect();
+        assert_eq!(left, &buffer.chan(0)[..left.len()]);
+        assert!(decoded.iter().skip(1).step_by(2).all(|&sample| sample == -3));
+        assert_eq!(titles_rx.try_recv().unwrap(), "Artist - Title");
+    }
+}
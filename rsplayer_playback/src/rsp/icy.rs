@@ -0,0 +1,274 @@
+//! ICY (Shoutcast) metadata sent by internet radio stations in between the audio data.
+use std::io::{self, Read};
+use std::sync::mpsc::Sender;
+
+use api_models::player::Song;
+use log::debug;
+use sled::{Db, Tree};
+
+/// Tag of the radio song that holds the stream title as sent by the station.
+pub const STREAM_TITLE_TAG: &str = "StreamTitle";
+/// Titles kept in the history of a single station.
+const HISTORY_LEN: usize = 100;
+
+/// Strips the metadata blocks from the audio stream and sends every new `StreamTitle`.
+pub struct IcyMetadataSource<R> {
+    inner: R,
+    /// Audio bytes between two metadata blocks.
+    metaint: usize,
+    /// Audio bytes left until the next metadata block.
+    audio_left: usize,
+    stream_title: Option<String>,
+    titles_tx: Sender<String>,
+}
+
+impl<R: Read> IcyMetadataSource<R> {
+    pub const fn new(inner: R, metaint: usize, titles_tx: Sender<String>) -> Self {
+        Self {
+            inner,
+            metaint,
+            audio_left: metaint,
+            stream_title: None,
+            titles_tx,
+        }
+    }
+
+    /// Reads the metadata block, returns false at the end of the stream.
+    fn read_metadata(&mut self) -> io::Result<bool> {
+        let mut len = [0];
+        if self.inner.read(&mut len)? == 0 {
+            return Ok(false);
+        }
+        if len[0] > 0 {
+            let mut block = vec![0; usize::from(len[0]) * 16];
+            self.inner.read_exact(&mut block)?;
+            if let Some(title) = parse_stream_title(&block) {
+                if self.stream_title.as_ref() != Some(&title) {
+                    debug!("Stream title changed to {title}");
+                    _ = self.titles_tx.send(title.clone());
+                    self.stream_title = Some(title);
+                }
+            }
+        }
+        self.audio_left = self.metaint;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for IcyMetadataSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.audio_left == 0 && !self.read_metadata()? {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.audio_left);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.audio_left -= read;
+        Ok(read)
+    }
+}
+
+/// Inserts a metadata block after every `metaint` audio bytes, the title is sent only when it changed.
+pub struct IcyMetadataWriter {
+    metaint: usize,
+    /// Audio bytes left until the next metadata block.
+    audio_left: usize,
+    sent_title: Option<String>,
+}
+
+impl IcyMetadataWriter {
+    pub const fn new(metaint: usize) -> Self {
+        Self {
+            metaint,
+            audio_left: metaint,
+            sent_title: None,
+        }
+    }
+
+    pub fn write(&mut self, audio: &[u8], stream_title: &str) -> Vec<u8> {
+        let mut out = Vec::with_capacity(audio.len() + 1);
+        let mut rest = audio;
+        while rest.len() >= self.audio_left {
+            let (part, next) = rest.split_at(self.audio_left);
+            out.extend_from_slice(part);
+            out.extend(self.metadata_block(stream_title));
+            self.audio_left = self.metaint;
+            rest = next;
+        }
+        out.extend_from_slice(rest);
+        self.audio_left -= rest.len();
+        out
+    }
+
+    /// The length in 16 byte units followed by the padded text, a single zero when there is nothing new.
+    fn metadata_block(&mut self, stream_title: &str) -> Vec<u8> {
+        if self.sent_title.as_deref() == Some(stream_title) {
+            return vec![0];
+        }
+        self.sent_title = Some(stream_title.to_string());
+        // The block can't be longer than 255 units.
+        let title: String = stream_title.chars().take(1000).collect();
+        let mut block = format!("StreamTitle='{title}';").into_bytes();
+        let units = block.len().div_ceil(16);
+        block.resize(units * 16, 0);
+        #[allow(clippy::cast_possible_truncation)]
+        block.insert(0, units as u8);
+        block
+    }
+}
+
+/// The title from a metadata block like `StreamTitle='Artist - Title';StreamUrl='';`, `None` when it is empty.
+fn parse_stream_title(block: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(block);
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let text = &text[start..];
+    // The title itself may contain quotes, it ends with the quote closing the field.
+    let end = text.find("';").or_else(|| text.rfind('\''))?;
+    let title = text[..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// Song currently played by the station, the title is split into artist and title when it has both.
+pub fn stream_title_song(station: &Song, stream_title: &str) -> Song {
+    let (artist, title) = match stream_title.split_once(" - ") {
+        Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+            (Some(artist.trim().to_string()), title.trim().to_string())
+        }
+        _ => (None, stream_title.to_string()),
+    };
+    let mut song = Song {
+        title: Some(title),
+        artist,
+        album: station.title.clone(),
+        genre: station.genre.clone(),
+        file: station.file.clone(),
+        ..Default::default()
+    };
+    song.tags.insert(STREAM_TITLE_TAG.to_string(), stream_title.to_string());
+    song
+}
+
+/// Titles played by each radio station, oldest first.
+#[derive(Clone)]
+pub struct RadioHistory {
+    db: Db,
+    tree: Tree,
+}
+
+impl RadioHistory {
+    pub fn new(db: &Db) -> Self {
+        Self {
+            db: db.clone(),
+            tree: db
+                .open_tree("radio_history")
+                .expect("Failed to open radio history tree"),
+        }
+    }
+
+    /// Records the stream title of a radio song, other songs are ignored.
+    pub fn record(&self, song: &Song) {
+        let Some(stream_title) = song.tags.get(STREAM_TITLE_TAG) else {
+            return;
+        };
+        if self.titles(&song.file).last() == Some(stream_title) {
+            return;
+        }
+        let Ok(id) = self.db.generate_id() else {
+            return;
+        };
+        let prefix = Self::key_prefix(&song.file);
+        let mut key = prefix.clone();
+        key.extend_from_slice(&id.to_be_bytes());
+        _ = self.tree.insert(key, stream_title.as_bytes());
+        let len = self.tree.scan_prefix(&prefix).count();
+        self.tree
+            .scan_prefix(&prefix)
+            .keys()
+            .filter_map(Result::ok)
+            .take(len.saturating_sub(HISTORY_LEN))
+            .for_each(|key| _ = self.tree.remove(key));
+    }
+
+    pub fn titles(&self, station_url: &str) -> Vec<String> {
+        self.tree
+            .scan_prefix(Self::key_prefix(station_url))
+            .values()
+            .filter_map(Result::ok)
+            .map(|title| String::from_utf8_lossy(&title).to_string())
+            .collect()
+    }
+
+    fn key_prefix(station_url: &str) -> Vec<u8> {
+        let mut prefix = station_url.as_bytes().to_vec();
+        prefix.push(0);
+        prefix
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::sync::mpsc;
+
+    use api_models::player::Song;
+
+    use super::{parse_stream_title, stream_title_song, IcyMetadataSource, RadioHistory, HISTORY_LEN};
+
+    #[test]
+    fn should_strip_metadata_blocks_from_audio() {
+        let block = b"StreamTitle='Artist - It''s a title';StreamUrl='';";
+        let mut stream = b"abcd".to_vec();
+        stream.push(u8::try_from(block.len().div_ceil(16)).unwrap());
+        stream.extend_from_slice(block);
+        stream.resize(stream.len() + (16 - block.len() % 16) % 16, 0);
+        stream.extend_from_slice(b"efgh");
+        stream.push(0);
+        stream.extend_from_slice(b"ij");
+        let (titles_tx, titles_rx) = mpsc::channel();
+
+        let mut audio = vec![];
+        IcyMetadataSource::new(stream.as_slice(), 4, titles_tx)
+            .read_to_end(&mut audio)
+            .unwrap();
+
+        assert_eq!(audio, b"abcdefghij");
+        assert_eq!(titles_rx.try_iter().collect::<Vec<_>>(), ["Artist - It''s a title"]);
+    }
+
+    #[test]
+    fn should_split_stream_title_into_artist_and_title() {
+        let station = Song {
+            title: Some("Radio".to_string()),
+            file: "http://radio".to_string(),
+            ..Default::default()
+        };
+        let song = stream_title_song(&station, "Artist - Title - Live");
+        assert_eq!(song.artist.as_deref(), Some("Artist"));
+        assert_eq!(song.title.as_deref(), Some("Title - Live"));
+        assert_eq!(song.album.as_deref(), Some("Radio"));
+        assert_eq!(stream_title_song(&station, "News").artist, None);
+        assert_eq!(parse_stream_title(b"StreamTitle='';\0\0"), None);
+    }
+
+    #[test]
+    fn should_record_latest_titles_per_station() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let history = RadioHistory::new(&db);
+        let station = Song {
+            file: "http://radio".to_string(),
+            ..Default::default()
+        };
+        for title in (0..=HISTORY_LEN)
+            .map(|i| i.to_string())
+            .chain(["last".to_string(), "last".to_string()])
+        {
+            history.record(&stream_title_song(&station, &title));
+        }
+        history.record(&station);
+
+        let titles = history.titles("http://radio");
+        assert_eq!(titles.len(), HISTORY_LEN);
+        assert_eq!(titles.first().map(String::as_str), Some("2"));
+        assert_eq!(titles.last().map(String::as_str), Some("last"));
+        assert!(history.titles("http://radio2").is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! Peak and RMS levels and a coarse spectrum of the played audio, for meters and visualisations.
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use api_models::state::{AudioLevels, StateChangeEvent};
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+use tokio::sync::broadcast::Sender;
+
+use super::symphonia::to_f32_buffer;
+
+const FFT_LEN: usize = 1024;
+const SPECTRUM_BANDS: usize = 16;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20_000.0;
+/// Levels below are reported as silence.
+const MIN_DB: f32 = -90.0;
+
+/// Measures the written buffers while a client subscribed to the levels, and sends them at the
+/// configured rate.
+pub struct LevelMeter {
+    subscribed: Arc<AtomicBool>,
+    rate_hz: u32,
+    changes_tx: Sender<StateChangeEvent>,
+    /// Frames measured since the levels were last sent.
+    frames: usize,
+    peak: Vec<f32>,
+    square_sum: Vec<f32>,
+    /// The latest `FFT_LEN` frames mixed down to mono, the oldest one at `history_pos`.
+    history: Vec<f32>,
+    history_pos: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    fft_input: Vec<f32>,
+    fft_output: Vec<Complex<f32>>,
+}
+
+impl LevelMeter {
+    pub fn new(subscribed: &Arc<AtomicBool>, rate_hz: u32, changes_tx: &Sender<StateChangeEvent>) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_LEN);
+        Self {
+            subscribed: subscribed.clone(),
+            rate_hz: rate_hz.max(1),
+            changes_tx: changes_tx.clone(),
+            frames: 0,
+            peak: vec![],
+            square_sum: vec![],
+            history: vec![0.0; FFT_LEN],
+            history_pos: 0,
+            fft_input: fft.make_input_vec(),
+            fft_output: fft.make_output_vec(),
+            fft,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.subscribed.load(Ordering::Relaxed)
+    }
+
+    pub fn measure(&mut self, buffer: &AudioBufferRef<'_>) {
+        if !self.is_active() {
+            return;
+        }
+        match buffer {
+            AudioBufferRef::F32(buffer) => self.measure_f32(buffer),
+            _ => self.measure_f32(&to_f32_buffer(buffer)),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn measure_f32(&mut self, buffer: &AudioBuffer<f32>) {
+        let channels = buffer.spec().channels.count();
+        if self.peak.len() != channels {
+            self.peak = vec![0.0; channels];
+            self.square_sum = vec![0.0; channels];
+            self.frames = 0;
+        }
+        for channel in 0..channels {
+            for &sample in buffer.chan(channel) {
+                self.peak[channel] = self.peak[channel].max(sample.abs());
+                self.square_sum[channel] += sample * sample;
+            }
+        }
+        for frame in 0..buffer.frames() {
+            let mono = (0..channels).map(|channel| buffer.chan(channel)[frame]).sum::<f32>() / channels as f32;
+            self.history[self.history_pos] = mono;
+            self.history_pos = (self.history_pos + 1) % FFT_LEN;
+        }
+        self.frames += buffer.frames();
+        let rate = buffer.spec().rate;
+        if self.frames >= (rate / self.rate_hz) as usize {
+            let levels = self.levels(rate);
+            self.changes_tx.send(StateChangeEvent::AudioLevelsEvent(levels)).ok();
+            self.peak.fill(0.0);
+            self.square_sum.fill(0.0);
+            self.frames = 0;
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn levels(&mut self, rate: u32) -> AudioLevels {
+        // Hann windowed history, oldest frame first.
+        let samples = self.history[self.history_pos..]
+            .iter()
+            .chain(&self.history[..self.history_pos]);
+        for (i, (input, sample)) in self.fft_input.iter_mut().zip(samples).enumerate() {
+            *input = sample * 0.5 * (1.0 - (2.0 * PI * i as f32 / FFT_LEN as f32).cos());
+        }
+        // Fails only for buffers of other lengths than the planned one.
+        _ = self.fft.process(&mut self.fft_input, &mut self.fft_output);
+        AudioLevels {
+            peak_db: self.peak.iter().map(|&peak| to_db(peak)).collect(),
+            rms_db: self
+                .square_sum
+                .iter()
+                .map(|&sum| to_db((sum / self.frames.max(1) as f32).sqrt()))
+                .collect(),
+            spectrum_db: spectrum(&self.fft_output, rate),
+        }
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(MIN_DB)
+}
+
+/// Highest amplitude within each of the logarithmically spaced frequency bands of the FFT bins.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn spectrum(bins: &[Complex<f32>], rate: u32) -> Vec<f32> {
+    // A full scale sine reaches a quarter of the length with the Hann window.
+    let amplitudes: Vec<f32> = bins.iter().map(|bin| bin.norm() * 4.0 / FFT_LEN as f32).collect();
+    let bin_width = rate as f32 / FFT_LEN as f32;
+    let band_ratio = (MAX_FREQUENCY / MIN_FREQUENCY).powf(1.0 / SPECTRUM_BANDS as f32);
+    let last_bin = amplitudes.len() - 1;
+    (0..SPECTRUM_BANDS)
+        .map(|band| {
+            let low = MIN_FREQUENCY * band_ratio.powf(band as f32);
+            let first = ((low / bin_width) as usize).min(last_bin);
+            let last = (((low * band_ratio) / bin_width).ceil() as usize).clamp(first + 1, last_bin + 1);
+            to_db(amplitudes[first..last].iter().copied().fold(0.0, f32::max))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::f32::consts::PI;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use api_models::state::StateChangeEvent;
+    use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+    use tokio::sync::broadcast;
+
+    use super::{LevelMeter, MIN_DB};
+
+    #[test]
+    fn should_measure_levels_and_spectrum_of_sine() {
+        let (changes_tx, mut changes_rx) = broadcast::channel(10);
+        let subscribed = Arc::new(AtomicBool::new(false));
+        let mut meter = LevelMeter::new(&subscribed, 10, &changes_tx);
+        let mut buffer = AudioBuffer::<f32>::new(
+            4410,
+            SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+        );
+        buffer.render_reserved(None);
+        for (i, sample) in (0u16..).zip(buffer.chan_mut(0)) {
+            *sample = (2.0 * PI * 1000.0 * f32::from(i) / 44100.0).sin();
+        }
+
+        meter.measure(&AudioBufferRef::F32(Cow::Borrowed(&buffer)));
+        assert!(changes_rx.try_recv().is_err());
+        subscribed.store(true, Ordering::Relaxed);
+        meter.measure(&AudioBufferRef::F32(Cow::Borrowed(&buffer)));
+
+        let Ok(StateChangeEvent::AudioLevelsEvent(levels)) = changes_rx.try_recv() else {
+            panic!("levels not sent");
+        };
+        assert!(levels.peak_db[0].abs() < 0.1);
+        assert!((levels.rms_db[0] + 3.0).abs() < 0.1);
+        assert!((levels.peak_db[1] - MIN_DB).abs() < f32::EPSILON);
+        let loudest_band = (0..levels.spectrum_db.len())
+            .max_by(|&a, &b| levels.spectrum_db[a].total_cmp(&levels.spectrum_db[b]))
+            .unwrap();
+        // The 1 kHz tone is in the band from 974 Hz to 1.5 kHz.
+        assert_eq!(loudest_band, 9);
+        assert!(levels.spectrum_db[3] < levels.spectrum_db[9] - 40.0);
+    }
+}
@@ -1,5 +1,23 @@
+mod alsa_output;
+mod channel_mixer;
+mod convolution;
+mod crossfade;
+mod dsd;
+pub mod dsp;
+mod flac_encoder;
+mod headless;
+mod http_source;
+pub mod http_stream;
+mod icy;
+mod level_meter;
+mod mpegts;
 mod output;
+mod radio;
+mod ram_loader;
+mod replay_gain;
+mod resampler;
 mod symphonia;
+mod volume;
 pub mod player_service;
-// #[cfg(test)]
-// mod test;
+#[cfg(test)]
+mod test;
@@ -0,0 +1,144 @@
+//! Extracts the audio elementary stream from the MPEG transport stream segments of HLS streams.
+const PACKET_LEN: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+/// Stream types of ADTS AAC and MPEG audio, which are read from the elementary stream as they are.
+const AUDIO_STREAM_TYPES: [u8; 3] = [0x0F, 0x03, 0x04];
+
+/// Keeps the program tables across the segments of a stream.
+#[derive(Default)]
+pub struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+}
+
+impl TsDemuxer {
+    pub fn is_transport_stream(data: &[u8]) -> bool {
+        data.len() >= PACKET_LEN
+            && data[0] == SYNC_BYTE
+            && data.get(PACKET_LEN).copied().unwrap_or(SYNC_BYTE) == SYNC_BYTE
+    }
+
+    /// Appends the payload of the first audio stream to `audio`.
+    pub fn demux(&mut self, data: &[u8], audio: &mut Vec<u8>) {
+        for packet in data.chunks_exact(PACKET_LEN).filter(|packet| packet[0] == SYNC_BYTE) {
+            let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+            let unit_start = packet[1] & 0x40 != 0;
+            let adaptation_field = (packet[3] >> 4) & 0x03;
+            if adaptation_field & 0x01 == 0 {
+                continue;
+            }
+            let start = if adaptation_field & 0x02 == 0 {
+                4
+            } else {
+                5 + usize::from(packet[4])
+            };
+            let Some(payload) = packet.get(start..) else {
+                continue;
+            };
+            if pid == PAT_PID {
+                self.pmt_pid = section(payload, unit_start).and_then(parse_pat).or(self.pmt_pid);
+            } else if Some(pid) == self.pmt_pid {
+                self.audio_pid = section(payload, unit_start).and_then(parse_pmt).or(self.audio_pid);
+            } else if Some(pid) == self.audio_pid {
+                audio.extend_from_slice(if unit_start { pes_payload(payload) } else { payload });
+            }
+        }
+    }
+}
+
+/// The table section starting in the payload, `None` when the payload continues a section.
+fn section(payload: &[u8], unit_start: bool) -> Option<&[u8]> {
+    if !unit_start {
+        return None;
+    }
+    let pointer = usize::from(*payload.first()?);
+    payload.get(1 + pointer..)
+}
+
+/// Entries of a table section between its header and the CRC.
+fn section_entries(section: &[u8], header_len: usize) -> Option<&[u8]> {
+    let section_len = usize::from(u16::from_be_bytes([section.get(1)? & 0x0F, *section.get(2)?]));
+    section.get(header_len..(3 + section_len).checked_sub(4)?)
+}
+
+/// PID of the program map table of the first program.
+fn parse_pat(section: &[u8]) -> Option<u16> {
+    section_entries(section, 8)?
+        .chunks_exact(4)
+        .find(|program| u16::from_be_bytes([program[0], program[1]]) != 0)
+        .map(|program| u16::from_be_bytes([program[2] & 0x1F, program[3]]))
+}
+
+/// PID of the first audio stream of the program.
+fn parse_pmt(section: &[u8]) -> Option<u16> {
+    let program_info_len = usize::from(u16::from_be_bytes([section.get(10)? & 0x0F, *section.get(11)?]));
+    let mut streams = section_entries(section, 12 + program_info_len)?;
+    while streams.len() >= 5 {
+        let pid = u16::from_be_bytes([streams[1] & 0x1F, streams[2]]);
+        if AUDIO_STREAM_TYPES.contains(&streams[0]) {
+            return Some(pid);
+        }
+        let es_info_len = usize::from(u16::from_be_bytes([streams[3] & 0x0F, streams[4]]));
+        streams = streams.get(5 + es_info_len..)?;
+    }
+    None
+}
+
+/// Payload of the packet starting a PES packet, without the PES header.
+fn pes_payload(payload: &[u8]) -> &[u8] {
+    if payload.len() >= 9 && payload.starts_with(&[0, 0, 1]) {
+        payload.get(9 + usize::from(payload[8])..).unwrap_or_default()
+    } else {
+        payload
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TsDemuxer, PACKET_LEN};
+
+    #[test]
+    fn should_extract_audio_stream_from_transport_stream() {
+        let mut segment = vec![];
+        // PAT with program 1 mapped to PID 0x100.
+        segment.extend(packet(
+            0,
+            true,
+            &[0, 0x00, 0xB0, 13, 0, 1, 0xC1, 0, 0, 0, 1, 0xE1, 0x00, 0, 0, 0, 0],
+        ));
+        // PMT with a video stream on PID 0x101 and an ADTS AAC stream on PID 0x102.
+        segment.extend(packet(
+            0x100,
+            true,
+            &[
+                0, 0x02, 0xB0, 23, 0, 1, 0xC1, 0, 0, 0xE1, 0x01, 0xF0, 0, 0x1B, 0xE1, 0x01, 0xF0, 0, 0x0F, 0xE1, 0x02,
+                0xF0, 0, 0, 0, 0,
+            ],
+        ));
+        segment.extend(packet(0x101, true, b"video"));
+        segment.extend(packet(
+            0x102,
+            true,
+            &[0, 0, 1, 0xC0, 0, 0, 0x80, 0x80, 5, 1, 2, 3, 4, 5, b'a', b'b'],
+        ));
+        segment.extend(packet(0x102, false, b"cd"));
+        assert!(TsDemuxer::is_transport_stream(&segment));
+
+        let mut audio = vec![];
+        TsDemuxer::default().demux(&segment, &mut audio);
+
+        assert_eq!(audio, b"abcd");
+    }
+
+    /// Transport stream packet with the payload at its end, after an adaptation field used as stuffing.
+    fn packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let [pid_high, pid_low] = pid.to_be_bytes();
+        let stuffing = PACKET_LEN - 5 - payload.len();
+        let mut packet = vec![0x47, pid_high | if unit_start { 0x40 } else { 0 }, pid_low, 0x30];
+        packet.push(u8::try_from(stuffing).unwrap());
+        packet.resize(5 + stuffing, 0xFF);
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
@@ -7,20 +7,69 @@
 
 //! Platform-dependant Audio Outputs
 
-use anyhow::Result;
+use std::fmt;
+
+use anyhow::{format_err, Result};
+use api_models::common::OutputBackend;
 use api_models::settings::RsPlayerSettings;
 use log::info;
 use symphonia::core::audio::{AudioBufferRef, SignalSpec};
 
+use super::alsa_output::AlsaAudioOutput;
+use super::headless::{NullOutput, WavFileOutput};
+use super::resampler::ResamplingOutput;
+
+/// Failure of the audio output or its device. Unlike the errors of a song it stops the playback,
+/// as the songs after it would fail the same way.
+#[derive(Debug)]
+pub struct OutputError(pub String);
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for OutputError {}
+
 pub trait AudioOutput {
     fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()>;
+    /// Blocks until all buffered samples are played and pauses the output.
     fn flush(&mut self);
+    fn spec(&self) -> SignalSpec;
+    /// The last written samples reached the device unchanged.
+    fn is_bit_perfect(&self) -> bool {
+        false
+    }
+    /// Bits of the signed integer samples the device is written with unconverted, `None` for
+    /// other sample formats.
+    fn integer_bits(&self) -> Option<u32> {
+        None
+    }
+    /// Stops playing without dropping any written samples, outputs that can't hold them play
+    /// them out first.
+    fn pause(&mut self) {
+        self.flush();
+    }
+    /// Continues playing the samples held by `pause`.
+    fn resume(&mut self) {}
+}
+
+/// Pauses or resumes the output of the playback thread, if it is open.
+pub fn set_paused(audio_output: &mut Option<Box<dyn AudioOutput>>, paused: bool) {
+    if let Some(out) = audio_output.as_mut() {
+        if paused {
+            out.pause();
+        } else {
+            out.resume();
+        }
+    }
 }
 
 mod cpal {
 
-    use std::process::exit;
-    use std::time::Duration;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     use super::AudioOutput;
 
@@ -40,13 +89,19 @@ mod cpal {
     trait AudioOutputSample:
         cpal::Sample + cpal::SizedSample + ConvertibleSample + RawSample + std::marker::Send + 'static
     {
+        /// Bits of the sample if it is a signed integer.
+        const INTEGER_BITS: Option<u32> = None;
     }
 
     impl AudioOutputSample for f32 {}
-    impl AudioOutputSample for i16 {}
+    impl AudioOutputSample for i16 {
+        const INTEGER_BITS: Option<u32> = Some(16);
+    }
     impl AudioOutputSample for u16 {}
     impl AudioOutputSample for u32 {}
-    impl AudioOutputSample for i32 {}
+    impl AudioOutputSample for i32 {
+        const INTEGER_BITS: Option<u32> = Some(32);
+    }
 
     impl CpalAudioOutput {
         pub fn try_open(
@@ -55,12 +110,7 @@ mod cpal {
             audio_device: &str,
             rsp_settings: &RsPlayerSettings,
         ) -> Result<Box<dyn AudioOutput>> {
-            // Get default host.
-            let host = cpal::default_host();
-            let device = host
-                .devices()?
-                .find(|d| d.name().unwrap_or_default() == audio_device)
-                .ok_or_else(|| Error::msg(format!("Device {audio_device} not found!")))?;
+            let device = find_device(audio_device)?;
             debug!("Spec: {:?}", spec);
 
             let config = match device.default_output_config() {
@@ -81,15 +131,50 @@ mod cpal {
                 _ => panic!("Unsupported sample format!"),
             }
         }
+
+        /// Opens the device with 32 bit integer samples regardless of its default config, so
+        /// the samples reach it unconverted.
+        pub fn try_open_integer(
+            spec: SignalSpec,
+            duration: u64,
+            audio_device: &str,
+            rsp_settings: &RsPlayerSettings,
+        ) -> Result<Box<dyn AudioOutput>> {
+            let device = find_device(audio_device)?;
+            let supported = device.supported_output_configs()?.any(|range| {
+                range.sample_format() == cpal::SampleFormat::I32
+                    && usize::from(range.channels()) == spec.channels.count()
+                    && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&spec.rate)
+            });
+            if !supported {
+                return Err(Error::msg(format!(
+                    "Device {audio_device} does not play 32 bit integer samples at {} Hz",
+                    spec.rate
+                )));
+            }
+            CpalAudioOutputImpl::<i32>::try_open(spec, duration, &device, rsp_settings)
+        }
+    }
+
+    fn find_device(audio_device: &str) -> Result<cpal::Device> {
+        cpal::default_host()
+            .devices()?
+            .find(|d| d.name().unwrap_or_default() == audio_device)
+            .ok_or_else(|| Error::msg(format!("Device {audio_device} not found!")))
     }
 
     struct CpalAudioOutputImpl<T>
     where
         T: AudioOutputSample,
     {
+        ring_buf: SpscRb<T>,
         ring_buf_producer: rb::Producer<T>,
         sample_buf: SampleBuffer<T>,
         stream: cpal::Stream,
+        /// Failure reported by the stream, returned by the next write.
+        stream_error: Arc<Mutex<Option<cpal::StreamError>>>,
+        spec: SignalSpec,
+        drain_timeout: Duration,
     }
 
     impl<T: AudioOutputSample> CpalAudioOutputImpl<T> {
@@ -116,6 +201,8 @@ mod cpal {
 
             let ring_buf = SpscRb::new(ring_len);
             let (ring_buf_producer, ring_buf_consumer) = (ring_buf.producer(), ring_buf.consumer());
+            let stream_error = Arc::new(Mutex::new(None));
+            let callback_error = stream_error.clone();
 
             let stream_result = device.build_output_stream(
                 &config,
@@ -128,7 +215,7 @@ mod cpal {
                 },
                 move |err| {
                     error!("audio output error: {}", err);
-                    exit(299);
+                    *callback_error.lock().unwrap() = Some(err);
                 },
                 None,
             );
@@ -149,20 +236,34 @@ mod cpal {
             let sample_buf = SampleBuffer::<T>::new(duration, spec);
 
             Ok(Box::new(CpalAudioOutputImpl {
+                ring_buf,
                 ring_buf_producer,
                 sample_buf,
                 stream,
+                stream_error,
+                spec,
+                drain_timeout: Duration::from_millis(rsp_settings.ring_buffer_size_ms as u64 * 2),
             }))
         }
     }
 
     impl<T: AudioOutputSample> AudioOutput for CpalAudioOutputImpl<T> {
         fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            let stream_error = self.stream_error.lock().unwrap().take();
+            if let Some(err) = stream_error {
+                return Err(err.into());
+            }
             // Do nothing if there are no audio frames.
             if decoded.frames() == 0 {
                 return Ok(());
             }
 
+            // The output is shared by consecutive songs whose decoders may produce bigger buffers
+            // than the one the output was opened with.
+            if decoded.capacity() * self.spec.channels.count() > self.sample_buf.capacity() {
+                self.sample_buf = SampleBuffer::<T>::new(decoded.capacity() as u64, self.spec);
+            }
+
             // Audio samples must be interleaved for cpal. Interleave the samples in the audio
             // buffer into the sample buffer.
             self.sample_buf.copy_interleaved_ref(decoded);
@@ -181,9 +282,31 @@ mod cpal {
         }
 
         fn flush(&mut self) {
+            // Let the stream play out whatever is left in the ring buffer before pausing it.
+            let start = Instant::now();
+            while !self.ring_buf.is_empty() && start.elapsed() < self.drain_timeout {
+                std::thread::sleep(Duration::from_millis(10));
+            }
             // Flush is best-effort, ignore the returned result.
             _ = self.stream.pause();
         }
+
+        fn pause(&mut self) {
+            // The samples left in the ring buffer are played once the stream is resumed.
+            _ = self.stream.pause();
+        }
+
+        fn resume(&mut self) {
+            _ = self.stream.play();
+        }
+
+        fn spec(&self) -> SignalSpec {
+            self.spec
+        }
+
+        fn integer_bits(&self) -> Option<u32> {
+            T::INTEGER_BITS
+        }
     }
 }
 
@@ -193,13 +316,68 @@ pub fn try_open(
     audio_device: &str,
     rsp_settings: &RsPlayerSettings,
 ) -> Result<Box<dyn AudioOutput>> {
-    let result = cpal::CpalAudioOutput::try_open(spec, duration, audio_device, rsp_settings);
+    match rsp_settings.resample_rate {
+        Some(rate) if rate != spec.rate => {
+            let out_spec = SignalSpec::new(rate, spec.channels);
+            let out_duration = duration * u64::from(rate) / u64::from(spec.rate) + 1;
+            let inner = try_open_device(out_spec, out_duration, audio_device, rsp_settings)?;
+            Ok(Box::new(ResamplingOutput::new(inner, spec)?))
+        }
+        _ => try_open_device(spec, duration, audio_device, rsp_settings),
+    }
+}
+
+/// Opens the device for DSD over PCM. The markers survive only when the samples are written
+/// unconverted as integers of at least 24 bits, which the `null:` output doesn't play.
+pub fn try_open_dop(
+    spec: SignalSpec,
+    duration: u64,
+    audio_device: &str,
+    rsp_settings: &RsPlayerSettings,
+) -> Result<Box<dyn AudioOutput>> {
+    let output = if let Some(path) = audio_device.strip_prefix("file:") {
+        WavFileOutput::try_open(spec, duration, path)?
+    } else if audio_device.starts_with("null:") {
+        return Err(format_err!("{audio_device} does not play DSD over PCM"));
+    } else {
+        match rsp_settings.output_backend {
+            OutputBackend::Cpal => cpal::CpalAudioOutput::try_open_integer(spec, duration, audio_device, rsp_settings)?,
+            OutputBackend::Alsa => AlsaAudioOutput::try_open(spec, duration, audio_device, rsp_settings)?,
+        }
+    };
+    match output.integer_bits() {
+        Some(bits) if bits >= 24 => Ok(output),
+        bits => Err(format_err!(
+            "{audio_device} is not written with integer samples of at least 24 bits: {bits:?}"
+        )),
+    }
+}
+
+/// Opens the device with the signal spec, without resampling. The `null:` and `file:` devices
+/// play without a sound card.
+pub fn try_open_device(
+    spec: SignalSpec,
+    duration: u64,
+    audio_device: &str,
+    rsp_settings: &RsPlayerSettings,
+) -> Result<Box<dyn AudioOutput>> {
+    if let Some(mode) = audio_device.strip_prefix("null:") {
+        return Ok(Box::new(NullOutput::new(spec, mode != "fast")));
+    }
+    if let Some(path) = audio_device.strip_prefix("file:") {
+        return WavFileOutput::try_open(spec, duration, path);
+    }
+    let open = match rsp_settings.output_backend {
+        OutputBackend::Cpal => cpal::CpalAudioOutput::try_open,
+        OutputBackend::Alsa => AlsaAudioOutput::try_open,
+    };
+    let result = open(spec, duration, audio_device, rsp_settings);
     if result.is_err() && audio_device.starts_with("hw:") {
         info!(
             "Failed to open audio output {}. Trying with plughw: prefix.",
             audio_device
         );
-        return cpal::CpalAudioOutput::try_open(spec, duration, &audio_device.replace("hw:", "plughw:"), rsp_settings);
+        return open(spec, duration, &audio_device.replace("hw:", "plughw:"), rsp_settings);
     }
     result
 }
@@ -1,38 +1,66 @@
 use log::{debug, error, info, warn};
 use sled::Db;
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicBool, AtomicU16, Ordering},
+    atomic::{AtomicBool, Ordering},
     Arc, Mutex,
 };
 use std::thread::JoinHandle;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use thread_priority::{ThreadBuilder, ThreadPriority};
 use tokio::sync::broadcast::Sender;
 
 use api_models::{
-    settings::{RsPlayerSettings, Settings},
-    state::{PlayerState, StateChangeEvent},
+    common::{HttpStreamMode, RepeatMode, SeekTarget, StopAfter, Volume},
+    player::Song,
+    podcast::Episode,
+    settings::{DspSettings, RsPlayerSettings, Settings},
+    state::{AudioOut, PlayerState, SongProgress, StateChangeEvent},
 };
+use rsplayer_metadata::dsd::is_dsd_file;
 use rsplayer_metadata::metadata_service::MetadataService;
-use rsplayer_metadata::queue_service::QueueService;
+use rsplayer_metadata::podcast_service::PodcastService;
+use rsplayer_metadata::queue_service::{QueueEntry, QueueService};
 
-use super::symphonia::PlaybackResult;
+use super::crossfade::{is_same_album_sequence, CrossfadeTrack};
+use super::dsp::{DspChain, DspControl};
+use super::http_stream::HttpStream;
+use super::icy::RadioHistory;
+use super::level_meter::LevelMeter;
+use super::output::{AudioOutput, OutputError};
+use super::ram_loader::RamLoader;
+use super::replay_gain;
+use super::symphonia::{PlaybackResult, SeekRequest};
+use super::volume::VolumeControl;
 
 pub struct PlayerService {
     state_db: Db,
     queue_service: Arc<QueueService>,
     #[allow(dead_code)]
     metadata_service: Arc<MetadataService>,
+    podcast_service: Arc<PodcastService>,
     playback_thread_handle: Arc<Mutex<Option<JoinHandle<PlaybackResult>>>>,
     stop_signal: Arc<AtomicBool>,
-    skip_to_time: Arc<AtomicU16>,
+    pause_signal: Arc<AtomicBool>,
+    seek_request: SeekRequest,
     audio_device: String,
     rsp_settings: RsPlayerSettings,
     music_dir: String,
     changes_tx: Sender<StateChangeEvent>,
+    dsp_control: DspControl,
+    radio_history: RadioHistory,
+    /// Set while a client subscribed to the audio levels.
+    audio_levels_subscribed: Arc<AtomicBool>,
+    http_stream: HttpStream,
+    ram_loader: RamLoader,
+    /// The first song played after the start resumes where it was left.
+    resume_pending: AtomicBool,
 }
 const LAST_SONG_PAUSED_KEY: &str = "last_song_paused";
-const LAST_SONG_PROGRESS_KEY: &str = "last_played_song_progress";
+/// Milliseconds the last played song was left at, followed by its file.
+const LAST_SONG_PROGRESS_KEY: &str = "last_played_song_position";
+/// Podcast episodes stopped this close to their end count as played.
+const EPISODE_END_MARGIN: Duration = Duration::from_secs(15);
 
 impl PlayerService {
     #[must_use]
@@ -40,24 +68,84 @@ impl PlayerService {
         settings: &Settings,
         metadata_service: Arc<MetadataService>,
         queue_service: Arc<QueueService>,
+        podcast_service: Arc<PodcastService>,
         state_changes_tx: Sender<StateChangeEvent>,
     ) -> Self {
         let db = sled::open("player_state").expect("Failed to open queue db");
+        Self::with_state_db(
+            db,
+            settings,
+            metadata_service,
+            queue_service,
+            podcast_service,
+            state_changes_tx,
+        )
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub(super) fn with_state_db(
+        db: Db,
+        settings: &Settings,
+        metadata_service: Arc<MetadataService>,
+        queue_service: Arc<QueueService>,
+        podcast_service: Arc<PodcastService>,
+        state_changes_tx: Sender<StateChangeEvent>,
+    ) -> Self {
         let state_db = db.clone();
         let mut rx = state_changes_tx.subscribe();
         let state_tx = state_changes_tx.clone();
+        let dsp_control = DspControl::new(
+            settings.dsp_settings.clone(),
+            settings.convolution_settings.clone(),
+            settings.output_channel_settings.clone(),
+            VolumeControl::new(&settings.volume_ctrl_settings),
+        );
+        let output_dsp_control = dsp_control.clone();
+        let radio_history = RadioHistory::new(&db);
+        let song_radio_history = radio_history.clone();
+        let http_stream = HttpStream::new(settings.rs_player_settings.http_stream_mode);
+        let song_http_stream = http_stream.clone();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let ram_loader = RamLoader::new(
+            settings.rs_player_settings.load_into_ram_mb,
+            &state_changes_tx,
+            &stop_signal,
+        );
+        let progress_podcast_service = podcast_service.clone();
         tokio::task::spawn(async move {
             let mut i = 0;
+            // File of the playing song, saved along with its position.
+            let mut current_file = String::new();
+            // Playing podcast episode, its position is saved along with the global one.
+            let mut current_episode = None;
             loop {
                 match rx.recv().await {
                     Ok(StateChangeEvent::SongTimeEvent(st)) => {
                         i += 1;
                         if i % 2 == 0 {
-                            let lt = st.current_time.as_secs().to_string();
-                            debug!("Save time state: {lt}");
-                            _ = state_db.insert(LAST_SONG_PROGRESS_KEY, lt.as_bytes());
+                            debug!("Save time state: {:?}", st.current_time);
+                            save_song_position(&state_db, &current_file, st.current_time);
+                            if let Some(episode) = current_episode.as_ref() {
+                                save_episode_progress(&progress_podcast_service, episode, &st);
+                            }
                         }
                     }
+                    Ok(StateChangeEvent::CurrentSongEvent(song)) => {
+                        current_file.clone_from(&song.file);
+                        song_radio_history.record(&song);
+                        song_http_stream.set_song(&song);
+                        current_episode = progress_podcast_service.find_song_episode(&song);
+                    }
+                    Ok(StateChangeEvent::SeekCompletedEvent(st)) => {
+                        save_song_position(&state_db, &current_file, st.current_time);
+                        if let Some(episode) = current_episode.as_ref() {
+                            save_episode_progress(&progress_podcast_service, episode, &st);
+                        }
+                    }
+                    Ok(StateChangeEvent::StreamerStateEvent(ss)) => {
+                        output_dsp_control.set_audio_output(ss.selected_audio_output);
+                        output_dsp_control.set_volume(&ss.volume_state);
+                    }
                     Ok(StateChangeEvent::PlaybackStateEvent(ps)) => {
                         debug!("Save player state: {:?}", ps);
                         match ps {
@@ -84,35 +172,50 @@ impl PlayerService {
                 }
             }
         });
-        let ps = PlayerService {
+        PlayerService {
             state_db: db,
             changes_tx: state_changes_tx,
             queue_service,
             metadata_service,
+            podcast_service,
             playback_thread_handle: Arc::new(Mutex::new(None)),
-            stop_signal: Arc::new(AtomicBool::new(false)),
-            skip_to_time: Arc::new(AtomicU16::new(0)),
-            audio_device: settings.alsa_settings.output_device.name.clone(),
+            stop_signal,
+            pause_signal: Arc::new(AtomicBool::new(false)),
+            seek_request: Arc::new(Mutex::new(None)),
+            // Nothing is played locally when the audio is only streamed.
+            audio_device: if settings.rs_player_settings.http_stream_mode == HttpStreamMode::StreamOnly {
+                "null:".to_string()
+            } else {
+                settings.alsa_settings.output_device.name.clone()
+            },
             rsp_settings: settings.rs_player_settings.clone(),
             music_dir: settings.metadata_settings.music_directory.clone(),
-        };
-        let last_played_song_progress = ps.get_last_played_song_time();
-        if last_played_song_progress > 0 {
-            ps.seek_current_song(last_played_song_progress);
+            dsp_control,
+            radio_history,
+            audio_levels_subscribed: Arc::new(AtomicBool::new(false)),
+            http_stream,
+            ram_loader,
+            resume_pending: AtomicBool::new(true),
         }
-        ps
     }
 
     pub fn play_from_current_queue_song(&self) {
-        if let Some(s) = self.queue_service.get_current_song() {
+        let song = self.queue_service.get_current_song();
+        if let Some(s) = song.as_ref() {
             self.metadata_service.increase_play_count(&s.file);
         }
-        if let Ok(Some(_)) = self.state_db.get(LAST_SONG_PAUSED_KEY) {
-            let last_song_time = self.get_last_played_song_time();
-            self.seek_current_song(last_song_time);
+        let resume = self.resume_pending.swap(false, Ordering::Relaxed)
+            || matches!(self.state_db.get(LAST_SONG_PAUSED_KEY), Ok(Some(_)));
+        if let Some(position) = song
+            .filter(|_| resume)
+            .and_then(|s| self.get_last_played_song_position(&s.file))
+        {
+            self.seek_current_song(SeekTarget::Millis(
+                u64::try_from(position.as_millis()).unwrap_or(u64::MAX),
+            ));
         }
 
-        *self.playback_thread_handle.lock().unwrap() = Some(self.play_all_in_queue());
+        *self.playback_thread_handle.lock().unwrap() = Some(self.play_all_in_queue(None));
     }
 
     pub fn play_next_song(&self) {
@@ -130,21 +233,67 @@ impl PlayerService {
     pub fn stop_current_song(&self) -> Option<PlaybackResult> {
         let start = SystemTime::now();
         self.stop_signal.store(true, Ordering::Relaxed);
-        let handle = self
-            .playback_thread_handle
-            .lock()
-            .unwrap()
-            .take();
+        let handle = self.playback_thread_handle.lock().unwrap().take();
         let mut result = Option::<PlaybackResult>::None;
-        if let Some(h) = handle{
+        if let Some(h) = handle {
+            // A paused playback thread is parked until it is woken up.
+            h.thread().unpark();
             result = h.join().ok();
         }
-        debug!("Stop finished after [{}] ms with result: {:?}", SystemTime::now().duration_since(start).unwrap().as_millis(), result);
+        self.pause_signal.store(false, Ordering::Relaxed);
+        debug!(
+            "Stop finished after [{}] ms with result: {:?}",
+            SystemTime::now().duration_since(start).unwrap().as_millis(),
+            result
+        );
         result
     }
-    #[allow(clippy::unused_self, clippy::missing_const_for_fn)]
-    pub fn seek_current_song(&self, seconds: u16) {
-        self.skip_to_time.store(seconds, Ordering::Relaxed);
+    /// Parks the playback thread with the song open, so it resumes exactly where it was paused.
+    pub fn pause_current_song(&self) {
+        if self.is_playback_thread_running() {
+            self.pause_signal.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns false when there is no paused song to resume.
+    pub fn resume_current_song(&self) -> bool {
+        let handle = self.playback_thread_handle.lock().unwrap();
+        match handle.as_ref() {
+            Some(h) if !h.is_finished() && self.pause_signal.swap(false, Ordering::Relaxed) => {
+                h.thread().unpark();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn is_playback_thread_running(&self) -> bool {
+        self.playback_thread_handle
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|h| !h.is_finished())
+    }
+
+    /// The seek is applied by the playback thread, or when the next song starts playing.
+    pub fn seek_current_song(&self, target: SeekTarget) {
+        *self.seek_request.lock().unwrap() = Some(target);
+    }
+
+    pub fn set_dsp_settings(&self, dsp_settings: DspSettings) {
+        self.dsp_control.set_settings(dsp_settings);
+    }
+
+    pub fn get_dsp_settings(&self) -> DspSettings {
+        self.dsp_control.get_settings()
+    }
+
+    pub fn set_audio_output(&self, audio_out: AudioOut) {
+        self.dsp_control.set_audio_output(audio_out);
+    }
+
+    pub fn set_volume(&self, volume: &Volume) {
+        self.dsp_control.set_volume(volume);
     }
 
     pub fn play_song(&self, song_id: &str) {
@@ -153,17 +302,30 @@ impl PlayerService {
         self.play_from_current_queue_song();
     }
 
-
-    fn play_all_in_queue(&self) -> JoinHandle<PlaybackResult> {
+    /// Plays the queue from its current song into the output, which is opened by the first song when `None`.
+    #[allow(clippy::too_many_lines)]
+    pub(super) fn play_all_in_queue(
+        &self,
+        audio_output: Option<Box<dyn AudioOutput + Send>>,
+    ) -> JoinHandle<PlaybackResult> {
         self.stop_signal.store(false, Ordering::Relaxed);
+        self.pause_signal.store(false, Ordering::Relaxed);
         let stop_signal = self.stop_signal.clone();
-        let skip_to_time = self.skip_to_time.clone();
+        let pause_signal = self.pause_signal.clone();
+        let seek_request = self.seek_request.clone();
         let queue = self.queue_service.clone();
         let audio_device = self.audio_device.clone();
         let playback_thread_prio = self.rsp_settings.player_threads_priority;
         let music_dir = self.music_dir.clone();
         let changes_tx = self.changes_tx.clone();
         let rsp_settings = self.rsp_settings.clone();
+        let dsp_control = self.dsp_control.clone();
+        let state_db = self.state_db.clone();
+        let audio_levels_subscribed = self.audio_levels_subscribed.clone();
+        let http_stream = self.http_stream.clone();
+        let metadata_service = self.metadata_service.clone();
+        let podcast_service = self.podcast_service.clone();
+        let ram_loader = self.ram_loader.clone();
         let is_multi_core_platform = core_affinity::get_core_ids().map_or(false, |ids| ids.len() > 1);
         let prio = if is_multi_core_platform {
             ThreadPriority::Crossplatform(playback_thread_prio.try_into().unwrap())
@@ -188,7 +350,16 @@ impl PlayerService {
                         }
                     }
                 }
-                loop {
+                // The audio output is kept open for the whole queue, so songs with the same signal
+                // spec are written into the same stream without a gap between them.
+                let mut audio_output = audio_output.map(|out| out as Box<dyn AudioOutput>);
+                // Next song already started by the crossfade of the current one.
+                let mut crossfade_handover: Option<CrossfadeTrack> = None;
+                let mut dsp = DspChain::new(dsp_control);
+                let mut level_meter =
+                    LevelMeter::new(&audio_levels_subscribed, rsp_settings.audio_levels_rate_hz, &changes_tx);
+                let mut failed_songs = 0;
+                let result = loop {
                     let Some(song) = queue.get_current_song() else {
                         changes_tx
                             .send(StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED))
@@ -201,49 +372,209 @@ impl PlayerService {
                     changes_tx
                         .send(StateChangeEvent::PlaybackStateEvent(PlayerState::PLAYING))
                         .expect("msg send failed");
-                    match super::symphonia::play_file(
-                        &song.file,
+                    // Entry crossfaded into, the queue stays on the current song until it took over.
+                    let mut crossfade_next: Option<QueueEntry> = None;
+                    let mut next_song = || {
+                        crossfade_next = None;
+                        // Nothing is crossfaded into a repeated song or past a requested stop.
+                        if queue.get_repeat_mode() == RepeatMode::Single || queue.get_stop_after() != StopAfter::Off {
+                            return None;
+                        }
+                        crossfade_next = queue
+                            .get_next_entry()
+                            .filter(|next| !is_same_album_sequence(&song, &next.song) && !is_dsd_file(&next.song.file));
+                        let next = crossfade_next.as_ref()?;
+                        let gain_db = replay_gain::next_song_gain_db(&queue, next, &rsp_settings);
+                        Some((next.song.clone(), gain_db))
+                    };
+                    ram_loader.set_next(next_file_to_load(&queue, &music_dir));
+                    // Podcast episodes resume where they were left, unless already started by the crossfade.
+                    if crossfade_handover.is_none() {
+                        resume_episode(&podcast_service, &song, &seek_request);
+                    }
+                    let replay_gain_db = replay_gain::current_song_gain_db(&queue, &song, &rsp_settings);
+                    let play_result = super::symphonia::play_file(
+                        &song,
                         &stop_signal,
-                        &skip_to_time,
+                        &pause_signal,
+                        &seek_request,
                         &audio_device,
                         &rsp_settings,
                         &music_dir,
                         &changes_tx,
-                    ) {
+                        &mut audio_output,
+                        replay_gain_db,
+                        &mut dsp,
+                        &mut level_meter,
+                        &http_stream,
+                        &ram_loader,
+                        &mut next_song,
+                        &mut crossfade_handover,
+                    );
+                    match play_result {
                         Ok(PlaybackResult::PlaybackStopped) => {
                             changes_tx
                                 .send(StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED))
                                 .ok();
                             break PlaybackResult::PlaybackStopped;
                         }
+                        // The song is not to blame when the output fails, it stays in the queue as it is.
+                        Err(err) if err.is::<OutputError>() => {
+                            error!("Audio output failed while playing {}. Error: {err}", song.file);
+                            changes_tx
+                                .send(StateChangeEvent::NotificationError(err.to_string()))
+                                .ok();
+                            changes_tx
+                                .send(StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED))
+                                .ok();
+                            break PlaybackResult::PlaybackFailed;
+                        }
                         Err(err) => {
                             error!("Failed to play file {}. Error: {:?}", song.file, err);
+                            let reason = err.to_string();
+                            queue.mark_song_failed(&song.file, &reason);
+                            if rsp_settings.ignore_unplayable_files && !song.file.starts_with("http") {
+                                metadata_service.ignore_unplayable_song(&song, &reason);
+                            }
                             changes_tx
-                                .send(StateChangeEvent::PlaybackStateEvent(PlayerState::ERROR(song.file)))
+                                .send(StateChangeEvent::PlaybackStateEvent(PlayerState::ERROR(
+                                    song.file.clone(),
+                                )))
                                 .ok();
-                            break PlaybackResult::PlaybackFailed;
+                            failed_songs += 1;
+                            if failed_songs > rsp_settings.skip_unplayable_songs || !queue.move_current_to_next_song() {
+                                break PlaybackResult::PlaybackFailed;
+                            }
+                            warn!("Skipping unplayable file {}", song.file);
+                            continue;
                         }
                         res => {
                             info!("Playback finished with result {:?}", res);
+                            failed_songs = 0;
                         }
                     }
 
-                    if !queue.move_current_to_next_song() {
+                    let crossfaded = crossfade_next
+                        .take()
+                        .filter(|_| crossfade_handover.is_some())
+                        .is_some_and(|next| queue.move_current_to_entry(next));
+                    if !crossfaded {
+                        // The crossfade is dropped when its song was removed from the queue while fading in.
+                        crossfade_handover = None;
+                    }
+                    let advanced = crossfaded || queue.move_current_to_song_after_finished();
+                    if is_stop_requested_after(&queue, &song, advanced) {
+                        info!("Playback stopped after {} as requested", song.file);
+                        // The next song starts from the beginning.
+                        _ = state_db.remove(LAST_SONG_PROGRESS_KEY);
+                        changes_tx
+                            .send(StateChangeEvent::RepeatModeEvent(
+                                queue.get_repeat_mode(),
+                                StopAfter::Off,
+                            ))
+                            .ok();
+                        changes_tx
+                            .send(StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED))
+                            .ok();
+                        break PlaybackResult::QueueFinished;
+                    }
+                    if !advanced {
                         break PlaybackResult::QueueFinished;
                     }
+                };
+                // Let the last song play out. On stop or failure the output is just dropped.
+                if result == PlaybackResult::QueueFinished {
+                    if let Some(out) = audio_output.as_mut() {
+                        out.flush();
+                    }
                 }
+                result
             })
             .unwrap()
     }
 
-    fn get_last_played_song_time(&self) -> u16 {
-        let last_time = match self.state_db.get(LAST_SONG_PROGRESS_KEY) {
-            Ok(Some(lt)) => {
-                let v = lt.to_vec();
-                String::from_utf8(v).unwrap()
-            }
-            _ => "0".to_string(),
-        };
-        last_time.parse::<u16>().unwrap_or_default()
+    /// Levels are measured only while a client subscribed to them.
+    pub fn set_audio_levels_subscribed(&self, subscribed: bool) {
+        self.audio_levels_subscribed.store(subscribed, Ordering::Relaxed);
+    }
+
+    /// The FLAC stream of the played audio, served to HTTP clients.
+    pub fn get_http_stream(&self) -> HttpStream {
+        self.http_stream.clone()
+    }
+
+    /// Titles played by the radio station, oldest first.
+    pub fn get_radio_history(&self, station_url: &str) -> Vec<String> {
+        self.radio_history.titles(station_url)
+    }
+
+    /// Position the song was left at, `None` if another song was played last.
+    fn get_last_played_song_position(&self, file: &str) -> Option<Duration> {
+        let value = self.state_db.get(LAST_SONG_PROGRESS_KEY).ok()??;
+        let (millis, last_file) = value.split_first_chunk::<8>()?;
+        (last_file == file.as_bytes())
+            .then(|| Duration::from_millis(u64::from_be_bytes(*millis)))
+            .filter(|position| !position.is_zero())
+    }
+}
+
+fn save_song_position(state_db: &Db, file: &str, position: Duration) {
+    let mut value = u64::try_from(position.as_millis())
+        .unwrap_or(u64::MAX)
+        .to_be_bytes()
+        .to_vec();
+    value.extend_from_slice(file.as_bytes());
+    _ = state_db.insert(LAST_SONG_PROGRESS_KEY, value);
+}
+
+/// Consumes the stop request fulfilled by the finished song, the queue is already moved to the song after it.
+fn is_stop_requested_after(queue: &QueueService, finished: &Song, advanced: bool) -> bool {
+    let stop = match queue.get_stop_after() {
+        StopAfter::Off => false,
+        StopAfter::CurrentSong => true,
+        StopAfter::CurrentAlbum => {
+            !advanced
+                || !queue
+                    .get_current_song()
+                    .is_some_and(|next| finished.is_same_album(&next))
+        }
+    };
+    if stop {
+        queue.clear_stop_after();
+    }
+    stop
+}
+
+/// Saves the position of the playing podcast episode, close to its end the episode is played and
+/// starts from the beginning the next time.
+fn save_episode_progress(podcast_service: &PodcastService, episode: &Episode, progress: &SongProgress) {
+    let remaining = progress.total_time.saturating_sub(progress.current_time);
+    if !progress.total_time.is_zero() && remaining <= EPISODE_END_MARGIN {
+        podcast_service.set_played(&episode.feed_url, &episode.audio_url, true);
+    } else {
+        podcast_service.save_position(&episode.feed_url, &episode.audio_url, progress.current_time);
+    }
+}
+
+/// Seeks to the resume position of the podcast episode, replacing the position restored for the
+/// last played song.
+fn resume_episode(podcast_service: &PodcastService, song: &Song, seek_request: &SeekRequest) {
+    if let Some(position) = podcast_service.resume_position(song) {
+        let millis = u64::try_from(position.as_millis()).unwrap_or_default();
+        *seek_request.lock().unwrap() = Some(SeekTarget::Millis(millis));
+    }
+}
+
+/// File of the song played after the current one, read into memory ahead. Unknown in random play.
+fn next_file_to_load(queue: &QueueService, music_dir: &str) -> Option<PathBuf> {
+    if queue.get_random_next() {
+        return None;
     }
+    let next = if queue.get_repeat_mode() == RepeatMode::Single {
+        queue.get_current_song()
+    } else {
+        queue.get_current_song_neighbours().1
+    };
+    next.filter(|song| !song.file.starts_with("http") && !is_dsd_file(&song.file))
+        .map(|song| Path::new(music_dir).join(song.source_file()))
 }
@@ -0,0 +1,573 @@
+//! Internet radio streams: station playlists, HLS and reconnecting after network errors. Remote
+//! files are opened here as well, the ones served with range requests are seekable.
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Result};
+use api_models::player::Song;
+use api_models::state::{StateChangeEvent, StreamConnectionState};
+use log::{debug, info, warn};
+use symphonia::core::io::MediaSource;
+use ureq::{Agent, AgentBuilder, Response};
+
+use super::http_source::HttpRangeSource;
+use super::icy::{self, IcyMetadataSource};
+use super::mpegts::TsDemuxer;
+
+const MAX_REDIRECTS: usize = 10;
+/// Playlists pointing to other playlists, like an HLS master playlist pointing to a media playlist.
+const MAX_PLAYLIST_DEPTH: usize = 5;
+const RECONNECT_ATTEMPTS: u32 = 8;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Live HLS streams start this many segments before the end of the playlist.
+const LIVE_START_SEGMENTS: usize = 3;
+const MAX_SEGMENT_LEN: u64 = 64 * 1024 * 1024;
+
+const PLAYLIST_EXTENSIONS: [&str; 3] = [".pls", ".m3u", ".m3u8"];
+const PLAYLIST_CONTENT_TYPES: [&str; 5] = [
+    "audio/x-scpls",
+    "audio/x-mpegurl",
+    "audio/mpegurl",
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+];
+
+pub struct RadioMeta {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub url: String,
+    genre: Option<String>,
+    /// Titles sent by the station in the stream, when it supports ICY metadata.
+    stream_titles: Option<Receiver<String>>,
+}
+
+impl RadioMeta {
+    pub fn station_song(&self) -> Song {
+        Song {
+            title: self.name.clone(),
+            album: self.description.clone(),
+            genre: self.genre.clone(),
+            file: self.url.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The song for the latest stream title received since the last call.
+    pub fn stream_title_song(&self) -> Option<Song> {
+        let stream_title = self.stream_titles.as_ref()?.try_iter().last()?;
+        Some(icy::stream_title_song(&self.station_song(), &stream_title))
+    }
+}
+
+/// Lets a radio stream report its reconnects and give up on them once the playback is stopped.
+#[derive(Clone)]
+pub struct StreamControl {
+    changes_tx: tokio::sync::broadcast::Sender<StateChangeEvent>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl StreamControl {
+    pub fn new(changes_tx: &tokio::sync::broadcast::Sender<StateChangeEvent>, stop_signal: &Arc<AtomicBool>) -> Self {
+        Self {
+            changes_tx: changes_tx.clone(),
+            stop_signal: stop_signal.clone(),
+        }
+    }
+
+    /// Sleeps for the delay, returns false as soon as the playback is stopped.
+    fn sleep(&self, delay: Duration) -> bool {
+        let until = Instant::now() + delay;
+        while !self.stop_signal.load(Ordering::Relaxed) {
+            let Some(left) = until.checked_duration_since(Instant::now()) else {
+                return true;
+            };
+            thread::sleep(left.min(Duration::from_millis(100)));
+        }
+        false
+    }
+
+    fn send(&self, state: StreamConnectionState) {
+        self.changes_tx
+            .send(StateChangeEvent::StreamConnectionEvent(state))
+            .ok();
+    }
+
+    /// Connects again with an increasing delay between the attempts.
+    pub fn reconnect<T>(&self, url: &str, mut connect: impl FnMut() -> Result<T>) -> io::Result<T> {
+        let mut delay = Duration::from_secs(1);
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            self.send(StreamConnectionState::Reconnecting(attempt, RECONNECT_ATTEMPTS));
+            warn!("Reconnecting to {url} in {delay:?}, attempt {attempt}/{RECONNECT_ATTEMPTS}");
+            if !self.sleep(delay) {
+                return Err(stopped());
+            }
+            match connect() {
+                Ok(connection) => {
+                    info!("Reconnected to {url}");
+                    self.send(StreamConnectionState::Reconnected);
+                    return Ok(connection);
+                }
+                Err(err) => warn!("Failed to reconnect to {url}: {err}"),
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+        self.send(StreamConnectionState::Lost);
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            format!("lost connection to {url}"),
+        ))
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "playback stopped")
+}
+
+/// Opens the radio station, resolving its playlists to the audio stream. Remote files have no
+/// station metadata.
+pub fn open(url: &str, control: &StreamControl) -> Result<(Box<dyn MediaSource>, Option<RadioMeta>)> {
+    let agent = AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(5))
+        .timeout_read(Duration::from_secs(5))
+        .timeout_write(Duration::from_secs(5))
+        // Redirects are followed by `get`, also the ones between http and https.
+        .redirects(0)
+        .build();
+    let mut stream_url = url.to_string();
+    for _ in 0..MAX_PLAYLIST_DEPTH {
+        let resp = get(&agent, &stream_url)?;
+        if let Some(len) = HttpRangeSource::range_len(&resp) {
+            info!("Reading {stream_url} with range requests");
+            let file_url = resp.get_url().to_string();
+            return Ok((Box::new(HttpRangeSource::new(agent, &file_url, len, resp, control.clone())), None));
+        }
+        if !is_playlist(resp.get_url(), resp.content_type()) {
+            let (source, meta) = open_stream(agent, url, &stream_url, resp, control);
+            return Ok((source, Some(meta)));
+        }
+        let playlist_url = resp.get_url().to_string();
+        let body = resp.into_string()?;
+        match parse_playlist(&body, &playlist_url).ok_or_else(|| format_err!("Invalid playlist {playlist_url}"))? {
+            Playlist::Entry(entry) => {
+                debug!("Playlist {playlist_url} points to {entry}");
+                stream_url = entry;
+            }
+            Playlist::Hls(playlist) => {
+                info!("Playing HLS stream {playlist_url}");
+                let meta = RadioMeta {
+                    name: None,
+                    description: None,
+                    url: url.to_string(),
+                    genre: None,
+                    stream_titles: None,
+                };
+                let source = HlsSource::new(agent, playlist_url, playlist, control.clone());
+                return Ok((Box::new(source), Some(meta)));
+            }
+        }
+    }
+    Err(format_err!("Too many nested playlists for {url}"))
+}
+
+fn open_stream(
+    agent: Agent,
+    url: &str,
+    stream_url: &str,
+    resp: Response,
+    control: &StreamControl,
+) -> (Box<dyn MediaSource>, RadioMeta) {
+    info!(
+        "response status code:{} / status text:{}",
+        resp.status(),
+        resp.status_text()
+    );
+    resp.headers_names()
+        .iter()
+        .for_each(|header| debug!("{header} = {:?}", resp.header(header).unwrap_or("")));
+    let (titles_tx, titles_rx) = mpsc::channel();
+    let meta = RadioMeta {
+        name: resp.header("icy-name").map(ToString::to_string),
+        description: resp.header("icy-description").map(ToString::to_string),
+        genre: resp.header("icy-genre").map(ToString::to_string),
+        url: url.to_string(),
+        stream_titles: resp.header("icy-metaint").map(|_| titles_rx),
+    };
+    let source = ReconnectingSource {
+        is_live: resp.header("content-length").is_none(),
+        reader: stream_reader(resp, &titles_tx),
+        agent,
+        url: stream_url.to_string(),
+        titles_tx,
+        control: control.clone(),
+    };
+    (Box::new(source), meta)
+}
+
+/// GET request following the redirects.
+fn get(agent: &Agent, url: &str) -> Result<Response> {
+    let mut url = url.to_string();
+    for _ in 0..MAX_REDIRECTS {
+        let resp = agent
+            .get(&url)
+            .set("accept", "*/*")
+            .set("Icy-MetaData", "1")
+            .call()
+            .map_err(|err| format_err!("Failed to get url {url}: {err}"))?;
+        if !(300..400).contains(&resp.status()) {
+            return Ok(resp);
+        }
+        let location = resp
+            .header("location")
+            .ok_or_else(|| format_err!("Redirect without location from {url}"))?;
+        url = resolve_url(&url, location);
+        debug!("Redirected to {url}");
+    }
+    Err(format_err!("Too many redirects for {url}"))
+}
+
+/// Audio of the response without the ICY metadata.
+fn stream_reader(resp: Response, titles_tx: &Sender<String>) -> Box<dyn Read + Send + Sync> {
+    let metaint = resp
+        .header("icy-metaint")
+        .and_then(|metaint| metaint.parse::<usize>().ok());
+    match metaint {
+        Some(metaint) if metaint > 0 => {
+            Box::new(IcyMetadataSource::new(resp.into_reader(), metaint, titles_tx.clone()))
+        }
+        _ => resp.into_reader(),
+    }
+}
+
+fn is_playlist(url: &str, content_type: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+    PLAYLIST_EXTENSIONS.iter().any(|extension| path.ends_with(extension))
+        || PLAYLIST_CONTENT_TYPES.contains(&content_type.to_lowercase().as_str())
+}
+
+/// Absolute URL of a URL found in a playlist or a redirect.
+fn resolve_url(base: &str, reference: &str) -> String {
+    if reference.contains("://") {
+        return reference.to_string();
+    }
+    let (scheme, rest) = base.split_once("://").unwrap_or(("http", base));
+    if let Some(reference) = reference.strip_prefix("//") {
+        return format!("{scheme}://{reference}");
+    }
+    if reference.starts_with('/') {
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        return format!("{scheme}://{host}{reference}");
+    }
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let dir = path.rfind('/').map_or(path, |end| &path[..end]);
+    format!("{scheme}://{dir}/{reference}")
+}
+
+#[derive(Debug, PartialEq)]
+enum Playlist {
+    /// Station URL taken from a PLS or M3U playlist, or the stream of an HLS master playlist.
+    Entry(String),
+    Hls(MediaPlaylist),
+}
+
+#[derive(Debug, PartialEq)]
+struct MediaPlaylist {
+    media_sequence: u64,
+    target_duration: Duration,
+    segments: Vec<String>,
+    ended: bool,
+}
+
+fn parse_playlist(body: &str, playlist_url: &str) -> Option<Playlist> {
+    let mut lines = body.lines().map(str::trim).filter(|line| !line.is_empty());
+    if body.contains("#EXT-X-TARGETDURATION") {
+        return parse_media_playlist(body, playlist_url).map(Playlist::Hls);
+    }
+    let entry = if body.trim_start().to_lowercase().starts_with("[playlist]") {
+        lines
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| key.to_lowercase().starts_with("file"))
+            .map(|(_, value)| value.trim())
+    } else {
+        // The first stream of an M3U playlist, or of an HLS master playlist.
+        lines.find(|line| !line.starts_with('#'))
+    };
+    entry.map(|entry| Playlist::Entry(resolve_url(playlist_url, entry)))
+}
+
+fn parse_media_playlist(body: &str, playlist_url: &str) -> Option<MediaPlaylist> {
+    let mut playlist = MediaPlaylist {
+        media_sequence: 0,
+        target_duration: Duration::from_secs(10),
+        segments: vec![],
+        ended: false,
+    };
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(sequence) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = sequence.trim().parse().ok()?;
+        } else if let Some(duration) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = Duration::from_secs(duration.trim().parse::<u64>().ok()?.max(1));
+        } else if line.starts_with("#EXT-X-ENDLIST") {
+            playlist.ended = true;
+        } else if !line.starts_with('#') {
+            playlist.segments.push(resolve_url(playlist_url, line));
+        }
+    }
+    Some(playlist)
+}
+
+/// Audio stream that connects again when the connection is lost. Streams with a known length are
+/// not live, they end instead.
+struct ReconnectingSource {
+    reader: Box<dyn Read + Send + Sync>,
+    is_live: bool,
+    agent: Agent,
+    url: String,
+    titles_tx: Sender<String>,
+    control: StreamControl,
+}
+
+impl Read for ReconnectingSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.reader.read(buf) {
+                Ok(read) if read > 0 || !self.is_live => return Ok(read),
+                Ok(_) => warn!("Radio stream {} ended", self.url),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if !self.is_live => return Err(err),
+                Err(err) => warn!("Radio stream {} failed: {err}", self.url),
+            }
+            if self.control.stop_signal.load(Ordering::Relaxed) {
+                return Err(stopped());
+            }
+            let (agent, url, titles_tx) = (&self.agent, &self.url, &self.titles_tx);
+            self.reader = self
+                .control
+                .reconnect(url, || get(agent, url).map(|resp| stream_reader(resp, titles_tx)))?;
+        }
+    }
+}
+
+impl Seek for ReconnectingSource {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "radio stream is not seekable",
+        ))
+    }
+}
+
+impl MediaSource for ReconnectingSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Audio of the segments of an HLS media playlist, reloaded while the stream is live.
+struct HlsSource {
+    agent: Agent,
+    playlist_url: String,
+    segments: VecDeque<String>,
+    last_sequence: Option<u64>,
+    target_duration: Duration,
+    ended: bool,
+    reloaded_at: Instant,
+    segment: Cursor<Vec<u8>>,
+    demuxer: TsDemuxer,
+    control: StreamControl,
+}
+
+impl HlsSource {
+    fn new(agent: Agent, playlist_url: String, playlist: MediaPlaylist, control: StreamControl) -> Self {
+        let mut source = Self {
+            agent,
+            playlist_url,
+            segments: VecDeque::new(),
+            last_sequence: None,
+            target_duration: playlist.target_duration,
+            ended: false,
+            reloaded_at: Instant::now(),
+            segment: Cursor::new(vec![]),
+            demuxer: TsDemuxer::default(),
+            control,
+        };
+        source.add_segments(playlist);
+        source
+    }
+
+    fn add_segments(&mut self, playlist: MediaPlaylist) {
+        let live_start = if self.last_sequence.is_none() && !playlist.ended {
+            playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS)
+        } else {
+            0
+        };
+        for (sequence, segment) in (playlist.media_sequence..).zip(playlist.segments).skip(live_start) {
+            if self.last_sequence < Some(sequence) {
+                self.segments.push_back(segment);
+                self.last_sequence = Some(sequence);
+            }
+        }
+        self.target_duration = playlist.target_duration;
+        self.ended = playlist.ended;
+    }
+
+    fn reload(&mut self) -> io::Result<()> {
+        let body = self.fetch(&self.playlist_url)?;
+        let playlist = parse_media_playlist(&String::from_utf8_lossy(&body), &self.playlist_url)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HLS playlist"))?;
+        self.reloaded_at = Instant::now();
+        self.add_segments(playlist);
+        Ok(())
+    }
+
+    /// Downloads the next segment, returns false at the end of the stream.
+    fn next_segment(&mut self) -> io::Result<bool> {
+        loop {
+            if !self.ended && self.segments.len() < 2 && self.reloaded_at.elapsed() >= self.target_duration / 2 {
+                self.reload()?;
+            }
+            if let Some(url) = self.segments.pop_front() {
+                let data = self.fetch(&url)?;
+                let audio = if TsDemuxer::is_transport_stream(&data) {
+                    let mut audio = vec![];
+                    self.demuxer.demux(&data, &mut audio);
+                    audio
+                } else {
+                    data
+                };
+                self.segment = Cursor::new(audio);
+                return Ok(true);
+            }
+            if self.ended {
+                return Ok(false);
+            }
+            if !self.control.sleep(self.target_duration / 2) {
+                return Err(stopped());
+            }
+        }
+    }
+
+    fn fetch(&self, url: &str) -> io::Result<Vec<u8>> {
+        let download = || -> Result<Vec<u8>> {
+            let mut data = vec![];
+            get(&self.agent, url)?
+                .into_reader()
+                .take(MAX_SEGMENT_LEN)
+                .read_to_end(&mut data)?;
+            Ok(data)
+        };
+        download().or_else(|err| {
+            warn!("Failed to download {url}: {err}");
+            self.control.reconnect(url, download)
+        })
+    }
+}
+
+impl Read for HlsSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.segment.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            if !self.next_segment()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl Seek for HlsSource {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "HLS stream is not seekable"))
+    }
+}
+
+impl MediaSource for HlsSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{is_playlist, parse_playlist, resolve_url, MediaPlaylist, Playlist};
+
+    #[test]
+    fn should_resolve_urls_relative_to_playlist() {
+        let base = "https://radio.example/live/index.m3u8?token=1";
+        assert_eq!(resolve_url(base, "seg1.ts"), "https://radio.example/live/seg1.ts");
+        assert_eq!(resolve_url(base, "/hls/seg1.ts"), "https://radio.example/hls/seg1.ts");
+        assert_eq!(
+            resolve_url(base, "//cdn.example/seg1.ts"),
+            "https://cdn.example/seg1.ts"
+        );
+        assert_eq!(resolve_url(base, "http://other/stream"), "http://other/stream");
+        assert_eq!(
+            resolve_url("http://radio.example", "stream"),
+            "http://radio.example/stream"
+        );
+    }
+
+    #[test]
+    fn should_detect_playlists_by_extension_or_content_type() {
+        assert!(is_playlist("http://radio.example/listen.PLS", "text/plain"));
+        assert!(is_playlist("http://radio.example/live.m3u8?token=1", ""));
+        assert!(is_playlist("http://radio.example/stream", "audio/x-mpegurl"));
+        assert!(!is_playlist("http://radio.example/stream.mp3", "audio/mpeg"));
+    }
+
+    #[test]
+    fn should_take_first_stream_of_playlists() {
+        let pls = "[playlist]\nNumberOfEntries=2\nFile1=http://radio.example:8000/stream\nTitle1=Radio\nFile2=http://backup\n";
+        assert_eq!(
+            parse_playlist(pls, "http://radio.example/listen.pls"),
+            Some(Playlist::Entry("http://radio.example:8000/stream".to_string()))
+        );
+        let m3u = "#EXTM3U\n#EXTINF:-1,Radio\nhttp://radio.example/stream.aac\n";
+        assert_eq!(
+            parse_playlist(m3u, "http://radio.example/listen.m3u"),
+            Some(Playlist::Entry("http://radio.example/stream.aac".to_string()))
+        );
+        let master = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\nchunklist.m3u8\n";
+        assert_eq!(
+            parse_playlist(master, "http://radio.example/hls/playlist.m3u8"),
+            Some(Playlist::Entry("http://radio.example/hls/chunklist.m3u8".to_string()))
+        );
+    }
+
+    #[test]
+    fn should_parse_hls_media_playlist() {
+        let media = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:42\n\
+                     #EXTINF:6.0,\nseg42.aac\n#EXTINF:6.0,\nseg43.aac\n";
+        assert_eq!(
+            parse_playlist(media, "http://radio.example/hls/chunklist.m3u8"),
+            Some(Playlist::Hls(MediaPlaylist {
+                media_sequence: 42,
+                target_duration: Duration::from_secs(6),
+                segments: vec![
+                    "http://radio.example/hls/seg42.aac".to_string(),
+                    "http://radio.example/hls/seg43.aac".to_string(),
+                ],
+                ended: false,
+            }))
+        );
+    }
+}
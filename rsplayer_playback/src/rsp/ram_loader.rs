@@ -0,0 +1,243 @@
+//! Songs read into memory before they are played, so a slow network share can't interrupt the
+//! playback. The next song of the queue is read while the current one plays.
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use api_models::state::{SongLoadProgress, StateChangeEvent};
+use log::{debug, info, warn};
+use symphonia::core::io::MediaSource;
+use tokio::sync::broadcast::Sender;
+
+const CHUNK_LEN: usize = 1024 * 1024;
+/// Loading progress is reported at every that many percent of the file.
+const PROGRESS_STEP: u64 = 5;
+
+#[derive(Clone)]
+pub struct RamLoader {
+    /// Memory for the current and the next file, `None` streams all files from the disk.
+    max_bytes: Option<u64>,
+    changes_tx: Sender<StateChangeEvent>,
+    stop_signal: Arc<AtomicBool>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// File of the current song, kept for the following songs of the same file.
+    current: Option<(PathBuf, FileData)>,
+    /// File of the next song, read once the current one is loaded.
+    next: Option<PathBuf>,
+    prefetch: Option<Prefetch>,
+}
+
+struct Prefetch {
+    path: PathBuf,
+    cancel: Arc<AtomicBool>,
+    handle: JoinHandle<Option<FileData>>,
+}
+
+/// Contents of a file, shared by the songs played from it.
+#[derive(Clone)]
+struct FileData(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for FileData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl RamLoader {
+    pub fn new(
+        load_into_ram_mb: Option<usize>,
+        changes_tx: &Sender<StateChangeEvent>,
+        stop_signal: &Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            max_bytes: load_into_ram_mb.map(|mb| mb as u64 * 1024 * 1024),
+            changes_tx: changes_tx.clone(),
+            stop_signal: stop_signal.clone(),
+            shared: Arc::default(),
+        }
+    }
+
+    /// Sets the file read into memory after the current one, a prefetch of another file is cancelled.
+    pub fn set_next(&self, path: Option<PathBuf>) {
+        if self.max_bytes.is_none() {
+            return;
+        }
+        let mut shared = self.shared.lock().expect("Failed to lock the ram loader");
+        if shared
+            .prefetch
+            .as_ref()
+            .is_some_and(|prefetch| Some(&prefetch.path) != path.as_ref())
+        {
+            if let Some(prefetch) = shared.prefetch.take() {
+                prefetch.cancel.store(true, Ordering::Relaxed);
+            }
+        }
+        shared.next = path;
+    }
+
+    /// Reads the file into memory, reporting the progress, and starts reading the next one.
+    /// Returns `None` when the file is streamed from the disk instead: it doesn't fit into the
+    /// memory, it can't be read or the playback was stopped meanwhile.
+    pub fn open(&self, path: &Path, file: &str) -> Option<Box<dyn MediaSource>> {
+        let max_bytes = self.max_bytes?;
+        let data = self.take_loaded(path).or_else(|| {
+            let len = fs::metadata(path).ok()?.len();
+            if len > max_bytes {
+                info!("{file} is bigger than the memory for the songs, it is streamed from the disk");
+                return None;
+            }
+            let mut progress = |loaded_bytes| {
+                self.changes_tx
+                    .send(StateChangeEvent::SongLoadingEvent(SongLoadProgress {
+                        file: file.to_string(),
+                        loaded_bytes,
+                        total_bytes: len,
+                    }))
+                    .ok();
+            };
+            read_file(path, len, &self.stop_signal, &mut progress)
+        });
+        let mut shared = self.shared.lock().expect("Failed to lock the ram loader");
+        shared.current = data.as_ref().map(|data| (path.to_path_buf(), data.clone()));
+        self.start_prefetch(&mut shared);
+        data.map(|data| Box::new(Cursor::new(data)) as Box<dyn MediaSource>)
+    }
+
+    /// The file when it is the current or the prefetched one, waits for the prefetch to finish.
+    fn take_loaded(&self, path: &Path) -> Option<FileData> {
+        let mut shared = self.shared.lock().expect("Failed to lock the ram loader");
+        if let Some((current_path, data)) = shared.current.as_ref() {
+            if current_path == path {
+                return Some(data.clone());
+            }
+        }
+        // The current file is released before the next one is taken.
+        shared.current = None;
+        let prefetch = shared.prefetch.take_if(|prefetch| prefetch.path == path)?;
+        drop(shared);
+        debug!("Using prefetched {}", path.display());
+        prefetch.handle.join().ok().flatten()
+    }
+
+    /// Reads the next file in the background, when it fits into the memory with the current one.
+    fn start_prefetch(&self, shared: &mut Shared) {
+        let (Some(max_bytes), Some(path)) = (self.max_bytes, shared.next.take()) else {
+            return;
+        };
+        let current_len = shared.current.as_ref().map_or(0, |(_, data)| data.0.len() as u64);
+        let is_current = shared
+            .current
+            .as_ref()
+            .is_some_and(|(current_path, _)| *current_path == path);
+        let is_prefetched = shared.prefetch.as_ref().is_some_and(|prefetch| prefetch.path == path);
+        if is_current || is_prefetched {
+            return;
+        }
+        let Ok(len) = fs::metadata(&path).map(|metadata| metadata.len()) else {
+            return;
+        };
+        if current_len + len > max_bytes {
+            debug!("No memory left to prefetch {}", path.display());
+            return;
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (path, cancel) = (path.clone(), cancel.clone());
+            thread::Builder::new()
+                .name("prefetch".to_string())
+                .spawn(move || read_file(&path, len, &cancel, &mut |_| {}))
+        };
+        match handle {
+            Ok(handle) => shared.prefetch = Some(Prefetch { path, cancel, handle }),
+            Err(err) => warn!("Failed to start the prefetch of {}: {err}", path.display()),
+        }
+    }
+}
+
+/// Reads the whole file, `None` when it failed or was cancelled.
+#[allow(clippy::cast_possible_truncation)]
+fn read_file(path: &Path, len: u64, cancel: &AtomicBool, progress: &mut dyn FnMut(u64)) -> Option<FileData> {
+    let mut file = File::open(path).ok()?;
+    let mut data = Vec::with_capacity(len as usize);
+    let mut chunk = vec![0; CHUNK_LEN];
+    let mut reported_len = None;
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            debug!("Reading {} cancelled", path.display());
+            return None;
+        }
+        let read = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                warn!("Failed to read {} into memory: {err}", path.display());
+                return None;
+            }
+        };
+        data.extend_from_slice(&chunk[..read]);
+        let loaded = data.len() as u64;
+        if reported_len.is_none_or(|reported| (loaded - reported) * 100 >= PROGRESS_STEP * len) {
+            reported_len = Some(loaded);
+            progress(loaded);
+        }
+    }
+    if reported_len != Some(data.len() as u64) {
+        progress(data.len() as u64);
+    }
+    Some(FileData(Arc::new(data)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Read;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use api_models::state::StateChangeEvent;
+    use tokio::sync::broadcast;
+
+    use super::RamLoader;
+
+    #[test]
+    fn should_load_file_and_prefetch_next_one_within_memory_limit() {
+        let dir = std::env::temp_dir().join(format!("rsp_ram_loader_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (current, next, big) = (dir.join("current.flac"), dir.join("next.flac"), dir.join("big.flac"));
+        fs::write(&current, vec![1; 3 * 1024 * 1024]).unwrap();
+        fs::write(&next, vec![2; 1024 * 1024]).unwrap();
+        fs::write(&big, vec![3; 17 * 1024 * 1024]).unwrap();
+        let (changes_tx, mut changes_rx) = broadcast::channel(100);
+        let loader = RamLoader::new(Some(16), &changes_tx, &Arc::new(AtomicBool::new(false)));
+
+        loader.set_next(Some(next.clone()));
+        let mut source = loader.open(&current, "current.flac").unwrap();
+        let mut data = vec![];
+        source.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 3 * 1024 * 1024);
+        let mut progress = vec![];
+        while let Ok(StateChangeEvent::SongLoadingEvent(event)) = changes_rx.try_recv() {
+            progress.push(event.loaded_bytes);
+        }
+        assert_eq!(progress.first(), Some(&(1024 * 1024)));
+        assert_eq!(progress.last(), Some(&(3 * 1024 * 1024)));
+
+        // The prefetched file is read without progress events.
+        let mut source = loader.open(&next, "next.flac").unwrap();
+        let mut data = vec![];
+        source.read_to_end(&mut data).unwrap();
+        assert!(data.iter().all(|&byte| byte == 2));
+        assert!(changes_rx.try_recv().is_err());
+
+        assert!(loader.open(&big, "big.flac").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use api_models::common::ReplayGainMode;
+use api_models::player::Song;
+use api_models::settings::RsPlayerSettings;
+use rsplayer_metadata::queue_service::{QueueEntry, QueueService};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+
+use super::crossfade::is_same_album_sequence;
+use super::symphonia::to_f32_buffer;
+
+const TRACK_GAIN: &str = "REPLAYGAIN_TRACK_GAIN";
+const TRACK_PEAK: &str = "REPLAYGAIN_TRACK_PEAK";
+const ALBUM_GAIN: &str = "REPLAYGAIN_ALBUM_GAIN";
+const ALBUM_PEAK: &str = "REPLAYGAIN_ALBUM_PEAK";
+
+/// Replay gain in dB for the current song of the queue, `None` if it should be played unchanged.
+pub fn current_song_gain_db(queue: &QueueService, song: &Song, rsp_settings: &RsPlayerSettings) -> Option<f32> {
+    song_gain_db(queue, song, || queue.get_current_song_neighbours(), rsp_settings)
+}
+
+/// Replay gain in dB for the song the queue moves to next.
+pub fn next_song_gain_db(queue: &QueueService, next: &QueueEntry, rsp_settings: &RsPlayerSettings) -> Option<f32> {
+    song_gain_db(queue, &next.song, || queue.get_entry_neighbours(next), rsp_settings)
+}
+
+fn song_gain_db(
+    queue: &QueueService,
+    song: &Song,
+    neighbours: impl FnOnce() -> (Option<Song>, Option<Song>),
+    rsp_settings: &RsPlayerSettings,
+) -> Option<f32> {
+    let album_mode = match rsp_settings.replay_gain_mode {
+        ReplayGainMode::Off => return None,
+        ReplayGainMode::Track => false,
+        ReplayGainMode::Album => true,
+        ReplayGainMode::Auto => !queue.get_random_next() && is_album_in_order(song, neighbours()),
+    };
+    gain_db(song, album_mode, rsp_settings)
+}
+
+fn is_album_in_order(song: &Song, (prev, next): (Option<Song>, Option<Song>)) -> bool {
+    prev.is_some_and(|prev| is_same_album_sequence(&prev, song))
+        || next.is_some_and(|next| is_same_album_sequence(song, &next))
+}
+
+/// Gain from the song tags with preamp added, lowered if the song peak would clip.
+/// Falls back to the other gain type when the preferred one is missing.
+pub fn gain_db(song: &Song, album_mode: bool, rsp_settings: &RsPlayerSettings) -> Option<f32> {
+    let (gain_keys, peak_keys) = if album_mode {
+        ([ALBUM_GAIN, TRACK_GAIN], [ALBUM_PEAK, TRACK_PEAK])
+    } else {
+        ([TRACK_GAIN, ALBUM_GAIN], [TRACK_PEAK, ALBUM_PEAK])
+    };
+    let (idx, gain) = gain_keys
+        .iter()
+        .enumerate()
+        .find_map(|(idx, key)| tag_value(song, key).map(|gain| (idx, gain)))?;
+    let gain = gain + f32::from(rsp_settings.replay_gain_preamp_db);
+    if !rsp_settings.replay_gain_prevent_clipping {
+        return Some(gain);
+    }
+    match tag_value(song, peak_keys[idx]) {
+        Some(peak) if peak > 0.0 => Some(gain.min(-20.0 * peak.log10())),
+        _ => Some(gain),
+    }
+}
+
+fn tag_value(song: &Song, key: &str) -> Option<f32> {
+    song.tags
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .and_then(|(_, value)| value.trim().trim_end_matches("dB").trim().parse().ok())
+}
+
+pub fn db_to_factor(gain_db: f32) -> f32 {
+    10f32.powf(gain_db / 20.0)
+}
+
+/// Multiplies all samples of the buffer by the gain factor.
+pub fn apply(buffer: &mut AudioBuffer<f32>, factor: f32) {
+    for ch in 0..buffer.spec().channels.count() {
+        buffer.chan_mut(ch).iter_mut().for_each(|s| *s *= factor);
+    }
+}
+
+pub fn amplify(decoded: &AudioBufferRef<'_>, factor: f32) -> AudioBuffer<f32> {
+    let mut buffer = to_f32_buffer(decoded);
+    apply(&mut buffer, factor);
+    buffer
+}
+
+#[cfg(test)]
+mod test {
+    use api_models::player::Song;
+    use api_models::settings::RsPlayerSettings;
+
+    use super::{db_to_factor, gain_db};
+
+    fn song(tags: &[(&str, &str)]) -> Song {
+        Song {
+            tags: tags.iter().map(|(k, v)| ((*k).to_string(), (*v).to_string())).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_read_gain_for_mode_with_fallback() {
+        let settings = RsPlayerSettings {
+            replay_gain_prevent_clipping: false,
+            ..Default::default()
+        };
+        let both = song(&[("REPLAYGAIN_TRACK_GAIN", "-6.5 dB"), ("replaygain_album_gain", "-4 dB")]);
+        assert_eq!(gain_db(&both, false, &settings), Some(-6.5));
+        assert_eq!(gain_db(&both, true, &settings), Some(-4.0));
+        let track_only = song(&[("REPLAYGAIN_TRACK_GAIN", "+2.00 dB")]);
+        assert_eq!(gain_db(&track_only, true, &settings), Some(2.0));
+        assert_eq!(gain_db(&Song::default(), false, &settings), None);
+    }
+
+    #[test]
+    fn should_add_preamp_and_prevent_clipping() {
+        let settings = RsPlayerSettings {
+            replay_gain_preamp_db: 3,
+            ..Default::default()
+        };
+        let quiet = song(&[("REPLAYGAIN_TRACK_GAIN", "1 dB"), ("REPLAYGAIN_TRACK_PEAK", "0.5")]);
+        assert_eq!(gain_db(&quiet, false, &settings), Some(4.0));
+        let loud = song(&[("REPLAYGAIN_TRACK_GAIN", "1 dB"), ("REPLAYGAIN_TRACK_PEAK", "0.9")]);
+        let gain = gain_db(&loud, false, &settings).unwrap();
+        assert!(db_to_factor(gain).mul_add(0.9, -1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use log::info;
+use rubato::{FftFixedIn, Resampler};
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal, SignalSpec};
+
+use super::output::AudioOutput;
+use super::symphonia::to_f32_buffer;
+
+/// Number of input frames resampled at once.
+const CHUNK_SIZE: usize = 1024;
+
+/// Audio output that converts the samples to a fixed sample rate before they are written to the
+/// device, for DACs that accept only some rates or sound best at one.
+pub struct ResamplingOutput {
+    inner: Box<dyn AudioOutput>,
+    spec: SignalSpec,
+    resampler: FftFixedIn<f32>,
+    /// Input samples per channel waiting for a full chunk.
+    input: Vec<Vec<f32>>,
+    output: Vec<Vec<f32>>,
+    out_buffer: AudioBuffer<f32>,
+}
+
+impl ResamplingOutput {
+    /// Wraps the output opened with the target rate, `spec` is the signal spec of the songs.
+    pub fn new(inner: Box<dyn AudioOutput>, spec: SignalSpec) -> Result<Self> {
+        let out_spec = inner.spec();
+        let channels = spec.channels.count();
+        let resampler = FftFixedIn::<f32>::new(
+            spec.rate as usize,
+            out_spec.rate as usize,
+            CHUNK_SIZE,
+            2,
+            channels,
+        )?;
+        info!("Resampling from {} Hz to {} Hz", spec.rate, out_spec.rate);
+        let out_buffer = AudioBuffer::new(resampler.output_frames_max() as u64, out_spec);
+        Ok(Self {
+            output: resampler.output_buffer_allocate(true),
+            input: vec![Vec::with_capacity(2 * CHUNK_SIZE); channels],
+            inner,
+            spec,
+            resampler,
+            out_buffer,
+        })
+    }
+
+    fn write_output(&mut self, frames: usize) -> Result<()> {
+        self.out_buffer.clear();
+        self.out_buffer.render_reserved(Some(frames));
+        for (ch, samples) in self.output.iter().enumerate() {
+            self.out_buffer.chan_mut(ch).copy_from_slice(&samples[..frames]);
+        }
+        self.inner.write(self.out_buffer.as_audio_buffer_ref())
+    }
+}
+
+impl AudioOutput for ResamplingOutput {
+    fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+        let buffer = to_f32_buffer(&decoded);
+        for (ch, input) in self.input.iter_mut().enumerate() {
+            input.extend_from_slice(buffer.chan(ch));
+        }
+        while self.input[0].len() >= self.resampler.input_frames_next() {
+            let (consumed, produced) = self.resampler.process_into_buffer(&self.input, &mut self.output, None)?;
+            for input in &mut self.input {
+                input.drain(..consumed);
+            }
+            self.write_output(produced)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if !self.input[0].is_empty() {
+            if let Ok((_, produced)) = self
+                .resampler
+                .process_partial_into_buffer(Some(&self.input), &mut self.output, None)
+            {
+                _ = self.write_output(produced);
+            }
+            self.input.iter_mut().for_each(Vec::clear);
+        }
+        self.inner.flush();
+    }
+
+    fn spec(&self) -> SignalSpec {
+        self.spec
+    }
+
+    fn pause(&mut self) {
+        self.inner.pause();
+    }
+
+    fn resume(&mut self) {
+        self.inner.resume();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::f32::consts::PI;
+    use std::rc::Rc;
+
+    use anyhow::Result;
+    use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+
+    use super::ResamplingOutput;
+    use crate::rsp::output::AudioOutput;
+
+    struct CollectingOutput {
+        spec: SignalSpec,
+        frames: Rc<Cell<usize>>,
+    }
+
+    impl AudioOutput for CollectingOutput {
+        fn write(&mut self, decoded: AudioBufferRef<'_>) -> Result<()> {
+            assert_eq!(*decoded.spec(), self.spec);
+            self.frames.set(self.frames.get() + decoded.frames());
+            Ok(())
+        }
+
+        fn flush(&mut self) {}
+
+        fn spec(&self) -> SignalSpec {
+            self.spec
+        }
+    }
+
+    #[test]
+    #[allow(clippy::cast_precision_loss)]
+    fn should_resample_to_output_rate() {
+        let channels = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+        let in_spec = SignalSpec::new(44100, channels);
+        let frames = Rc::new(Cell::new(0));
+        let inner = CollectingOutput {
+            spec: SignalSpec::new(96000, channels),
+            frames: frames.clone(),
+        };
+        let mut output = ResamplingOutput::new(Box::new(inner), in_spec).unwrap();
+        let mut buffer = AudioBuffer::<f32>::new(4410, in_spec);
+        for packet in 0..10 {
+            buffer.clear();
+            buffer.render_reserved(Some(4410));
+            for ch in 0..2 {
+                for (i, sample) in buffer.chan_mut(ch).iter_mut().enumerate() {
+                    *sample = (2.0 * PI * 1000.0 * (packet * 4410 + i) as f32 / 44100.0).sin();
+                }
+            }
+            output.write(buffer.as_audio_buffer_ref()).unwrap();
+        }
+        output.flush();
+        // One second of audio, with at most one chunk of padding added by the flush.
+        assert!(frames.get() >= 96000);
+        assert!(frames.get() < 96000 + 2 * 1024 * 96000 / 44100);
+        assert_eq!(output.spec(), in_spec);
+    }
+}
@@ -1,29 +1,38 @@
 use std::fs::File;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::{format_err, Result};
 
+use api_models::common::SeekTarget;
 use api_models::player::Song;
 use api_models::settings::RsPlayerSettings;
-use api_models::state::{PlayerInfo, SongProgress, StateChangeEvent};
-use log::{debug, info, trace, warn};
-use symphonia::core::audio::Channels;
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use api_models::state::{PlayerInfo, PlayerState, SongProgress, StateChangeEvent};
+use log::{debug, trace, warn};
+use rsplayer_metadata::dsd;
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
-use symphonia::core::io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions, ReadOnlySource};
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, SeekMode, SeekTo, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream, MediaSourceStreamOptions};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 use symphonia::core::units::{Time, TimeBase};
 use symphonia::default::{get_codecs, get_probe};
 use tokio::sync::broadcast::Sender;
 
-use crate::rsp::output::AudioOutput;
+use crate::rsp::crossfade::{self, CrossfadeTrack};
+use crate::rsp::dsp::DspChain;
+use crate::rsp::http_stream::HttpStream;
+use crate::rsp::level_meter::LevelMeter;
+use crate::rsp::output::{self, AudioOutput};
+use crate::rsp::radio::{self, RadioMeta, StreamControl};
+use crate::rsp::ram_loader::RamLoader;
+use crate::rsp::replay_gain;
 
-use super::output::try_open;
+use super::output::{try_open, OutputError};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum PlaybackResult {
@@ -33,119 +42,308 @@ pub enum PlaybackResult {
     PlaybackFailed,
 }
 
-struct RadioMeta {
-    pub name: Option<String>,
-    pub description: Option<String>,
-    pub url: String,
-    genre: Option<String>,
+unsafe impl Send for PlaybackResult {}
+
+/// Format reader and decoder of a single song.
+pub struct TrackDecoder {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: TimeBase,
+    n_frames: Option<u64>,
+    is_seekable: bool,
+    player_info: PlayerInfo,
+    radio_meta: Option<RadioMeta>,
+    seek_ts: u64,
+    current_ts: u64,
+    gain_factor: Option<f32>,
+    /// Span of the file played as the song, the whole file unless the song is a CUE sheet track.
+    start: Duration,
+    start_ts: u64,
+    end_ts: Option<u64>,
 }
 
-unsafe impl Send for PlaybackResult {}
+impl TrackDecoder {
+    pub fn open(
+        song: &Song,
+        music_dir: &str,
+        rsp_settings: &RsPlayerSettings,
+        control: &StreamControl,
+        ram_loader: &RamLoader,
+    ) -> Result<Self> {
+        let mut hint = Hint::new();
+        let (s, radio_meta) = get_source(music_dir, song.source_file(), &mut hint, control, ram_loader);
+        let Ok(source) = s else {
+            return Err(format_err!("Failed to get source: {:?}", s.err()));
+        };
+        let is_seekable = source.is_seekable();
+        // Probe the media source stream for metadata and get the format reader.
+        let Ok(probed) = get_probe().format(
+            &hint,
+            MediaSourceStream::new(
+                source,
+                MediaSourceStreamOptions {
+                    buffer_len: (rsp_settings.input_stream_buffer_size_mb * 1024 * 1024).next_power_of_two(),
+                },
+            ),
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        ) else {
+            return Err(format_err!("Media source probe failed"));
+        };
+
+        let reader: Box<dyn FormatReader> = probed.format;
+
+        let Some(track) = first_supported_track(reader.tracks()) else {
+            return Err(format_err!("Invalid track"));
+        };
+        let track_id = track.id;
+        let codec_parameters = &track.codec_params;
+        let time_base = codec_parameters.time_base.unwrap_or_else(|| TimeBase::new(1, 1));
+        let start = song.cue_track.as_ref().map_or(Duration::ZERO, |cue| cue.start);
+        let start_ts = time_base.calc_timestamp(Time::from(start));
+        let end_ts = song
+            .cue_track
+            .as_ref()
+            .and_then(|cue| cue.end)
+            .map(|end| time_base.calc_timestamp(Time::from(end)));
+        let n_frames = end_ts.or_else(|| {
+            codec_parameters
+                .n_frames
+                .map(|frames| codec_parameters.start_ts + frames)
+        });
+        let cd = get_codecs().get_codec(codec_parameters.codec);
+        let player_info = PlayerInfo {
+            audio_format_bit: codec_parameters.bits_per_sample,
+            audio_format_channels: codec_parameters.channels.map(Channels::count),
+            audio_format_rate: codec_parameters.sample_rate,
+            codec: cd.map(|c| c.long_name.to_string()),
+            replay_gain_db: None,
+            native_dsd: false,
+            bit_perfect: None,
+        };
+        let decoder = get_codecs().make(codec_parameters, &DecoderOptions::default())?;
+
+        let mut track = Self {
+            reader,
+            decoder,
+            track_id,
+            time_base,
+            n_frames,
+            is_seekable,
+            player_info,
+            radio_meta,
+            seek_ts: 0,
+            current_ts: 0,
+            gain_factor: None,
+            start,
+            start_ts,
+            end_ts,
+        };
+        if start_ts > 0 {
+            track.seek(SeekTarget::Millis(0))?;
+        }
+        Ok(track)
+    }
+
+    pub const fn player_info(&self) -> &PlayerInfo {
+        &self.player_info
+    }
+
+    pub fn set_replay_gain(&mut self, gain_db: Option<f32>) {
+        self.player_info.replay_gain_db = gain_db;
+        self.gain_factor = gain_db.map(replay_gain::db_to_factor);
+    }
+
+    pub const fn gain_factor(&self) -> Option<f32> {
+        self.gain_factor
+    }
+
+    fn seek(&mut self, target: SeekTarget) -> Result<()> {
+        if !self.is_seekable {
+            return Err(format_err!("the stream is not seekable"));
+        }
+        let current = Duration::from(self.position(self.current_ts));
+        let total = self.n_frames.map(|n| Duration::from(self.position(n)));
+        let position = seek_position(target, current, total)?;
+        debug!("Seeking to {position:?}");
+        let seeked_to = self.reader.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(self.start + position),
+                track_id: Some(self.track_id),
+            },
+        )?;
+        self.seek_ts = seeked_to.required_ts;
+        Ok(())
+    }
+
+    /// Returns the next packet of the track, `None` at the end of the stream.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>> {
+        loop {
+            match self.reader.next_packet() {
+                Ok(packet) if packet.track_id() != self.track_id => {}
+                // The span of a CUE sheet track ends where the next track starts.
+                Ok(packet) if self.end_ts.is_some_and(|end| packet.ts() >= end) => return Ok(None),
+                Ok(packet) => {
+                    self.current_ts = packet.ts();
+                    return Ok(Some(packet));
+                }
+                Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Decodes the packet into audio samples. Returns `None` for packets that should not be
+    /// played: the ones that failed to decode and the ones before the seeked position.
+    pub fn decode(&mut self, packet: &Packet) -> Result<Option<AudioBufferRef<'_>>> {
+        match self.decoder.decode(packet) {
+            Ok(decoded) if packet.ts() >= self.seek_ts => Ok(Some(decoded)),
+            Ok(_) => Ok(None),
+            Err(Error::DecodeError(err)) => {
+                // Decode errors are not fatal. Print the error message and try to decode the next
+                // packet as usual.
+                warn!("decode error: {err}");
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Position of the timestamp within the song.
+    fn position(&self, ts: u64) -> Time {
+        self.time_base.calc_time(ts.saturating_sub(self.start_ts))
+    }
+
+    /// Frames left until the end of the track, `None` if the track length is unknown.
+    fn remaining_frames(&self) -> Option<u64> {
+        self.n_frames.map(|n| n.saturating_sub(self.current_ts))
+    }
+}
 
 #[allow(clippy::type_complexity, clippy::too_many_arguments, clippy::too_many_lines)]
 pub fn play_file(
-    path_str: &str,
+    song: &Song,
     stop_signal: &Arc<AtomicBool>,
-    skip_to_time: &Arc<AtomicU16>,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
     audio_device: &str,
     rsp_settings: &RsPlayerSettings,
     music_dir: &str,
     changes_tx: &Sender<StateChangeEvent>,
+    audio_output: &mut Option<Box<dyn AudioOutput>>,
+    replay_gain_db: Option<f32>,
+    dsp: &mut DspChain,
+    level_meter: &mut LevelMeter,
+    http_stream: &HttpStream,
+    ram_loader: &RamLoader,
+    next_song: &mut dyn FnMut() -> Option<(Song, Option<f32>)>,
+    handover: &mut Option<CrossfadeTrack>,
 ) -> Result<PlaybackResult> {
-    debug!("Playing file {}", path_str);
-    let mut hint = Hint::new();
-    let (s, radio_meta) = get_source(music_dir, path_str, &mut hint);
-    let Ok(source) = s else {
-        return Err(format_err!("Failed to get source: {:?}", s.err()));
-    };
-    if let Some(radio_meta) = radio_meta {
-        changes_tx.send(StateChangeEvent::CurrentSongEvent(
-            Song {
-                title: radio_meta.name,
-                album: radio_meta.description,
-                genre: radio_meta.genre,
-                file: radio_meta.url,
-                ..Default::default()
-            }
-        )).ok();
-    }
-    let is_seekable = source.is_seekable();
-    // Probe the media source stream for metadata and get the format reader.
-    let Ok(probed) = get_probe().format(
-        &hint,
-        MediaSourceStream::new(
-            source,
-            MediaSourceStreamOptions {
-                buffer_len: (rsp_settings.input_stream_buffer_size_mb * 1024 * 1024).next_power_of_two(),
-            },
-        ),
-        &FormatOptions::default(),
-        &MetadataOptions::default(),
-    ) else {
-        return Err(format_err!("Media source probe failed"));
-    };
-
-    let mut reader: Box<dyn FormatReader> = probed.format;
-
-    let tracks = reader.tracks();
-    let Some(track) = first_supported_track(tracks) else {
-        return Err(format_err!("Invalid track"));
+    debug!("Playing file {}", song.file);
+    // DSD files are read from disk as they play, without the RAM loader, and are neither crossfaded
+    // in nor out.
+    if dsd::is_dsd_file(&song.file) {
+        return super::dsd::play_dsd_file(
+            &song.file,
+            stop_signal,
+            pause_signal,
+            seek_request,
+            audio_device,
+            rsp_settings,
+            music_dir,
+            changes_tx,
+            audio_output,
+            replay_gain_db,
+            dsp,
+            level_meter,
+            http_stream,
+        );
+    }
+    let control = StreamControl::new(changes_tx, stop_signal);
+    // The song may already be playing, started by the crossfade from the previous one.
+    let (mut track, pending) = if let Some(crossfade) = handover.take() {
+        crossfade.into_parts()
+    } else {
+        let mut track = TrackDecoder::open(song, music_dir, rsp_settings, &control, ram_loader)?;
+        track.set_replay_gain(replay_gain_db);
+        (track, None)
     };
-    let track_id = track.id;
-    let codec_parameters = &track.codec_params;
-    let tb = codec_parameters.time_base.unwrap_or_else(|| TimeBase::new(1, 1));
-    let dur = codec_parameters
-        .n_frames
-        .map_or(1, |frames| codec_parameters.start_ts + frames);
-    let dur = tb.calc_time(dur);
-    let rate = codec_parameters.sample_rate;
-    let bps = codec_parameters.bits_per_sample;
-    let chan_num = codec_parameters.channels.map(Channels::count);
-    let cd = get_codecs().get_codec(codec_parameters.codec);
+    if let Some(radio_meta) = track.radio_meta.as_ref() {
+        changes_tx
+            .send(StateChangeEvent::CurrentSongEvent(radio_meta.station_song()))
+            .ok();
+    }
     changes_tx
-        .send(StateChangeEvent::PlayerInfoEvent(PlayerInfo {
-            audio_format_bit: bps,
-            audio_format_channels: chan_num,
-            audio_format_rate: rate,
-            codec: cd.map(|c| c.long_name.to_string()),
-        }))
+        .send(StateChangeEvent::PlayerInfoEvent(track.player_info.clone()))
         .expect("msg send failed");
+    // The level meters and the HTTP stream get the samples as they are written to the output.
+    let mut tap = |buffer: &AudioBufferRef<'_>| {
+        level_meter.measure(buffer);
+        http_stream.write(buffer);
+    };
+    if let Some(mut pending) = pending {
+        if dsp.is_active(pending.spec()) {
+            dsp.process(&mut pending);
+        }
+        tap(&pending.as_audio_buffer_ref());
+        write_buffer(pending.as_audio_buffer_ref(), audio_output, audio_device, rsp_settings)?;
+    }
 
-    let decode_opts = &DecoderOptions::default();
-    let mut decoder = get_codecs().make(codec_parameters, decode_opts)?;
-    let mut audio_output: Option<Box<dyn AudioOutput>> = None;
+    let dur = track.position(track.n_frames.unwrap_or(1));
+    let fade_frames = crossfade::frames(rsp_settings, track.player_info.audio_format_rate);
+    let mut incoming: Option<CrossfadeTrack> = None;
+    let mut crossfade_started = false;
     let mut last_current_time = 0;
+    let mut seek_pending = false;
     // Decode and play the packets belonging to the selected track.
     let loop_result = loop {
         if stop_signal.load(Ordering::Relaxed) {
             debug!("Exit from play thread due to running flag change");
             break Ok(PlaybackResult::PlaybackStopped);
         }
-        if is_seekable && skip_to_time.load(Ordering::Relaxed) > 0 {
-            let skip_to = skip_to_time.swap(0, Ordering::Relaxed);
-            debug!("Seeking to {}", skip_to);
-            let seek_result = reader.seek(
-                SeekMode::Accurate,
-                SeekTo::Time {
-                    time: Time::new(u64::from(skip_to), 0.0),
-                    track_id: Some(track_id),
-                },
-            );
-            if let Err(err) = seek_result {
-                warn!("Seek failed: {}", err);
+        if pause_signal.load(Ordering::Relaxed) {
+            park_while_paused(pause_signal, stop_signal, changes_tx, &mut |paused| {
+                output::set_paused(audio_output, paused);
+            });
+            continue;
+        }
+        if let Some(target) = take_seek_request(seek_request) {
+            match track.seek(target) {
+                Ok(()) => {
+                    seek_pending = true;
+                    // The next song is played from its start after seeking away from the fade, and
+                    // crossfaded again once the song reaches the fade.
+                    incoming = None;
+                    crossfade_started = false;
+                }
+                Err(err) => send_seek_error(changes_tx, &err),
             }
-        };
+        }
 
         //  Get the next packet from the format reader.
-        let packet = match reader.next_packet() {
-            Ok(packet) => packet,
-            Err(Error::IoError(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+        let packet = match track.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => {
+                *handover = incoming.take();
                 break Ok(PlaybackResult::SongFinished);
             }
-            Err(err) => break Err(err.into()),
+            // A radio stream gives up reconnecting once the playback is stopped.
+            Err(_) if stop_signal.load(Ordering::Relaxed) => break Ok(PlaybackResult::PlaybackStopped),
+            Err(err) => break Err(err),
         };
 
-        let current_time = tb.calc_time(packet.ts()).seconds;
+        if let Some(song) = track.radio_meta.as_ref().and_then(RadioMeta::stream_title_song) {
+            changes_tx.send(StateChangeEvent::CurrentSongEvent(song)).ok();
+        }
+
+        let current_time = track.position(packet.ts()).seconds;
         if current_time != last_current_time {
             last_current_time = current_time;
             changes_tx
@@ -155,82 +353,200 @@ pub fn play_file(
                 }))
                 .expect("msg send failed");
         }
-        // Decode the packet into audio samples.
-        match decoder.decode(&packet) {
-            Ok(decoded_buff) => {
-                // If the audio output is not open, try to open it.
-                if audio_output.is_none() {
-                    // Get the audio buffer specification. This is a description of the decoded
-                    // audio buffer's sample format and sample rate.
-                    let spec = *decoded_buff.spec();
-
-                    // Get the capacity of the decoded buffer. Note that this is capacity, not
-                    // length! The capacity of the decoded buffer is constant for the life of the
-                    // decoder, but the length is not.
-                    let duration = decoded_buff.capacity() as u64;
-
-                    // Try to open the audio output.
-                    let Ok(audio_out) = try_open(spec, duration, audio_device, rsp_settings) else {
-                        break Err(format_err!("Failed to open audio output {}", audio_device));
-                    };
-                    debug!("Audio opened");
-
-                    audio_output.replace(audio_out);
-                } else {
-                    // TODO: Check the audio spec. and duration hasn't changed.
-                }
-                // Write the decoded audio samples to the audio output if the presentation timestamp
-                // for the packet is >= the seeked position (0 if not seeking).
 
-                if packet.ts() > 0 {
-                    if let Some(audio_output) = audio_output.as_mut() {
-                        trace!("Before audio write");
-                        _ = audio_output.write(decoded_buff);
+        // Start decoding the next song once the current one reaches the crossfade.
+        let remaining_frames = track.remaining_frames().unwrap_or(u64::MAX);
+        if fade_frames > 0 && !crossfade_started && remaining_frames <= fade_frames {
+            crossfade_started = true;
+            incoming = next_song().and_then(|(next, next_gain_db)| {
+                CrossfadeTrack::start(
+                    &next,
+                    next_gain_db,
+                    &track.player_info,
+                    music_dir,
+                    rsp_settings,
+                    fade_frames,
+                    &control,
+                    ram_loader,
+                )
+            });
+        }
+
+        let curve = rsp_settings.crossfade_curve;
+        let gain_factor = track.gain_factor();
+        let write_result = match track.decode(&packet) {
+            Ok(Some(decoded_buff)) => {
+                // Samples are converted to f32 only when they have to be altered, otherwise the
+                // decoded buffer is written as is.
+                let mut altered = gain_factor.map(|factor| replay_gain::amplify(&decoded_buff, factor));
+                let dsp_active = dsp.is_active(decoded_buff.spec());
+                if incoming.is_some() || dsp_active {
+                    let buffer = altered.get_or_insert_with(|| to_f32_buffer(&decoded_buff));
+                    if let Some(crossfade) = incoming.as_mut() {
+                        crossfade.mix(buffer, remaining_frames, curve);
+                    }
+                    if dsp_active {
+                        dsp.process(buffer);
                     }
                 }
+                match altered.as_ref() {
+                    Some(altered) => tap(&altered.as_audio_buffer_ref()),
+                    None => tap(&decoded_buff),
+                }
+                match altered {
+                    Some(altered) => {
+                        write_buffer(altered.as_audio_buffer_ref(), audio_output, audio_device, rsp_settings)
+                    }
+                    None => write_buffer(decoded_buff, audio_output, audio_device, rsp_settings),
+                }
             }
-            Err(Error::DecodeError(err)) => {
-                // Decode errors are not fatal. Print the error message and try to decode the next
-                // packet as usual.
-                warn!("decode error: {}", err);
-            }
-            Err(err) => break Err(err.into()),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = write_result {
+            break Err(err);
+        }
+        // The playback continues from the first packet at or after the seeked position.
+        if seek_pending && packet.ts() >= track.seek_ts {
+            seek_pending = false;
+            changes_tx
+                .send(StateChangeEvent::SeekCompletedEvent(SongProgress {
+                    total_time: Duration::from_secs(dur.seconds),
+                    current_time: Duration::from(track.position(packet.ts())),
+                }))
+                .ok();
+        }
+        let bit_perfect = audio_output.as_ref().map(|out| out.is_bit_perfect());
+        if bit_perfect != track.player_info.bit_perfect {
+            track.player_info.bit_perfect = bit_perfect;
+            changes_tx
+                .send(StateChangeEvent::PlayerInfoEvent(track.player_info.clone()))
+                .expect("msg send failed");
         }
     };
-    // Flush the audio output to finish playing back any leftover samples.
-    if let Some(audio_output) = audio_output.as_mut() {
-        audio_output.flush();
-    }
+    // The audio output is intentionally not flushed here: the next song in the queue is opened
+    // while the ring buffer drains, so consecutive songs play without a gap.
     debug!("Play finished with result {:?}", loop_result);
     loop_result
 }
 
-fn get_source(music_dir: &str, path_str: &str, hint: &mut Hint) -> (Result<Box<dyn MediaSource>, anyhow::Error>, Option<RadioMeta>) {
+/// Seek requested by the user, taken by the playback thread.
+pub type SeekRequest = Arc<Mutex<Option<SeekTarget>>>;
+
+pub fn take_seek_request(seek_request: &SeekRequest) -> Option<SeekTarget> {
+    seek_request.lock().unwrap().take()
+}
+
+pub fn send_seek_error(changes_tx: &Sender<StateChangeEvent>, err: &anyhow::Error) {
+    warn!("Seek failed: {err}");
+    changes_tx
+        .send(StateChangeEvent::NotificationError(format!("Seek failed: {err}")))
+        .ok();
+}
+
+/// Position within the song the target points to, limited to the song duration when it is known.
+pub fn seek_position(target: SeekTarget, current: Duration, total: Option<Duration>) -> Result<Duration> {
+    let position = match target {
+        SeekTarget::Millis(millis) => Duration::from_millis(millis),
+        SeekTarget::RelativeMillis(millis) if millis < 0 => {
+            current.saturating_sub(Duration::from_millis(millis.unsigned_abs()))
+        }
+        SeekTarget::RelativeMillis(millis) => current + Duration::from_millis(millis.unsigned_abs()),
+        SeekTarget::Percent(percent) => total
+            .ok_or_else(|| format_err!("the song duration is unknown"))?
+            .mul_f32(percent.clamp(0.0, 100.0) / 100.0),
+    };
+    Ok(total.map_or(position, |total| position.min(total)))
+}
+
+/// Parks the playback thread until the playback is resumed or stopped. The decoder and the
+/// samples held by the output are kept, so the song continues exactly where it was paused.
+pub fn park_while_paused(
+    pause_signal: &AtomicBool,
+    stop_signal: &AtomicBool,
+    changes_tx: &Sender<StateChangeEvent>,
+    set_paused: &mut dyn FnMut(bool),
+) {
+    set_paused(true);
+    changes_tx
+        .send(StateChangeEvent::PlaybackStateEvent(PlayerState::PAUSED))
+        .ok();
+    debug!("Playback paused");
+    while pause_signal.load(Ordering::Relaxed) && !stop_signal.load(Ordering::Relaxed) {
+        std::thread::park();
+    }
+    if stop_signal.load(Ordering::Relaxed) {
+        return;
+    }
+    set_paused(false);
+    changes_tx
+        .send(StateChangeEvent::PlaybackStateEvent(PlayerState::PLAYING))
+        .ok();
+    debug!("Playback resumed");
+}
+
+pub fn write_buffer(
+    decoded_buff: AudioBufferRef<'_>,
+    audio_output: &mut Option<Box<dyn AudioOutput>>,
+    audio_device: &str,
+    rsp_settings: &RsPlayerSettings,
+) -> Result<()> {
+    // Get the audio buffer specification. This is a description of the decoded
+    // audio buffer's sample format and sample rate.
+    let spec = *decoded_buff.spec();
+
+    // The output stays open between songs, so it is reused as long as the next song
+    // has the same signal spec. Otherwise drain what is left and reopen it.
+    if audio_output.as_ref().is_some_and(|out| out.spec() != spec) {
+        debug!("Signal spec changed to {spec:?}, reopening audio output");
+        if let Some(mut out) = audio_output.take() {
+            out.flush();
+        }
+    }
+    if audio_output.is_none() {
+        // Get the capacity of the decoded buffer. Note that this is capacity, not
+        // length! The capacity of the decoded buffer is constant for the life of the
+        // decoder, but the length is not.
+        let duration = decoded_buff.capacity() as u64;
+
+        // Try to open the audio output.
+        let audio_out = try_open(spec, duration, audio_device, rsp_settings)
+            .map_err(|err| OutputError(format!("Failed to open audio output {audio_device}: {err}")))?;
+        debug!("Audio opened");
+
+        audio_output.replace(audio_out);
+    }
+    if let Some(audio_output) = audio_output.as_mut() {
+        trace!("Before audio write");
+        audio_output
+            .write(decoded_buff)
+            .map_err(|err| OutputError(format!("Failed to write to {audio_device}: {err}")))?;
+    }
+    Ok(())
+}
+
+/// Copies the decoded samples into an `f32` buffer that can be altered before output.
+pub fn to_f32_buffer(decoded: &AudioBufferRef<'_>) -> AudioBuffer<f32> {
+    let mut buffer = decoded.make_equivalent::<f32>();
+    decoded.convert(&mut buffer);
+    buffer
+}
+
+fn get_source(
+    music_dir: &str,
+    path_str: &str,
+    hint: &mut Hint,
+    control: &StreamControl,
+    ram_loader: &RamLoader,
+) -> (Result<Box<dyn MediaSource>, anyhow::Error>, Option<RadioMeta>) {
     let mut radio_meta = None;
     let source = if path_str.starts_with("http") {
-        let agent = ureq::AgentBuilder::new()
-            .timeout_connect(Duration::from_secs(5))
-            .timeout_read(Duration::from_secs(5))
-            .timeout_write(Duration::from_secs(5))
-            .build();
-        let Ok(resp) = agent.get(path_str).set("accept", "*/*").call() else {
-            return (Err(format_err!("Failed to get url {path_str}")), None);
-        };
-        let status = resp.status();
-        info!("response status code:{status} / status text:{}", resp.status_text());
-        resp.headers_names()
-            .iter()
-            .for_each(|header| debug!("{header} = {:?}", resp.header(header).unwrap_or("")));
-        radio_meta = Some(RadioMeta {
-            name: resp.header("icy-name").map(ToString::to_string),
-            description: resp.header("icy-description").map(ToString::to_string),
-            genre: resp.header("icy-genre").map(ToString::to_string),
-            url: path_str.to_string(),
-        });
-        if status == 200 {
-            Box::new(ReadOnlySource::new(resp.into_reader())) as Box<dyn MediaSource>
-        } else {
-            return (Err(format_err!("Invalid streaming url {path_str}")), None);
+        match radio::open(path_str, control) {
+            Ok((source, meta)) => {
+                radio_meta = meta;
+                source
+            }
+            Err(err) => return (Err(err), None),
         }
     } else {
         let path = Path::new(music_dir).join(path_str);
@@ -239,7 +555,9 @@ fn get_source(music_dir: &str, path_str: &str, hint: &mut Hint) -> (Result<Box<d
                 hint.with_extension(extension_str);
             }
         }
-        if let Ok(p) = File::open(path) {
+        if let Some(loaded) = ram_loader.open(&path, path_str) {
+            loaded
+        } else if let Ok(p) = File::open(path) {
             Box::new(p)
         } else {
             return (Err(format_err!("Unable to open file: {}", path_str)), None);
@@ -1,100 +1,685 @@
-use std::{env, path::Path};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::*;
-use crate::{Player, CATEGORY_ID_BY_FOLDER};
-use api_models::{player::Song, settings::PlaybackQueueSetting};
-use log::info;
+use anyhow::{format_err, Result};
+use api_models::common::{HttpStreamMode, SeekTarget, VolumeCrtlType};
+use api_models::player::{CueTrack, Song};
+use api_models::settings::{
+    ConvolutionSettings, DspSettings, OutputChannelSettings, RsPlayerSettings, Settings, VolumeControlSettings,
+};
+use api_models::state::{PlayerState, StateChangeEvent};
+use rsplayer_metadata::album_repository::AlbumRepository;
+use rsplayer_metadata::metadata_service::MetadataService;
+use rsplayer_metadata::play_statistic_repository::PlayStatisticsRepository;
+use rsplayer_metadata::podcast_service::PodcastService;
+use rsplayer_metadata::queue_service::QueueService;
+use rsplayer_metadata::song_repository::SongRepository;
+use symphonia::core::audio::{AudioBufferRef, Channels, SignalSpec};
+use tokio::sync::broadcast::{self, Receiver};
 
+use super::dsp::{DspChain, DspControl};
+use super::http_stream::HttpStream;
+use super::level_meter::LevelMeter;
+use super::output::{AudioOutput, OutputError};
+use super::player_service::PlayerService;
+use super::ram_loader::RamLoader;
+use super::symphonia::{play_file, seek_position, PlaybackResult, SeekRequest};
+use super::volume::VolumeControl;
+
+const RATE: u32 = 44100;
 
 #[test]
-fn test_get_dynamic_pl(){
-    let player = create_player();
-    let dpl = player.get_dynamic_playlists(vec![CATEGORY_ID_BY_FOLDER.to_owned()], 0, 10);
-    assert_eq!(dpl[0].category_id, CATEGORY_ID_BY_FOLDER);
+fn should_write_decoded_samples_to_wav_file() {
+    let ctx = Context::default();
+    let input = ctx.create_wav("in.wav", RATE as usize * 3 / 2);
+    let output = ctx.path("out.wav");
+
+    let (result, mut changes_rx) = play(&input, &format!("file:{}", output.display()));
+
+    assert_eq!(result, PlaybackResult::SongFinished);
+    assert_eq!(read_wav_samples(&output), test_samples(RATE as usize * 3 / 2));
+    let events = received_events(&mut changes_rx);
+    assert!(events.iter().any(|event| matches!(
+        event,
+        StateChangeEvent::PlayerInfoEvent(info) if info.audio_format_rate == Some(RATE)
+    )));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        StateChangeEvent::SongTimeEvent(progress) if progress.current_time == Duration::from_secs(1)
+    )));
 }
 
 #[test]
-fn should_play_radio_url() {
-    let player = create_player();
-    player.add_song_in_queue(
-        "https://fluxmusic.api.radiosphere.io/channels/90s/stream.aac?quality=10",
+fn should_tell_output_failures_apart_from_unplayable_songs() {
+    let ctx = Context::default();
+    let input = ctx.create_wav("in.wav", RATE as usize / 10);
+
+    let output = ctx.path("missing/out.wav");
+    let err = try_play(&input, &format!("file:{}", output.display())).unwrap_err();
+    assert!(err.is::<OutputError>());
+
+    let err = try_play(&ctx.path("missing.wav"), "null:fast").unwrap_err();
+    assert!(!err.is::<OutputError>());
+}
+
+#[tokio::test]
+async fn should_stop_the_queue_without_failing_the_song_when_the_output_fails() {
+    let ctx = Context::default();
+    ctx.create_wav("in.wav", RATE as usize / 10);
+    let (player, queue, mut changes_rx) = ctx.create_player_service("null:fast", RsPlayerSettings::default());
+    queue.add_song(&Song {
+        file: "in.wav".to_string(),
+        ..Default::default()
+    });
+
+    let result = player
+        .play_all_in_queue(Some(Box::new(UnpluggedOutput)))
+        .join()
+        .unwrap();
+
+    assert_eq!(result, PlaybackResult::PlaybackFailed);
+    assert_eq!(queue.get_current_song().unwrap().play_error, None);
+    let events = received_events(&mut changes_rx);
+    assert!(events.iter().any(|event| matches!(
+        event,
+        StateChangeEvent::NotificationError(msg) if msg.ends_with("device unplugged")
+    )));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, StateChangeEvent::PlaybackStateEvent(PlayerState::STOPPED))));
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, StateChangeEvent::PlaybackStateEvent(PlayerState::ERROR(_)))));
+}
+
+#[tokio::test]
+async fn should_move_the_queue_to_the_crossfaded_song_once_it_took_over() {
+    let ctx = Context::default();
+    let output = ctx.path("out.wav");
+    let (player, queue, mut changes_rx) = ctx.create_player_service(
+        &format!("file:{}", output.display()),
+        RsPlayerSettings {
+            crossfade_ms: 500,
+            ..Default::default()
+        },
     );
-    player.add_song_in_queue("https://stream.rcast.net/66036");
+    for name in ["a.wav", "b.wav"] {
+        ctx.create_wav(name, RATE as usize);
+        queue.add_song(&Song {
+            file: name.to_string(),
+            ..Default::default()
+        });
+    }
+
+    let result = player.play_all_in_queue(None).join().unwrap();
+
+    assert_eq!(result, PlaybackResult::QueueFinished);
+    drop(player);
+    // The songs overlap for the crossfade, which starts at the first packet within it.
+    let frames = read_wav_samples(&output).len() / 2;
+    assert!((RATE as usize * 3 / 2..RATE as usize * 31 / 20).contains(&frames));
+    assert_eq!(queue.get_current_song().unwrap().file, "b.wav");
+    let songs = received_events(&mut changes_rx)
+        .into_iter()
+        .filter_map(|event| match event {
+            StateChangeEvent::CurrentSongEvent(song) => Some(song.file),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(songs, ["a.wav", "b.wav"]);
+}
+
+#[tokio::test]
+async fn should_keep_the_queue_on_the_fading_out_song_when_stopped() {
+    let ctx = Context::default();
+    let (player, queue, _changes_rx) = ctx.create_player_service(
+        "null:",
+        RsPlayerSettings {
+            crossfade_ms: 800,
+            ..Default::default()
+        },
+    );
+    for name in ["a.wav", "b.wav"] {
+        ctx.create_wav(name, RATE as usize);
+        queue.add_song(&Song {
+            file: name.to_string(),
+            ..Default::default()
+        });
+    }
+
     player.play_from_current_queue_song();
-    std::thread::sleep(Duration::from_secs(10));
-    player.play_next_song();
-    std::thread::sleep(Duration::from_secs(10));
-    player.stop_current_song();
-    player.await_playing_song_to_finish();
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(player.stop_current_song(), Some(PlaybackResult::PlaybackStopped));
+
+    assert_eq!(queue.get_current_song().unwrap().file, "a.wav");
 }
+
 #[test]
-fn should_play_all_songs_in_queue() {
-    let player = create_player();
-    player.add_song_in_queue("mp3");
-    player.add_song_in_queue("flac");
-    player.play_from_current_queue_song();
-    assert!(player.await_playing_song_to_finish()[0].is_ok());
+fn should_play_in_real_time_to_null_output() {
+    let ctx = Context::default();
+    let input = ctx.create_wav("in.wav", RATE as usize / 2);
+
+    let start = Instant::now();
+    let (result, _) = play(&input, "null:");
+    assert_eq!(result, PlaybackResult::SongFinished);
+    assert!(start.elapsed() >= Duration::from_millis(450));
+
+    let start = Instant::now();
+    let (result, _) = play(&input, "null:fast");
+    assert_eq!(result, PlaybackResult::SongFinished);
+    assert!(start.elapsed() < Duration::from_millis(450));
 }
 
-fn create_player() -> RsPlayer {
+#[test]
+fn should_resume_paused_song_without_losing_samples() {
     let ctx = Context::default();
-    let mut ms = MetadataService::default();
-    ms.expect_find_song_by_id().returning(|song_id| {
-        Some(Song {
-            file: if song_id.starts_with("http") {
-                song_id.to_string()
-            } else {
-                format!("music.{song_id}")
-            },
-            id: song_id.to_string(),
-            ..Default::default()
+    let input = ctx.create_wav("in.wav", RATE as usize);
+    let output = ctx.path("out.wav");
+    let pause_signal = Arc::new(AtomicBool::new(true));
+
+    let playback = {
+        let pause_signal = pause_signal.clone();
+        let audio_device = format!("file:{}", output.display());
+        thread::spawn(move || play_with_pause(&input, &audio_device, &pause_signal))
+    };
+    thread::sleep(Duration::from_millis(200));
+    assert!(!playback.is_finished());
+    pause_signal.store(false, Ordering::Relaxed);
+    playback.thread().unpark();
+    let (result, mut changes_rx) = playback.join().unwrap();
+
+    assert_eq!(result, PlaybackResult::SongFinished);
+    assert_eq!(read_wav_samples(&output), test_samples(RATE as usize));
+    let states = received_events(&mut changes_rx)
+        .into_iter()
+        .filter_map(|event| match event {
+            StateChangeEvent::PlaybackStateEvent(state) => Some(state),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(states, [PlayerState::PAUSED, PlayerState::PLAYING]);
+}
+
+#[test]
+fn should_start_playing_from_seeked_position() {
+    let ctx = Context::default();
+    let input = ctx.create_wav("in.wav", RATE as usize * 3 / 2);
+    let output = ctx.path("out.wav");
+    let seek_request = Arc::new(Mutex::new(Some(SeekTarget::Millis(500))));
+
+    let (result, mut changes_rx) = play_with(
+        &input,
+        &format!("file:{}", output.display()),
+        &Arc::new(AtomicBool::new(false)),
+        &seek_request,
+    );
+
+    assert_eq!(result, PlaybackResult::SongFinished);
+    let expected = test_samples(RATE as usize * 3 / 2);
+    let played = read_wav_samples(&output);
+    assert_eq!(played, expected[expected.len() - played.len()..]);
+    let skipped_frames = u64::try_from((expected.len() - played.len()) / 2).unwrap();
+    let landed = Duration::from_micros(skipped_frames * 1_000_000 / u64::from(RATE));
+    assert!(landed >= Duration::from_millis(500));
+    assert!(received_events(&mut changes_rx).iter().any(|event| matches!(
+        event,
+        StateChangeEvent::SeekCompletedEvent(progress)
+            if progress.current_time.abs_diff(landed) < Duration::from_millis(1)
+    )));
+}
+
+#[test]
+fn should_resolve_seek_targets_within_song() {
+    let current = Duration::from_secs(10);
+    let total = Some(Duration::from_secs(100));
+    let position = |target| seek_position(target, current, total).unwrap();
+
+    assert_eq!(position(SeekTarget::Millis(1500)), Duration::from_millis(1500));
+    assert_eq!(position(SeekTarget::Millis(200_000)), Duration::from_secs(100));
+    assert_eq!(position(SeekTarget::RelativeMillis(5000)), Duration::from_secs(15));
+    assert_eq!(position(SeekTarget::RelativeMillis(-15000)), Duration::ZERO);
+    assert_eq!(position(SeekTarget::Percent(25.0)), Duration::from_secs(25));
+    assert!(seek_position(SeekTarget::Percent(25.0), current, None).is_err());
+}
+
+#[test]
+fn should_play_radio_stream_from_station_playlist() {
+    let ctx = Context::default();
+    let stream = std::fs::read(ctx.create_wav("in.wav", RATE as usize / 2)).unwrap();
+    let output = ctx.path("out.wav");
+    let url = serve(vec![
+        ("audio/x-mpegurl", b"#EXTM3U\n#EXTINF:-1,Radio\nstream.wav\n".to_vec()),
+        ("audio/wav", stream),
+    ]);
+    let station_url = format!("{url}/radio.m3u");
+
+    let (result, mut changes_rx) = play(Path::new(&station_url), &format!("file:{}", output.display()));
+
+    assert_eq!(result, PlaybackResult::SongFinished);
+    assert_eq!(read_wav_samples(&output), test_samples(RATE as usize / 2));
+    assert!(received_events(&mut changes_rx).iter().any(|event| matches!(
+        event,
+        StateChangeEvent::CurrentSongEvent(song) if song.file == station_url
+    )));
+}
+
+#[test]
+fn should_play_cue_sheet_tracks_gaplessly() {
+    let ctx = Context::default();
+    let input = ctx.create_wav("image.wav", RATE as usize * 5);
+    let output = ctx.path("out.wav");
+    let track = |start, end: Option<u64>| Song {
+        file: format!("image.wav#{start}"),
+        cue_track: Some(CueTrack {
+            image_file: input.to_str().unwrap().to_string(),
+            start: Duration::from_millis(start),
+            end: end.map(Duration::from_millis),
+        }),
+        ..Default::default()
+    };
+
+    let (results, mut changes_rx) = play_songs(
+        &[track(1000, Some(3000)), track(3000, None)],
+        &format!("file:{}", output.display()),
+        &Arc::new(AtomicBool::new(false)),
+        &Arc::new(Mutex::new(None)),
+    );
+
+    assert_eq!(results, [PlaybackResult::SongFinished, PlaybackResult::SongFinished]);
+    // Both tracks play from the same image without a gap, starting at the first one.
+    let expected = test_samples(RATE as usize * 5);
+    let played = read_wav_samples(&output);
+    let skipped_frames = (expected.len() - played.len()) / 2;
+    assert_eq!(played, expected[skipped_frames * 2..]);
+    assert!((RATE as usize..RATE as usize * 11 / 10).contains(&skipped_frames));
+    let progress = received_events(&mut changes_rx)
+        .into_iter()
+        .filter_map(|event| match event {
+            StateChangeEvent::SongTimeEvent(progress) => Some((progress.current_time, progress.total_time)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(progress, [(Duration::from_secs(1), Duration::from_secs(2)); 2]);
+}
+
+#[test]
+fn should_play_consecutive_songs_into_the_same_output_stream() {
+    let ctx = Context::default();
+    let first = ctx.create_wav("first.wav", RATE as usize * 3 / 2);
+    let second = ctx.create_wav("second.wav", RATE as usize);
+    let output = ctx.path("out.wav");
+    let song = |path: &Path| Song {
+        file: path.to_str().unwrap().to_string(),
+        ..Default::default()
+    };
+    let mut data_lens = vec![];
+
+    let (results, _) = play_songs_with(
+        &[song(&first), song(&second)],
+        &format!("file:{}", output.display()),
+        &Arc::new(AtomicBool::new(false)),
+        &Arc::new(Mutex::new(None)),
+        &mut |audio_output| {
+            assert!(audio_output.is_some());
+            data_lens.push(wav_data_len(&output));
+        },
+    );
+
+    assert_eq!(results, [PlaybackResult::SongFinished, PlaybackResult::SongFinished]);
+    // The header is only updated when the output is drained, reopening it starts the file over.
+    assert_eq!(data_lens, [0, 0]);
+    let mut expected = test_samples(RATE as usize * 3 / 2);
+    expected.extend(test_samples(RATE as usize));
+    assert_eq!(read_wav_samples(&output), expected);
+}
+
+#[test]
+fn should_seek_in_remote_file_read_with_range_requests() {
+    let ctx = Context::default();
+    // Longer than the first part read from the response to the request of the whole file.
+    let file = std::fs::read(ctx.create_wav("in.wav", RATE as usize * 8)).unwrap();
+    let output = ctx.path("out.wav");
+    let url = format!("{}/music/in.wav", serve_file(file));
+    let seek_request = Arc::new(Mutex::new(Some(SeekTarget::Millis(6000))));
+
+    let (result, mut changes_rx) = play_with(
+        Path::new(&url),
+        &format!("file:{}", output.display()),
+        &Arc::new(AtomicBool::new(false)),
+        &seek_request,
+    );
+
+    assert_eq!(result, PlaybackResult::SongFinished);
+    let expected = test_samples(RATE as usize * 8);
+    let played = read_wav_samples(&output);
+    assert!(played.len() < expected.len() / 4);
+    assert_eq!(played, expected[expected.len() - played.len()..]);
+    let events = received_events(&mut changes_rx);
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, StateChangeEvent::SeekCompletedEvent(_))));
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, StateChangeEvent::CurrentSongEvent(_))));
+}
+
+fn play(path: &Path, audio_device: &str) -> (PlaybackResult, Receiver<StateChangeEvent>) {
+    play_with_pause(path, audio_device, &Arc::new(AtomicBool::new(false)))
+}
+
+fn play_with_pause(
+    path: &Path,
+    audio_device: &str,
+    pause_signal: &Arc<AtomicBool>,
+) -> (PlaybackResult, Receiver<StateChangeEvent>) {
+    play_with(path, audio_device, pause_signal, &Arc::new(Mutex::new(None)))
+}
+
+fn play_with(
+    path: &Path,
+    audio_device: &str,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
+) -> (PlaybackResult, Receiver<StateChangeEvent>) {
+    let song = Song {
+        file: path.to_str().unwrap().to_string(),
+        ..Default::default()
+    };
+    let (mut results, changes_rx) = play_songs(&[song], audio_device, pause_signal, seek_request);
+    (results.remove(0), changes_rx)
+}
+
+/// Plays the songs one after another to the same audio output.
+fn play_songs(
+    songs: &[Song],
+    audio_device: &str,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
+) -> (Vec<PlaybackResult>, Receiver<StateChangeEvent>) {
+    play_songs_with(songs, audio_device, pause_signal, seek_request, &mut |_| {})
+}
+
+/// Plays the songs one after another to the same audio output, which is inspected after each song.
+fn play_songs_with(
+    songs: &[Song],
+    audio_device: &str,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
+    after_song: &mut dyn FnMut(Option<&dyn AudioOutput>),
+) -> (Vec<PlaybackResult>, Receiver<StateChangeEvent>) {
+    let (results, changes_rx) = try_play_songs_with(songs, audio_device, pause_signal, seek_request, after_song);
+    (results.into_iter().map(Result::unwrap).collect(), changes_rx)
+}
+
+fn try_play(path: &Path, audio_device: &str) -> Result<PlaybackResult> {
+    let song = Song {
+        file: path.to_str().unwrap().to_string(),
+        ..Default::default()
+    };
+    let (mut results, _) = try_play_songs_with(
+        &[song],
+        audio_device,
+        &Arc::new(AtomicBool::new(false)),
+        &Arc::new(Mutex::new(None)),
+        &mut |_| {},
+    );
+    results.remove(0)
+}
+
+fn try_play_songs_with(
+    songs: &[Song],
+    audio_device: &str,
+    pause_signal: &Arc<AtomicBool>,
+    seek_request: &SeekRequest,
+    after_song: &mut dyn FnMut(Option<&dyn AudioOutput>),
+) -> (Vec<Result<PlaybackResult>>, Receiver<StateChangeEvent>) {
+    let (changes_tx, changes_rx) = broadcast::channel(100);
+    let volume = VolumeControl::new(&VolumeControlSettings {
+        volume_step: 2,
+        ctrl_device: VolumeCrtlType::Alsa,
+        alsa_mixer: None,
+        rotary_enabled: false,
+        rotary_event_device_path: String::new(),
+        software_volume_range_db: 60,
+    });
+    let mut dsp = DspChain::new(DspControl::new(
+        DspSettings::default(),
+        ConvolutionSettings::default(),
+        OutputChannelSettings::default(),
+        volume,
+    ));
+    let mut audio_output: Option<Box<dyn AudioOutput>> = None;
+    let mut level_meter = LevelMeter::new(&Arc::new(AtomicBool::new(false)), 20, &changes_tx);
+    let stop_signal = Arc::new(AtomicBool::new(false));
+    let ram_loader = RamLoader::new(None, &changes_tx, &stop_signal);
+    let results = songs
+        .iter()
+        .map(|song| {
+            let result = play_file(
+                song,
+                &stop_signal,
+                pause_signal,
+                seek_request,
+                audio_device,
+                &RsPlayerSettings::default(),
+                "",
+                &changes_tx,
+                &mut audio_output,
+                None,
+                &mut dsp,
+                &mut level_meter,
+                &HttpStream::new(HttpStreamMode::Off),
+                &ram_loader,
+                &mut || None,
+                &mut None,
+            );
+            after_song(audio_output.as_deref());
+            result
         })
+        .collect();
+    // Finishes the WAV file.
+    drop(audio_output);
+    (results, changes_rx)
+}
+
+/// Serves the responses to the requests in order, returns the server URL.
+fn serve(responses: Vec<(&'static str, Vec<u8>)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for (content_type, body) in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
     });
-    ms.expect_get_all_songs_iterator().returning(|| {
-        Box::new(vec![Song::default(), Song::default()].into_iter())
+    url
+}
+
+/// Serves the file to any number of requests, in parts when a range is requested.
+fn serve_file(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                if let Some(value) = line.to_lowercase().trim().strip_prefix("range: bytes=") {
+                    let (start, end) = value.split_once('-').unwrap();
+                    range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                }
+                line.clear();
+            }
+            let (status, part) = match range {
+                Some((start, end)) => ("206 Partial Content", &body[start..=end]),
+                None => ("200 OK", body.as_slice()),
+            };
+            let header = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: audio/wav\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                part.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            // The client stops reading the whole file once it has the first part.
+            _ = stream.write_all(part);
+        }
     });
+    url
+}
 
-    let queue = Arc::new(PlaybackQueue::new(&PlaybackQueueSetting {
-        db_path: ctx.db_dir.clone(),
-    }));
-    RsPlayer {
-        metadata_service: Arc::new(ms),
-        playlist_service: Arc::new(PlaylistService::new(&PlaylistSetting {
-            db_path: format!("{}plista", ctx.db_dir),
-        })),
-        queue: queue.clone(),
-        symphonia_player: SymphoniaPlayer::new(
-            queue,
-            "plughw:CARD=PCH,DEV=0".to_string(),
-            1,
-            "../rsplayer_metadata/assets".to_string(),
-        ),
-        play_handle: Arc::new(Mutex::new(vec![])),
-        music_dir_depth: 0,
+fn received_events(changes_rx: &mut Receiver<StateChangeEvent>) -> Vec<StateChangeEvent> {
+    let mut events = vec![];
+    while let Ok(event) = changes_rx.try_recv() {
+        events.push(event);
+    }
+    events
+}
+
+/// Samples written by the WAV file output, scaled back to 16 bit.
+fn read_wav_samples(path: &Path) -> Vec<i16> {
+    let played = std::fs::read(path).unwrap();
+    played[44..]
+        .chunks_exact(4)
+        .map(|b| i16::try_from(i32::from_le_bytes([b[0], b[1], b[2], b[3]]) >> 16).unwrap())
+        .collect()
+}
+
+/// Size of the samples in the WAV file header.
+fn wav_data_len(path: &Path) -> u32 {
+    let header = std::fs::read(path).unwrap();
+    u32::from_le_bytes([header[40], header[41], header[42], header[43]])
+}
+
+/// Interleaved stereo samples, a ramp on the left channel and its inverse on the right one.
+fn test_samples(frames: usize) -> Vec<i16> {
+    (i16::MIN..=i16::MAX)
+        .cycle()
+        .take(frames)
+        .flat_map(|sample| [sample, !sample])
+        .collect()
+}
+
+/// Output of the test songs whose device is gone.
+struct UnpluggedOutput;
+
+impl AudioOutput for UnpluggedOutput {
+    fn write(&mut self, _decoded: AudioBufferRef<'_>) -> Result<()> {
+        Err(format_err!("device unplugged"))
+    }
+
+    fn flush(&mut self) {}
+
+    fn spec(&self) -> SignalSpec {
+        SignalSpec::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT)
     }
 }
 
 pub struct Context {
-    pub db_dir: String,
+    pub dir: PathBuf,
+}
+
+impl Context {
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    /// Player service playing the songs of the directory, with its databases in it.
+    fn create_player_service(
+        &self,
+        audio_device: &str,
+        rsp_settings: RsPlayerSettings,
+    ) -> (PlayerService, Arc<QueueService>, Receiver<StateChangeEvent>) {
+        let db_path = |name: &str| self.path(name).to_str().unwrap().to_string();
+        let mut settings = Settings::default();
+        settings.alsa_settings.output_device.name = audio_device.to_string();
+        settings.rs_player_settings = rsp_settings;
+        settings.metadata_settings.music_directory = db_path("");
+        settings.metadata_settings.db_path = db_path("ignored_files.db");
+        settings.playback_queue_settings.db_path = db_path("queue.db");
+        settings.podcast_settings.db_path = db_path("podcast.db");
+        let song_repository = Arc::new(SongRepository::new(&db_path("songs.db")));
+        let statistics_repository = Arc::new(PlayStatisticsRepository::new(&db_path("statistics.db")));
+        let queue = Arc::new(QueueService::new(
+            &settings.playback_queue_settings,
+            song_repository.clone(),
+            statistics_repository.clone(),
+        ));
+        let metadata_service = MetadataService::new(
+            &settings.metadata_settings,
+            song_repository,
+            Arc::new(AlbumRepository::new(&db_path("albums.db"))),
+            statistics_repository,
+        )
+        .unwrap();
+        let (changes_tx, changes_rx) = broadcast::channel(1000);
+        let player = PlayerService::with_state_db(
+            sled::open(db_path("player_state")).unwrap(),
+            &settings,
+            Arc::new(metadata_service),
+            queue.clone(),
+            Arc::new(PodcastService::new(&settings.podcast_settings)),
+            changes_tx,
+        );
+        (player, queue, changes_rx)
+    }
+
+    fn create_wav(&self, name: &str, frames: usize) -> PathBuf {
+        let data = test_samples(frames)
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        let data_len = u32::try_from(data.len()).unwrap();
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.extend_from_slice(&data);
+        let path = self.path(name);
+        std::fs::write(&path, wav).unwrap();
+        path
+    }
 }
 
 impl Default for Context {
     fn default() -> Self {
         _ = env_logger::builder().is_test(true).try_init();
-        let path = env::current_dir().unwrap();
-        info!("Current directory is {}", path.display());
-        let rnd = random_string::generate(6, "utf8");
-        Self {
-            db_dir: format!("/tmp/queue{rnd}"),
-        }
+        let rnd = random_string::generate(6, "abcdefghijklmnopqrstuvwxyz");
+        let dir = std::env::temp_dir().join(format!("rsp_test_{rnd}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
     }
 }
 
 impl Drop for Context {
     fn drop(&mut self) {
-        let path = &self.db_dir;
-        if Path::new(path).exists() {
-            _ = std::fs::remove_dir_all(path);
+        if self.dir.exists() {
+            _ = std::fs::remove_dir_all(&self.dir);
         }
     }
 }
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use api_models::common::{Volume, VolumeCrtlType};
+use api_models::settings::VolumeControlSettings;
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+/// Amplitude of one least significant bit of a 24 bit sample, the resolution of most DACs.
+const DITHER_LSB: f64 = 1.0 / 8_388_608.0;
+
+/// Software volume gain shared between the player service and the playback thread.
+#[derive(Clone)]
+pub struct VolumeControl {
+    /// `None` if the volume is controlled by the DAC or the ALSA mixer.
+    range_db: Option<f64>,
+    gain: Arc<AtomicU64>,
+}
+
+impl VolumeControl {
+    pub fn new(settings: &VolumeControlSettings) -> Self {
+        Self {
+            range_db: (settings.ctrl_device == VolumeCrtlType::Software)
+                .then_some(f64::from(settings.software_volume_range_db)),
+            gain: Arc::new(AtomicU64::new(1f64.to_bits())),
+        }
+    }
+
+    pub fn set_volume(&self, volume: &Volume) {
+        if let Some(range_db) = self.range_db {
+            let gain = volume_to_db(volume, range_db).map_or(0.0, |db| 10f64.powf(db / 20.0));
+            self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    fn gain(&self) -> f64 {
+        f64::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+}
+
+/// Maps the volume steps evenly onto `range_db` below 0 dB, `None` means muted.
+#[allow(clippy::cast_precision_loss)]
+pub fn volume_to_db(volume: &Volume, range_db: f64) -> Option<f64> {
+    let steps = volume.max - volume.min;
+    if steps <= 0 || volume.current >= volume.max {
+        return Some(0.0);
+    }
+    if volume.current <= volume.min {
+        return None;
+    }
+    Some(range_db * (volume.current - volume.max) as f64 / steps as f64)
+}
+
+/// Scales samples by the software volume in 64 bit precision and adds TPDF dither.
+pub struct VolumeScaler {
+    control: VolumeControl,
+    gain: f64,
+    target: f64,
+    seed: u32,
+}
+
+impl VolumeScaler {
+    pub fn new(control: VolumeControl) -> Self {
+        let gain = control.gain();
+        Self {
+            control,
+            gain,
+            target: gain,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    /// Returns false when the volume is at 0 dB, so samples can pass unchanged.
+    pub fn is_active(&mut self) -> bool {
+        self.target = self.control.gain();
+        (self.target - 1.0).abs() > f64::EPSILON || (self.gain - 1.0).abs() > f64::EPSILON
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let frames = buffer.frames();
+        if frames == 0 {
+            return;
+        }
+        // Volume changes are ramped over the buffer to avoid clicks.
+        let gain_step = (self.target - self.gain) / frames as f64;
+        for ch in 0..buffer.spec().channels.count() {
+            let mut gain = self.gain;
+            for sample in buffer.chan_mut(ch) {
+                gain += gain_step;
+                let value = f64::from(*sample).mul_add(gain, self.dither());
+                #[allow(clippy::cast_possible_truncation)]
+                let value = value as f32;
+                *sample = value;
+            }
+        }
+        self.gain = self.target;
+    }
+
+    /// Triangular noise of +-1 LSB made from the sum of two uniform random values.
+    fn dither(&mut self) -> f64 {
+        (self.next_random() + self.next_random() - 1.0) * DITHER_LSB
+    }
+
+    fn next_random(&mut self) -> f64 {
+        // xorshift32
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        f64::from(self.seed) / f64::from(u32::MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use api_models::common::Volume;
+
+    use super::volume_to_db;
+
+    fn volume(current: i64) -> Volume {
+        Volume {
+            step: 2,
+            min: 0,
+            max: 100,
+            current,
+        }
+    }
+
+    #[test]
+    fn should_map_volume_steps_to_db_range() {
+        assert_eq!(volume_to_db(&volume(100), 60.0), Some(0.0));
+        assert_eq!(volume_to_db(&volume(50), 60.0), Some(-30.0));
+        assert_eq!(volume_to_db(&volume(1), 60.0), Some(-59.4));
+        assert_eq!(volume_to_db(&volume(0), 60.0), None);
+    }
+}
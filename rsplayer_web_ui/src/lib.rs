@@ -3,7 +3,7 @@ extern crate api_models;
 use std::{rc::Rc, str::FromStr};
 
 use api_models::{
-    common::{MetadataCommand, PlayerCommand, QueueCommand, SystemCommand, UserCommand, Volume}, player::Song, state::{AudioOut, PlayerInfo, PlayerState, SongProgress, StateChangeEvent, StreamerState}
+    common::{MetadataCommand, PlayerCommand, QueueCommand, RepeatMode, SeekTarget, StopAfter, SystemCommand, UserCommand, Volume}, player::Song, state::{AudioLevels, AudioOut, PlayerInfo, PlayerState, SleepTimerState, SongLoadProgress, SongProgress, StateChangeEvent, StreamConnectionState, StreamerState}
 };
 use gloo_console::{error, log};
 use gloo_net::http::Request;
@@ -12,7 +12,7 @@ use serde::Deserialize;
 use strum_macros::IntoStaticStr;
 use wasm_sockets::{self, ConnectionStatus, EventClient, Message, WebSocketError};
 use web_sys::CloseEvent;
-use PlayerCommand::{Next, Pause, Play, Prev, RandomToggle};
+use PlayerCommand::{Next, Pause, Play, Prev, RandomToggle, SetRepeatMode};
 use UserCommand::{Player, Queue};
 
 mod page;
@@ -26,6 +26,7 @@ const MUSIC_LIBRARY: &str = "library";
 const MUSIC_LIBRARY_FILES: &str = "files";
 const MUSIC_LIBRARY_ARTISTS: &str = "artists";
 const MUSIC_LIBRARY_RADIO: &str = "radio";
+const MUSIC_LIBRARY_PODCASTS: &str = "podcasts";
 const MUSIC_LIBRARY_PL_STATIC: &str = "playlists";
 
 // ------ ------
@@ -38,6 +39,15 @@ pub struct PlayerModel {
     current_song: Option<Song>,
     progress: SongProgress,
     random: bool,
+    repeat_mode: RepeatMode,
+    stop_after: StopAfter,
+    sleep_timer: Option<SleepTimerState>,
+    /// Id of the ringing alarm.
+    alarm_ringing: Option<String>,
+    /// Song being read into memory before it is played.
+    song_loading: Option<SongLoadProgress>,
+    /// Levels of the played audio, received while the player page is shown.
+    audio_levels: Option<AudioLevels>,
     player_state: PlayerState,
     stop_progress_updates: bool,
 }
@@ -71,6 +81,7 @@ pub enum Msg {
     MusicLibraryFiles(page::music_library_files::Msg),
     MusicLibraryArtists(page::music_library_artists::Msg),
     MusicLibraryRadio(page::music_library_radio::Msg),
+    MusicLibraryPodcasts(page::music_library_podcasts::Msg),
     Ignore,
 
     SendUserCommand(UserCommand),
@@ -116,6 +127,7 @@ enum Page {
     MusicLibraryFiles(page::music_library_files::Model),
     MusicLibraryArtists(page::music_library_artists::Model),
     MusicLibraryRadio(page::music_library_radio::Model),
+    MusicLibraryPodcasts(page::music_library_podcasts::Model),
     NotFound,
 }
 
@@ -138,6 +150,10 @@ impl Page {
                     url,
                     &mut orders.proxy(Msg::MusicLibraryRadio),
                 )),
+                MUSIC_LIBRARY_PODCASTS => Self::MusicLibraryPodcasts(page::music_library_podcasts::init(
+                    url,
+                    &mut orders.proxy(Msg::MusicLibraryPodcasts),
+                )),
                 MUSIC_LIBRARY_ARTISTS => Self::MusicLibraryArtists(page::music_library_artists::init(
                     url,
                     &mut orders.proxy(Msg::MusicLibraryArtists),
@@ -163,6 +179,7 @@ impl Page {
                 | Page::MusicLibraryStaticPlaylist(_)
                 | Page::MusicLibraryArtists(_)
                 | Page::MusicLibraryRadio(_)
+                | Page::MusicLibraryPodcasts(_)
         )
     }
 }
@@ -201,6 +218,12 @@ fn init(url: Url, orders: &mut impl Orders<Msg>) -> Model {
             current_song: None,
             progress: SongProgress::default(),
             random: false,
+            repeat_mode: RepeatMode::Off,
+            stop_after: StopAfter::Off,
+            sleep_timer: None,
+            alarm_ringing: None,
+            song_loading: None,
+            audio_levels: None,
             player_state: PlayerState::STOPPED,
             stop_progress_updates: false,
         },
@@ -243,7 +266,11 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             log!("WebSocket connection is open now");
             orders.send_msg(Msg::SendUserCommand(Queue(QueueCommand::QueryCurrentSong)));
             orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::QueryCurrentPlayerInfo)));
+            orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::QuerySleepTimer)));
             orders.send_msg(Msg::SendSystemCommand(SystemCommand::QueryCurrentStreamerState));
+            if matches!(model.page, Page::Player) {
+                orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::SubscribeAudioLevels(true))));
+            }
             if let Page::Queue(model) = &mut model.page {
                 page::queue::update(page::queue::Msg::WebSocketOpen, model, &mut orders.proxy(Msg::Queue));
             }
@@ -268,6 +295,13 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                     &mut orders.proxy(Msg::MusicLibraryRadio),
                 );
             }
+            if let Page::MusicLibraryPodcasts(model) = &mut model.page {
+                page::music_library_podcasts::update(
+                    page::music_library_podcasts::Msg::WebSocketOpen,
+                    model,
+                    &mut orders.proxy(Msg::MusicLibraryPodcasts),
+                );
+            }
             if let Page::MusicLibraryStaticPlaylist(model) = &mut model.page {
                 page::music_library_static_playlist::update(
                     page::music_library_static_playlist::Msg::WebSocketOpen,
@@ -306,7 +340,16 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             model.web_socket = create_websocket(orders).unwrap();
         }
 
-        Msg::UrlChanged(subs::UrlChanged(url)) => model.page = Page::new(url, orders),
+        Msg::UrlChanged(subs::UrlChanged(url)) => {
+            let was_player = matches!(model.page, Page::Player);
+            model.page = Page::new(url, orders);
+            // The levels are sent many times per second, only while the player page shows them.
+            let is_player = matches!(model.page, Page::Player);
+            if was_player != is_player {
+                model.player_model.audio_levels = None;
+                orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::SubscribeAudioLevels(is_player))));
+            }
+        }
 
         Msg::AlbumImageUpdated(image) => {
             model.player_model.current_song.as_mut().unwrap().image_url = Some(image.text);
@@ -315,7 +358,9 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             log!(format!("Seeking to {}", pos));
             model.player_model.stop_progress_updates = true;
             model.player_model.progress.current_time = std::time::Duration::from_secs(pos.into());
-            orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::Seek(pos))));
+            orders.send_msg(Msg::SendUserCommand(Player(PlayerCommand::Seek(SeekTarget::Millis(
+                u64::from(pos) * 1000,
+            )))));
             orders.perform_cmd(cmds::timeout(2000, || Msg::ResumeProgressUpdates));
         }
         Msg::ResumeProgressUpdates => {
@@ -379,9 +424,33 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 StateChangeEvent::RandomToggleEvent(random) => {
                     model.player_model.random = *random;
                 }
+                StateChangeEvent::RepeatModeEvent(repeat_mode, stop_after) => {
+                    model.player_model.repeat_mode = *repeat_mode;
+                    model.player_model.stop_after = *stop_after;
+                }
+                StateChangeEvent::SleepTimerEvent(timer) => {
+                    model.player_model.sleep_timer = *timer;
+                }
+                StateChangeEvent::AlarmRingingEvent(alarm_id) => {
+                    model.player_model.alarm_ringing.clone_from(alarm_id);
+                }
+                StateChangeEvent::SongLoadingEvent(progress) => {
+                    model.player_model.song_loading =
+                        Some(progress.clone()).filter(|progress| progress.loaded_bytes < progress.total_bytes);
+                }
+                StateChangeEvent::AudioLevelsEvent(levels) => {
+                    model.player_model.audio_levels = Some(levels.clone());
+                }
                 StateChangeEvent::PlaybackStateEvent(ps) => {
+                    if *ps != PlayerState::PLAYING {
+                        model.player_model.audio_levels = None;
+                    }
                     model.player_model.player_state = ps.clone();
                 }
+                StateChangeEvent::SeekCompletedEvent(progress) => {
+                    model.player_model.progress = progress.clone();
+                    model.player_model.stop_progress_updates = false;
+                }
                 StateChangeEvent::MetadataSongScanStarted => {
                     model.metadata_scan_info = Some("Music directory scanning started.".to_string());
                     orders.after_next_render(|_| scrollToId("scaninfo"));
@@ -394,7 +463,9 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                     orders.perform_cmd(cmds::timeout(5000, || Msg::HideMetadataScanInfo));
                     orders.after_next_render(|_| scrollToId("scaninfo"));
                 }
-                StateChangeEvent::NotificationSuccess(_) | StateChangeEvent::NotificationError(_) => {
+                StateChangeEvent::NotificationSuccess(_)
+                | StateChangeEvent::NotificationError(_)
+                | StateChangeEvent::StreamConnectionEvent(_) => {
                     model.notification = Some(chg_ev.clone());
                     orders.perform_cmd(cmds::timeout(4000, || Msg::HideNotification));
                     orders.skip();
@@ -432,6 +503,12 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                     model,
                     &mut orders.proxy(Msg::MusicLibraryRadio),
                 );
+            } else if let Page::MusicLibraryPodcasts(model) = &mut model.page {
+                page::music_library_podcasts::update(
+                    page::music_library_podcasts::Msg::StatusChangeEventReceived(chg_ev),
+                    model,
+                    &mut orders.proxy(Msg::MusicLibraryPodcasts),
+                );
             }
         }
 
@@ -493,6 +570,18 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 page::music_library_radio::update(msg, music_lib_model, &mut orders.proxy(Msg::MusicLibraryRadio));
             }
         }
+        Msg::MusicLibraryPodcasts(msg) => {
+            if let Page::MusicLibraryPodcasts(music_lib_model) = &mut model.page {
+                if let page::music_library_podcasts::Msg::SendUserCommand(cmd) = &msg {
+                    _ = model.web_socket.send_string(&serde_json::to_string(cmd).unwrap());
+                }
+                page::music_library_podcasts::update(
+                    msg,
+                    music_lib_model,
+                    &mut orders.proxy(Msg::MusicLibraryPodcasts),
+                );
+            }
+        }
 
         Msg::WebSocketMessageReceived(message) => {
             let msg = serde_json::from_str::<StateChangeEvent>(&message)
@@ -590,6 +679,18 @@ fn view_notification(model: &Model) -> Node<Msg> {
             StateChangeEvent::NotificationError(error) => {
                 div![C!["notification", "is-error", "is-light"], error]
             }
+            StateChangeEvent::StreamConnectionEvent(StreamConnectionState::Reconnecting(attempt, max_attempts)) => {
+                div![
+                    C!["notification", "is-warning", "is-light"],
+                    format!("Radio stream interrupted, reconnecting ({attempt}/{max_attempts})")
+                ]
+            }
+            StateChangeEvent::StreamConnectionEvent(StreamConnectionState::Reconnected) => {
+                div![C!["notification", "is-success", "is-light"], "Radio stream reconnected"]
+            }
+            StateChangeEvent::StreamConnectionEvent(StreamConnectionState::Lost) => {
+                div![C!["notification", "is-error", "is-light"], "Radio stream lost"]
+            }
             _ => empty!(),
         })
     ]
@@ -628,6 +729,7 @@ fn view_player_footer(page: &Page, player_model: &PlayerModel) -> Node<Msg> {
     } else {
         "fa-list-ol"
     };
+    let next_repeat_mode = player_model.repeat_mode.next();
 
     div![
         C!["page-foot", "container"],
@@ -718,6 +820,17 @@ fn view_player_footer(page: &Page, player_model: &PlayerModel) -> Node<Msg> {
                                 C!["fas", "is-clickable", "small-button-footer", shuffle_class],
                                 ev(Ev::Click, |_| Msg::SendUserCommand(Player(RandomToggle))),
                             ],
+                            i![
+                                C![
+                                    "fas",
+                                    "is-clickable",
+                                    "small-button-footer",
+                                    "fa-repeat",
+                                    IF!(player_model.repeat_mode != RepeatMode::Off => "has-text-success")
+                                ],
+                                IF!(player_model.repeat_mode == RepeatMode::Single => sup!["1"]),
+                                ev(Ev::Click, move |_| Msg::SendUserCommand(Player(SetRepeatMode(next_repeat_mode)))),
+                            ],
                             i![
                                 C!["fas", "is-clickable", "fa-backward", "small-button-footer"],
                                 ev(Ev::Click, |_| Msg::SendUserCommand(Player(Prev))),
@@ -805,6 +918,8 @@ fn view_content(main_model: &Model, base_url: &Url) -> Node<Msg> {
             Page::MusicLibraryArtists(model) =>
                 page::music_library_artists::view(model).map_msg(Msg::MusicLibraryArtists),
             Page::MusicLibraryRadio(model) => page::music_library_radio::view(model).map_msg(Msg::MusicLibraryRadio),
+            Page::MusicLibraryPodcasts(model) =>
+                page::music_library_podcasts::view(model).map_msg(Msg::MusicLibraryPodcasts),
         }
     ]
 }
@@ -883,6 +998,13 @@ fn view_music_lib_tabs(page: &Page) -> Node<Msg> {
                     span!("Radio")
                 ]
             ],
+            li![
+                IF!(page_name == "MusicLibraryPodcasts" => C!["is-active"]),
+                a![
+                    attrs! {At::Href => Urls::library_abs().add_hash_path_part(MUSIC_LIBRARY_PODCASTS)},
+                    span!("Podcasts")
+                ]
+            ],
             li![
                 IF!(page_name == "MusicLibraryArtists" => C!["is-active"]),
                 a![
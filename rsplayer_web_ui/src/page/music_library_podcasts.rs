@@ -0,0 +1,300 @@
+use api_models::common::{dur_to_string, PodcastCommand, UserCommand};
+use api_models::podcast::{Episode, Podcast};
+use api_models::state::StateChangeEvent;
+use seed::prelude::web_sys::KeyboardEvent;
+use seed::{a, attrs, div, i, img, input, p, prelude::*, section, span, style, C, IF};
+
+use crate::view_spinner_modal;
+
+#[derive(Debug)]
+pub struct Model {
+    wait_response: bool,
+    feed_url_input: String,
+    podcasts: Vec<Podcast>,
+    /// Feed url of the podcast the episodes are shown for.
+    selected_podcast: Option<String>,
+    episodes: Vec<Episode>,
+}
+
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Msg {
+    SendUserCommand(UserCommand),
+    StatusChangeEventReceived(StateChangeEvent),
+    WebSocketOpen,
+    FeedUrlInputChanged(String),
+    Subscribe,
+    Unsubscribe(String),
+    RefreshPodcasts,
+    SelectPodcast(String),
+    AddEpisodeToQueue(String, String),
+    PlayEpisode(String, String),
+    SetEpisodePlayed(String, String, bool),
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn init(_url: Url, orders: &mut impl Orders<Msg>) -> Model {
+    orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+        PodcastCommand::QueryPodcasts,
+    )));
+    Model {
+        wait_response: true,
+        feed_url_input: String::new(),
+        podcasts: vec![],
+        selected_podcast: None,
+        episodes: vec![],
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
+    match msg {
+        Msg::WebSocketOpen => {
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+                PodcastCommand::QueryPodcasts,
+            )));
+        }
+        Msg::StatusChangeEventReceived(StateChangeEvent::PodcastsEvent(podcasts)) => {
+            model.wait_response = false;
+            if model
+                .selected_podcast
+                .as_ref()
+                .is_some_and(|selected| !podcasts.iter().any(|podcast| &podcast.feed_url == selected))
+            {
+                model.selected_podcast = None;
+                model.episodes.clear();
+            }
+            model.podcasts = podcasts;
+        }
+        Msg::StatusChangeEventReceived(StateChangeEvent::PodcastEpisodesEvent(feed_url, episodes)) => {
+            model.wait_response = false;
+            if model.selected_podcast.as_ref() == Some(&feed_url) {
+                model.episodes = episodes;
+            }
+        }
+        Msg::StatusChangeEventReceived(StateChangeEvent::NotificationError(_)) => {
+            model.wait_response = false;
+        }
+        Msg::FeedUrlInputChanged(url) => {
+            orders.skip();
+            model.feed_url_input = url;
+        }
+        Msg::Subscribe => {
+            let feed_url = model.feed_url_input.trim().to_string();
+            if !feed_url.is_empty() {
+                model.wait_response = true;
+                model.feed_url_input = String::new();
+                orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(PodcastCommand::Subscribe(
+                    feed_url,
+                ))));
+            }
+        }
+        Msg::Unsubscribe(feed_url) => {
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(PodcastCommand::Unsubscribe(
+                feed_url,
+            ))));
+        }
+        Msg::RefreshPodcasts => {
+            model.wait_response = true;
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+                PodcastCommand::RefreshPodcasts,
+            )));
+        }
+        Msg::SelectPodcast(feed_url) => {
+            model.episodes.clear();
+            if model.selected_podcast.as_ref() == Some(&feed_url) {
+                model.selected_podcast = None;
+            } else {
+                model.selected_podcast = Some(feed_url.clone());
+                model.wait_response = true;
+                orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+                    PodcastCommand::QueryEpisodes(feed_url),
+                )));
+            }
+        }
+        Msg::AddEpisodeToQueue(feed_url, audio_url) => {
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+                PodcastCommand::AddEpisodeToQueue(feed_url, audio_url),
+            )));
+        }
+        Msg::PlayEpisode(feed_url, audio_url) => {
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(PodcastCommand::PlayEpisode(
+                feed_url, audio_url,
+            ))));
+        }
+        Msg::SetEpisodePlayed(feed_url, audio_url, played) => {
+            orders.send_msg(Msg::SendUserCommand(UserCommand::Podcast(
+                PodcastCommand::SetEpisodePlayed(feed_url, audio_url, played),
+            )));
+        }
+        _ => {
+            orders.skip();
+        }
+    }
+}
+
+pub fn view(model: &Model) -> Node<Msg> {
+    section![
+        view_spinner_modal(model.wait_response),
+        view_subscribe_input(model),
+        div![
+            C!["list has-overflow-ellipsis has-visible-pointer-controls has-hoverable-list-items"],
+            model.podcasts.iter().map(|podcast| view_podcast(model, podcast))
+        ]
+    ]
+}
+
+fn view_subscribe_input(model: &Model) -> Node<Msg> {
+    div![
+        C!["transparent is-flex is-justify-content-center has-background-dark-transparent mt-2"],
+        div![
+            C!["control"],
+            input![
+                C!["input", "input-size"],
+                attrs! {
+                    At::Value => model.feed_url_input,
+                    At::Name => "feed_url",
+                    At::Type => "url",
+                    At::Placeholder => "Podcast feed url",
+                },
+                input_ev(Ev::Input, Msg::FeedUrlInputChanged),
+                ev(Ev::KeyDown, |keyboard_event| {
+                    if keyboard_event.value_of().to_string() == "[object KeyboardEvent]" {
+                        let kev: KeyboardEvent = keyboard_event.unchecked_into();
+                        IF!(kev.key_code() == 13 => Msg::Subscribe)
+                    } else {
+                        None
+                    }
+                }),
+            ],
+        ],
+        div![
+            C!["control"],
+            a![
+                C!["ml-2"],
+                attrs!(At::Title =>"Subscribe"),
+                i![C!["material-icons", "is-large-icon", "white-icon"], "add_circle"],
+                ev(Ev::Click, |_| Msg::Subscribe)
+            ],
+            a![
+                C!["ml-2"],
+                attrs!(At::Title =>"Refresh podcasts"),
+                i![C!["material-icons", "is-large-icon", "white-icon"], "refresh"],
+                ev(Ev::Click, |_| Msg::RefreshPodcasts)
+            ],
+        ],
+    ]
+}
+
+fn view_podcast(model: &Model, podcast: &Podcast) -> Node<Msg> {
+    let is_selected = model.selected_podcast.as_ref() == Some(&podcast.feed_url);
+    let select_url = podcast.feed_url.clone();
+    let unsubscribe_url = podcast.feed_url.clone();
+    div![
+        div![
+            C!["list-item"],
+            podcast.image_url.as_ref().map(|image_url| div![
+                C!["list-item-image"],
+                img![
+                    C!["is-rounded"],
+                    style! {St::Height => "40px", St::Width => "40px"},
+                    attrs! {At::Src => image_url}
+                ]
+            ]),
+            div![
+                C!["list-item-content", "has-background-dark-transparent"],
+                ev(Ev::Click, move |_| Msg::SelectPodcast(select_url)),
+                div![
+                    C!["list-item-title", "has-text-light"],
+                    i![
+                        C!["material-icons"],
+                        if is_selected { "expand_less" } else { "expand_more" }
+                    ],
+                    &podcast.title,
+                    podcast.refresh_error.as_ref().map(|error| span![
+                        C!["icon", "has-text-warning"],
+                        attrs! {At::Title => error},
+                        i![C!["material-icons"], "error"]
+                    ]),
+                ],
+                div![C!["description", "has-text-light"], podcast.author.clone()]
+            ],
+            div![
+                C!["list-item-controls"],
+                div![
+                    C!["buttons"],
+                    a![
+                        attrs!(At::Title =>"Unsubscribe"),
+                        C!["icon"],
+                        i![C!("material-icons"), "delete"],
+                        ev(Ev::Click, move |_| Msg::Unsubscribe(unsubscribe_url))
+                    ],
+                ]
+            ],
+        ],
+        IF!(is_selected => model.episodes.iter().map(view_episode).collect::<Vec<_>>()),
+    ]
+}
+
+fn view_episode(episode: &Episode) -> Node<Msg> {
+    let queue_urls = (episode.feed_url.clone(), episode.audio_url.clone());
+    let play_urls = queue_urls.clone();
+    let played_urls = queue_urls.clone();
+    let played = episode.played;
+    let mut details = vec![];
+    if let Some(published) = episode.published {
+        details.push(published.date_naive().to_string());
+    }
+    if let Some(duration) = episode.duration.as_ref() {
+        details.push(dur_to_string(duration));
+    }
+    if !episode.position.is_zero() {
+        details.push(format!("resumes at {}", dur_to_string(&episode.position)));
+    }
+    div![
+        C!["list-item", "ml-5"],
+        div![
+            C!["list-item-content", "has-background-dark-transparent"],
+            div![
+                C![
+                    "list-item-title",
+                    IF!(played => "has-text-grey-light"),
+                    IF!(!played => "has-text-light")
+                ],
+                &episode.title
+            ],
+            p![C!["description", "has-text-light"], details.join(" / ")]
+        ],
+        div![
+            C!["list-item-controls"],
+            div![
+                C!["buttons"],
+                a![
+                    attrs!(At::Title => if played { "Mark as unplayed" } else { "Mark as played" }),
+                    C!["icon"],
+                    i![
+                        C!("material-icons"),
+                        if played {
+                            "check_circle"
+                        } else {
+                            "radio_button_unchecked"
+                        }
+                    ],
+                    ev(Ev::Click, move |_| Msg::SetEpisodePlayed(played_urls.0, played_urls.1, !played))
+                ],
+                a![
+                    attrs!(At::Title =>"Add episode to queue"),
+                    C!["icon"],
+                    i![C!("material-icons"), "playlist_add"],
+                    ev(Ev::Click, move |_| Msg::AddEpisodeToQueue(queue_urls.0, queue_urls.1))
+                ],
+                a![
+                    attrs!(At::Title =>"Play episode"),
+                    C!["icon"],
+                    i![C!("material-icons"), "play_circle_filled"],
+                    ev(Ev::Click, move |_| Msg::PlayEpisode(play_urls.0, play_urls.1))
+                ],
+            ]
+        ],
+    ]
+}
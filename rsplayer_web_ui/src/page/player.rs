@@ -1,9 +1,11 @@
 use api_models::common::UserCommand::Player;
-use api_models::common::{MetadataCommand, PlayerCommand, SystemCommand, Volume};
+use api_models::common::{
+    AlarmCommand, MetadataCommand, PlayerCommand, RepeatMode, SleepTimer, StopAfter, SystemCommand, UserCommand, Volume,
+};
 use api_models::player::Song;
-use api_models::state::{AudioOut, PlayerInfo, PlayerState, SongProgress};
+use api_models::state::{AudioLevels, AudioOut, PlayerInfo, PlayerState, SongLoadProgress, SongProgress};
 
-use seed::{a, attrs, button, div, empty, i, input, nav, nodes, p, prelude::*, span, style, C, IF};
+use seed::{a, attrs, button, div, empty, i, input, nav, nodes, p, prelude::*, progress, span, style, C, IF};
 
 use std::str::FromStr;
 
@@ -20,6 +22,24 @@ pub fn view(model: &PlayerModel) -> Node<Msg> {
     ]]
 }
 
+fn view_alarm_controls() -> Node<Msg> {
+    div![
+        C!["has-text-centered"],
+        button![
+            C!["button", "is-small", "is-warning", "mr-2"],
+            span![C!["icon"], i![C!("material-icons"), "snooze"]],
+            span!["Snooze"],
+            ev(Ev::Click, |_| Msg::SendUserCommand(UserCommand::Alarm(AlarmCommand::Snooze))),
+        ],
+        button![
+            C!["button", "is-small", "is-danger"],
+            span![C!["icon"], i![C!("material-icons"), "alarm_off"]],
+            span!["Dismiss"],
+            ev(Ev::Click, |_| Msg::SendUserCommand(UserCommand::Alarm(AlarmCommand::Dismiss))),
+        ],
+    ]
+}
+
 #[allow(clippy::too_many_lines)]
 fn view_track_info(song: Option<&Song>, player_info: Option<&PlayerInfo>) -> Node<Msg> {
     song.map_or_else(
@@ -114,6 +134,18 @@ fn view_track_info(song: Option<&Song>, player_info: Option<&PlayerInfo>) -> Nod
                                     C!["level-item", "has-text-centered", "mb-2"],
                                     div![p![C!["has-text-light has-background-dark-transparent", "has-min-width"], "Codec: ", c]],
                                 ]
+                            }),
+                            pi.replay_gain_db.map(|gain| {
+                                div![
+                                    C!["level-item", "has-text-centered", "mb-2"],
+                                    div![p![C!["has-text-light has-background-dark-transparent", "has-min-width"], format!("ReplayGain: {gain:+.2} dB")]],
+                                ]
+                            }),
+                            pi.bit_perfect.filter(|bit_perfect| *bit_perfect).map(|_| {
+                                div![
+                                    C!["level-item", "has-text-centered", "mb-2"],
+                                    div![p![C!["has-text-light has-background-dark-transparent", "has-min-width"], "Bit-perfect"]],
+                                ]
                             })
                         ]
                     )
@@ -123,6 +155,47 @@ fn view_track_info(song: Option<&Song>, player_info: Option<&PlayerInfo>) -> Nod
     )
 }
 
+fn view_song_loading(loading: &SongLoadProgress) -> Node<Msg> {
+    div![
+        C!["has-text-centered", "has-text-light", "is-size-7"],
+        style! { St::PaddingLeft => "1.2rem", St::PaddingRight => "1.2rem" },
+        "Loading into memory",
+        progress![
+            C!["progress", "is-small", "is-info"],
+            attrs! {At::Value => loading.loaded_bytes, At::Max => loading.total_bytes},
+        ],
+    ]
+}
+
+/// Peak meters of the channels and the spectrum, from -60 dB to full scale.
+fn view_audio_levels(levels: &AudioLevels) -> Node<Msg> {
+    const RANGE_DB: f32 = 60.0;
+    let fill = |db: f32| ((db + RANGE_DB) / RANGE_DB * 100.0).clamp(0.0, 100.0);
+    div![
+        style! { St::PaddingLeft => "1.2rem", St::PaddingRight => "1.2rem" },
+        levels.peak_db.iter().map(|peak_db| {
+            progress![
+                C!["progress", "is-small", "is-success", "mb-1"],
+                attrs! {At::Value => format!("{:.0}", fill(*peak_db)), At::Max => 100},
+            ]
+        }),
+        div![
+            C!["is-flex", "is-align-items-flex-end"],
+            style! { St::Height => "40px" },
+            levels.spectrum_db.iter().map(|band_db| {
+                div![
+                    C!["has-background-success"],
+                    style! {
+                        St::FlexGrow => 1,
+                        St::MarginRight => "2px",
+                        St::Height => format!("{:.0}%", fill(*band_db)),
+                    },
+                ]
+            }),
+        ],
+    ]
+}
+
 fn view_track_progress_bar(progress: &SongProgress) -> Node<Msg> {
     div![
         style! {
@@ -182,12 +255,32 @@ fn view_controls_up(model: &PlayerModel) -> Node<Msg> {
     } else {
         "format_list_numbered"
     };
+    let repeat = match model.repeat_mode {
+        RepeatMode::Off => "repeat",
+        RepeatMode::Queue => "repeat_on",
+        RepeatMode::Single => "repeat_one_on",
+    };
+    let next_repeat_mode = model.repeat_mode.next();
+    // The sleep timer button cycles through 15, 30 and 60 minutes, then cancels the timer.
+    let sleep_timer_cmd = match model.sleep_timer.map(|timer| timer.timer) {
+        None => PlayerCommand::SetSleepTimer(SleepTimer::Minutes(15)),
+        Some(SleepTimer::Minutes(15)) => PlayerCommand::SetSleepTimer(SleepTimer::Minutes(30)),
+        Some(SleepTimer::Minutes(30)) => PlayerCommand::SetSleepTimer(SleepTimer::Minutes(60)),
+        Some(_) => PlayerCommand::CancelSleepTimer,
+    };
+    let sleep_timer_remaining = model
+        .sleep_timer
+        .and_then(|timer| timer.remaining)
+        .map(|remaining| format!("{}:{:02}", remaining.as_secs() / 60, remaining.as_secs() % 60));
 
     div![
         C!["centered", "box", "has-background-dark-transparent"],
         style! { St::Top => "28%", St::Padding => "10px", St::Width => "80%" },
+        model.song_loading.as_ref().map(view_song_loading),
         view_track_progress_bar(&model.progress),
+        model.audio_levels.as_ref().map(view_audio_levels),
         view_volume_slider(&model.streamer_status.volume_state),
+        IF!(model.alarm_ringing.is_some() => view_alarm_controls()),
         div![
             C!["has-text-centered"],
             button![
@@ -205,6 +298,39 @@ fn view_controls_up(model: &PlayerModel) -> Node<Msg> {
                 span![C!["icon"], i![C!("material-icons"), shuffle]],
                 ev(Ev::Click, |_| Msg::SendUserCommand(Player(PlayerCommand::RandomToggle))),
             ],
+            button![
+                C!["small-button"],
+                span![C!["icon"], i![C!("material-icons"), repeat]],
+                ev(Ev::Click, move |_| Msg::SendUserCommand(Player(PlayerCommand::SetRepeatMode(next_repeat_mode)))),
+            ],
+            button![
+                C!["small-button"],
+                attrs! {At::Title => "Stop after current song"},
+                span![
+                    C!["icon", IF!(model.stop_after == StopAfter::CurrentSong => "has-text-success")],
+                    i![C!("material-icons"), "stop_circle"]
+                ],
+                ev(Ev::Click, |_| Msg::SendUserCommand(Player(PlayerCommand::StopAfterCurrentSong))),
+            ],
+            button![
+                C!["small-button"],
+                attrs! {At::Title => "Stop after current album"},
+                span![
+                    C!["icon", IF!(model.stop_after == StopAfter::CurrentAlbum => "has-text-success")],
+                    i![C!("material-icons"), "album"]
+                ],
+                ev(Ev::Click, |_| Msg::SendUserCommand(Player(PlayerCommand::StopAfterCurrentAlbum))),
+            ],
+            button![
+                C!["small-button"],
+                attrs! {At::Title => "Sleep timer"},
+                span![
+                    C!["icon", IF!(model.sleep_timer.is_some() => "has-text-success")],
+                    i![C!("material-icons"), "bedtime"]
+                ],
+                sleep_timer_remaining.map(|remaining| span![C!["is-size-7"], remaining]),
+                ev(Ev::Click, move |_| Msg::SendUserCommand(Player(sleep_timer_cmd.clone()))),
+            ],
             button![
                 C!["small-button"],
                 span![C!["icon"], i![C!("material-icons"), audio_out]],
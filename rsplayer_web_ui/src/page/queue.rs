@@ -454,6 +454,11 @@ fn view_queue_item(song: &Song, model: &Model) -> Node<Msg> {
                     .time
                     .as_ref()
                     .map(|t| span![format!(" [{}]", api_models::common::dur_to_string(t))]),
+                song.play_error.as_ref().map(|error| span![
+                    C!["icon", "has-text-danger"],
+                    attrs!(At::Title => error),
+                    i![C!("material-icons"), "error"]
+                ]),
             ],
             ev(Ev::Click, move |_| Msg::PlaylistItemSelected(id)),
         ],
@@ -2,17 +2,19 @@ use std::str::FromStr;
 
 use api_models::{
     common::{
-        CardMixer, FilterType, GainLevel, MetadataCommand::RescanMetadata, SystemCommand, UserCommand, VolumeCrtlType,
+        CardMixer, DsdOutput, FilterType, GainLevel, HttpStreamMode, MetadataCommand::RescanMetadata, OutputBackend,
+        SystemCommand, UserCommand, VolumeCrtlType,
     },
     settings::{
-        DacSettings, IRInputControlerSettings, MetadataStoreSettings, OLEDSettings, OutputSelectorSettings,
-        RsPlayerSettings, Settings,
+        ChannelSettings, Crossfeed, DacSettings, IRInputControlerSettings, MetadataStoreSettings, OLEDSettings,
+        OutputSelectorSettings, RsPlayerSettings, Settings,
     },
+    state::AudioOut,
     validator::Validate,
 };
 use gloo_console::log;
 use gloo_net::{http::Request, Error};
-use seed::{attrs, button, div, h1, i, input, label, option, prelude::*, section, select, span, style, C, IF};
+use seed::{attrs, button, div, h1, i, input, label, nodes, option, prelude::*, section, select, span, style, C, IF};
 use strum::IntoEnumIterator;
 
 use crate::view_spinner_modal;
@@ -40,6 +42,10 @@ pub enum Msg {
     ToggleRotaryVolume,
     ToggleResumePlayback,
     ToggleRspAlsaBufferSize,
+    ToggleRspResampling,
+    ToggleRspAlsaMmap,
+    ToggleRspIgnoreUnplayableFiles,
+    ToggleRspLoadIntoRam,
     // ---- Input capture ----
     InputMetadataMusicDirectoryChanged(String),
     InputAlsaCardChange(i32),
@@ -48,11 +54,26 @@ pub enum Msg {
     InputLircRemoteMakerChanged(String),
     InputRotaryEventDevicePathChanged(String),
     InputVolumeStepChanged(String),
+    InputSoftwareVolumeRangeChanged(String),
     InputVolumeCtrlDeviceChanged(VolumeCrtlType),
     InputRspInputBufferSizeChange(String),
     InputRspAudioBufferSizeChange(String),
     InputRspAlsaBufferSizeChange(String),
+    InputRspResampleRateChange(String),
+    InputRspDsdOutputChange(DsdOutput),
+    InputRspHttpStreamModeChange(HttpStreamMode),
+    InputRspOutputBackendChange(OutputBackend),
+    InputRspAlsaPeriodSizeChange(String),
     InputRspThreadPriorityChange(String),
+    InputRspSkipUnplayableSongsChange(String),
+    InputRspLoadIntoRamChange(String),
+    ToggleChannelCrossfeed(AudioOut),
+    InputChannelCrossfeedFeedChange(AudioOut, String),
+    InputChannelCrossfeedCutoffChange(AudioOut, String),
+    InputChannelBalanceChange(AudioOut, String),
+    ToggleChannelSwap(AudioOut),
+    ToggleChannelPolarity(AudioOut),
+    ToggleChannelMono(AudioOut),
     InputVolumeAlsaMixerChanged(String),
     InputDacAddressChanged(String),
     ClickRescanMetadataButton(bool),
@@ -137,6 +158,28 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 model.settings.rs_player_settings.alsa_buffer_size = Some(10000);
             }
         }
+        Msg::ToggleRspResampling => {
+            if model.settings.rs_player_settings.resample_rate.is_some() {
+                model.settings.rs_player_settings.resample_rate = None;
+            } else {
+                model.settings.rs_player_settings.resample_rate = Some(192_000);
+            }
+        }
+
+        Msg::ToggleRspAlsaMmap => {
+            model.settings.rs_player_settings.alsa_mmap = !model.settings.rs_player_settings.alsa_mmap;
+        }
+        Msg::ToggleRspLoadIntoRam => {
+            if model.settings.rs_player_settings.load_into_ram_mb.is_some() {
+                model.settings.rs_player_settings.load_into_ram_mb = None;
+            } else {
+                model.settings.rs_player_settings.load_into_ram_mb = Some(512);
+            }
+        }
+        Msg::ToggleRspIgnoreUnplayableFiles => {
+            model.settings.rs_player_settings.ignore_unplayable_files =
+                !model.settings.rs_player_settings.ignore_unplayable_files;
+        }
 
         Msg::InputMetadataMusicDirectoryChanged(value) => {
             model.settings.metadata_settings.music_directory = value;
@@ -170,6 +213,9 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::InputVolumeStepChanged(step) => {
             model.settings.volume_ctrl_settings.volume_step = step.parse::<u8>().unwrap_or_default();
         }
+        Msg::InputSoftwareVolumeRangeChanged(range) => {
+            model.settings.volume_ctrl_settings.software_volume_range_db = range.parse::<u8>().unwrap_or(60);
+        }
         Msg::InputVolumeAlsaMixerChanged(mixer) => {
             let pair: Vec<&str> = mixer.split(',').collect();
             model.settings.volume_ctrl_settings.alsa_mixer = Some(CardMixer {
@@ -197,6 +243,23 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 model.settings.rs_player_settings.alsa_buffer_size = Some(num);
             };
         }
+        Msg::InputRspDsdOutputChange(dsd_output) => {
+            model.settings.rs_player_settings.dsd_output = dsd_output;
+        }
+        Msg::InputRspHttpStreamModeChange(mode) => {
+            model.settings.rs_player_settings.http_stream_mode = mode;
+        }
+        Msg::InputRspOutputBackendChange(backend) => {
+            model.settings.rs_player_settings.output_backend = backend;
+        }
+        Msg::InputRspAlsaPeriodSizeChange(value) => {
+            model.settings.rs_player_settings.alsa_period_size = value.parse::<u32>().ok();
+        }
+        Msg::InputRspResampleRateChange(value) => {
+            if let Ok(num) = value.parse::<u32>() {
+                model.settings.rs_player_settings.resample_rate = Some(num);
+            };
+        }
         Msg::InputRspThreadPriorityChange(value) => {
             if let Ok(num) = value.parse::<u8>() {
                 if num > 0 && num < 100 {
@@ -204,6 +267,56 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 }
             };
         }
+        Msg::ToggleChannelCrossfeed(out) => {
+            let channel_settings = channel_settings_mut(&mut model.settings, out);
+            channel_settings.crossfeed = match channel_settings.crossfeed {
+                Some(_) => None,
+                None => Some(Crossfeed::default()),
+            };
+        }
+        Msg::InputChannelCrossfeedFeedChange(out, value) => {
+            if let (Ok(num), Some(crossfeed)) = (
+                value.parse::<f32>(),
+                channel_settings_mut(&mut model.settings, out).crossfeed.as_mut(),
+            ) {
+                crossfeed.feed_db = num;
+            };
+        }
+        Msg::InputChannelCrossfeedCutoffChange(out, value) => {
+            if let (Ok(num), Some(crossfeed)) = (
+                value.parse::<u16>(),
+                channel_settings_mut(&mut model.settings, out).crossfeed.as_mut(),
+            ) {
+                crossfeed.cutoff_hz = num;
+            };
+        }
+        Msg::InputChannelBalanceChange(out, value) => {
+            if let Ok(num) = value.parse::<i8>() {
+                channel_settings_mut(&mut model.settings, out).balance = num;
+            };
+        }
+        Msg::ToggleChannelSwap(out) => {
+            let channel_settings = channel_settings_mut(&mut model.settings, out);
+            channel_settings.swap_channels = !channel_settings.swap_channels;
+        }
+        Msg::ToggleChannelPolarity(out) => {
+            let channel_settings = channel_settings_mut(&mut model.settings, out);
+            channel_settings.invert_polarity = !channel_settings.invert_polarity;
+        }
+        Msg::ToggleChannelMono(out) => {
+            let channel_settings = channel_settings_mut(&mut model.settings, out);
+            channel_settings.mono = !channel_settings.mono;
+        }
+        Msg::InputRspLoadIntoRamChange(value) => {
+            if let Ok(num) = value.parse::<usize>() {
+                model.settings.rs_player_settings.load_into_ram_mb = Some(num);
+            };
+        }
+        Msg::InputRspSkipUnplayableSongsChange(value) => {
+            if let Ok(num) = value.parse::<u16>() {
+                model.settings.rs_player_settings.skip_unplayable_songs = num;
+            };
+        }
         Msg::InputDacAddressChanged(value) => {
             if let Ok(num) = value.parse::<u16>() {
                 model.settings.dac_settings.i2c_address = num;
@@ -315,6 +428,24 @@ pub fn view(model: &Model) -> Node<Msg> {
             h1![C!["title","has-text-white"], "Volume control"],
             view_volume_control(model)
         ],
+        // channel processing
+        section![
+            C!["section"],
+            h1![C!["title","has-text-white"], "Channels"],
+            div![
+                C!["columns"],
+                div![
+                    C!["column"],
+                    h1![C!["subtitle","has-text-white"], "Speakers"],
+                    view_channel_settings(&settings.output_channel_settings.speakers, AudioOut::SPKR),
+                ],
+                div![
+                    C!["column"],
+                    h1![C!["subtitle","has-text-white"], "Headphones"],
+                    view_channel_settings(&settings.output_channel_settings.headphones, AudioOut::HEAD),
+                ],
+            ],
+        ],
         // dac
         section![
             C!["section"],
@@ -543,6 +674,25 @@ fn view_volume_control(model: &Model) -> Node<Msg> {
                ],
            ]
         ),
+        IF!(volume_settings.ctrl_device == VolumeCrtlType::Software =>
+            div![
+                C!["field"],
+                label!["Software volume range (dB)", C!["label", "has-text-white"]],
+                div![
+                    C!["control"],
+                    input![
+                        C!["input"],
+                        attrs! {
+                            At::Value => volume_settings.software_volume_range_db
+                            At::Type => "number"
+                            At::Min => "10"
+                            At::Max => "120"
+                        },
+                        input_ev(Ev::Input, move |value| { Msg::InputSoftwareVolumeRangeChanged(value) }),
+                    ],
+                ],
+            ]
+        ),
         div![
             C!["field"],
             label!["Volume step", C!["label", "has-text-white"]],
@@ -624,6 +774,96 @@ fn view_oled_display(oled_settings: &OLEDSettings) -> Node<Msg> {
     ]
 }
 
+fn channel_settings_mut(settings: &mut Settings, out: AudioOut) -> &mut ChannelSettings {
+    match out {
+        AudioOut::SPKR => &mut settings.output_channel_settings.speakers,
+        AudioOut::HEAD => &mut settings.output_channel_settings.headphones,
+    }
+}
+
+fn view_channel_settings(channel_settings: &ChannelSettings, out: AudioOut) -> Node<Msg> {
+    let switch = |name: &str, text: &str, checked: bool, msg: fn(AudioOut) -> Msg| {
+        let id = format!("{name}_{out:?}_cb");
+        div![
+            C!["field", "mt-5"],
+            ev(Ev::Click, move |_| msg(out)),
+            input![
+                C!["switch"],
+                attrs! {
+                    At::Name => &id
+                    At::Type => "checkbox"
+                    At::Checked => checked.as_at_value(),
+                },
+            ],
+            label![
+                C!["label", "has-text-white"],
+                text,
+                attrs! {
+                    At::For => &id
+                }
+            ]
+        ]
+    };
+    div![
+        switch(
+            "crossfeed",
+            "Crossfeed",
+            channel_settings.crossfeed.is_some(),
+            Msg::ToggleChannelCrossfeed
+        ),
+        channel_settings.crossfeed.as_ref().map(|crossfeed| {
+            nodes![
+                label!["Crossfeed level (1-15 dB)", C!["label", "has-text-white"]],
+                div![
+                    C!["control", "has-icons-right"],
+                    style! {St::Width => "max-content"},
+                    input![
+                        C!["input"],
+                        attrs! {At::Value => crossfeed.feed_db, At::Type => "number", At::Step => "0.5"},
+                        input_ev(Ev::Input, move |value| Msg::InputChannelCrossfeedFeedChange(out, value)),
+                    ],
+                    view_validation_icon(crossfeed, "feed_db")
+                ],
+                label!["Crossfeed cut frequency (300-2000 Hz)", C!["label", "has-text-white", "mt-3"]],
+                div![
+                    C!["control", "has-icons-right"],
+                    style! {St::Width => "max-content"},
+                    input![
+                        C!["input"],
+                        attrs! {At::Value => crossfeed.cutoff_hz, At::Type => "number"},
+                        input_ev(Ev::Input, move |value| Msg::InputChannelCrossfeedCutoffChange(out, value)),
+                    ],
+                    view_validation_icon(crossfeed, "cutoff_hz")
+                ],
+            ]
+        }),
+        label!["Balance (-100 right quieter, 100 left quieter)", C!["label", "has-text-white", "mt-5"]],
+        div![
+            C!["control", "has-icons-right"],
+            style! {St::Width => "max-content"},
+            input![
+                C!["input"],
+                attrs! {At::Value => channel_settings.balance, At::Type => "number"},
+                input_ev(Ev::Input, move |value| Msg::InputChannelBalanceChange(out, value)),
+            ],
+            view_validation_icon(channel_settings, "balance")
+        ],
+        switch(
+            "swap",
+            "Swap left and right channel",
+            channel_settings.swap_channels,
+            Msg::ToggleChannelSwap
+        ),
+        switch(
+            "polarity",
+            "Invert polarity",
+            channel_settings.invert_polarity,
+            Msg::ToggleChannelPolarity
+        ),
+        switch("mono", "Mono", channel_settings.mono, Msg::ToggleChannelMono),
+    ]
+}
+
 fn view_output_selector(_out_settings: &OutputSelectorSettings) -> Node<Msg> {
     div![]
 }
@@ -813,6 +1053,128 @@ fn view_rsp(rsp_settings: &RsPlayerSettings) -> Node<Msg> {
             ],
             view_validation_icon(rsp_settings, "player_threads_priority")
         ],
+        div![
+            C!["field", "mt-5"],
+            ev(Ev::Click, |_| Msg::ToggleRspLoadIntoRam),
+            input![
+                C!["switch"],
+                attrs! {
+                    At::Name => "loadintoram_cb"
+                    At::Type => "checkbox"
+                    At::Checked => rsp_settings.load_into_ram_mb.is_some().as_at_value(),
+                },
+            ],
+            label![
+                C!["label", "has-text-white"],
+                "Read songs into memory before they are played",
+                attrs! {
+                    At::For => "loadintoram_cb"
+                }
+            ]
+        ],
+        IF!(rsp_settings.load_into_ram_mb.is_some() =>
+            div![
+                C!["field"],
+                label!["Memory for the current and the next song (MB)", C!["label", "has-text-white"]],
+                div![
+                    C!["control", "has-icons-right"],
+                    style! {St::Width => "max-content"},
+                    input![
+                        C!["input"],
+                        attrs! {
+                            At::Value => rsp_settings.load_into_ram_mb.unwrap_or(512),
+                            At::Type => "number"
+                        },
+                        input_ev(Ev::Input, move |value| { Msg::InputRspLoadIntoRamChange(value) }),
+                    ],
+                    view_validation_icon(rsp_settings, "load_into_ram_mb")
+                ],
+            ]
+        ),
+        label!["Unplayable songs skipped in a row (0 stops at the first)", C!["label", "has-text-white", "mt-5"]],
+        div![
+            C!["control", "has-icons-right"],
+            style! {St::Width => "max-content"},
+            input![
+                C!["input"],
+                attrs! {At::Value => rsp_settings.skip_unplayable_songs, At::Type => "number"},
+                input_ev(Ev::Input, move |value| { Msg::InputRspSkipUnplayableSongsChange(value) }),
+            ],
+            view_validation_icon(rsp_settings, "skip_unplayable_songs")
+        ],
+        div![
+            C!["field", "mt-5"],
+            ev(Ev::Click, |_| Msg::ToggleRspIgnoreUnplayableFiles),
+            input![
+                C!["switch"],
+                attrs! {
+                    At::Name => "ignoreunplayable_cb"
+                    At::Type => "checkbox"
+                    At::Checked => rsp_settings.ignore_unplayable_files.as_at_value(),
+                },
+            ],
+            label![
+                C!["label", "has-text-white"],
+                "Remove unplayable files from the library",
+                attrs! {
+                    At::For => "ignoreunplayable_cb"
+                }
+            ]
+        ],
+        label!["Audio output", C!["label", "has-text-white", "mt-5"]],
+        div![
+            C!["control"],
+            div![
+                C!["select"],
+                select![
+                    OutputBackend::iter().map(|backend| {
+                        let v: &str = backend.into();
+                        option![
+                            attrs!(At::Value => v),
+                            IF!(rsp_settings.output_backend == backend => attrs!(At::Selected => "")),
+                            v
+                        ]
+                    }),
+                    input_ev(Ev::Change, move |v| Msg::InputRspOutputBackendChange(
+                        OutputBackend::from_str(v.as_str()).expect("msg")
+                    )),
+                ],
+            ],
+        ],
+        IF!(rsp_settings.output_backend == OutputBackend::Alsa => nodes![
+            label!["ALSA period size in frames, empty for the driver default", C!["label", "has-text-white", "mt-5"]],
+            div![
+                C!["control"],
+                style! {St::Width => "max-content"},
+                input![
+                    C!["input"],
+                    attrs! {
+                        At::Value => rsp_settings.alsa_period_size.map(|size| size.to_string()).unwrap_or_default(),
+                        At::Type => "number"
+                    },
+                    input_ev(Ev::Input, move |value| { Msg::InputRspAlsaPeriodSizeChange(value) }),
+                ],
+            ],
+            div![
+                C!["field", "mt-5"],
+                ev(Ev::Click, |_| Msg::ToggleRspAlsaMmap),
+                input![
+                    C!["switch"],
+                    attrs! {
+                        At::Name => "alsammap_cb"
+                        At::Type => "checkbox"
+                        At::Checked => rsp_settings.alsa_mmap.as_at_value(),
+                    },
+                ],
+                label![
+                    C!["label", "has-text-white"],
+                    "Write to the memory mapped ALSA buffer",
+                    attrs! {
+                        At::For => "alsammap_cb"
+                    }
+                ]
+            ],
+        ]),
         div![
             C!["field", "mt-5"],
             ev(Ev::Click, |_| Msg::ToggleRspAlsaBufferSize),
@@ -847,7 +1209,87 @@ fn view_rsp(rsp_settings: &RsPlayerSettings) -> Node<Msg> {
                     ],
                 ],
             ]
-        )
+        ),
+        div![
+            C!["field", "mt-5"],
+            ev(Ev::Click, |_| Msg::ToggleRspResampling),
+            input![
+                C!["switch"],
+                attrs! {
+                    At::Name => "resample_cb"
+                    At::Type => "checkbox"
+                    At::Checked => rsp_settings.resample_rate.is_some().as_at_value(),
+                },
+            ],
+            label![
+                C!["label", "has-text-white"],
+                "Resample all songs to a fixed sample rate",
+                attrs! {
+                    At::For => "resample_cb"
+                }
+            ]
+        ],
+        IF!(rsp_settings.resample_rate.is_some() =>
+            div![
+                C!["field"],
+                div![
+                    C!["control"],
+                    div![
+                        C!["select"],
+                        select![
+                            [44_100, 48_000, 88_200, 96_000, 176_400, 192_000, 352_800, 384_000].iter().map(|rate| {
+                                option![
+                                    attrs!(At::Value => rate),
+                                    IF!(rsp_settings.resample_rate == Some(*rate) => attrs!(At::Selected => "")),
+                                    format!("{rate} Hz")
+                                ]
+                            }),
+                            input_ev(Ev::Change, Msg::InputRspResampleRateChange),
+                        ],
+                    ],
+                ],
+            ]
+        ),
+        label!["DSD output", C!["label", "has-text-white", "mt-5"]],
+        div![
+            C!["control"],
+            div![
+                C!["select"],
+                select![
+                    DsdOutput::iter().map(|mode| {
+                        let v: &str = mode.into();
+                        option![
+                            attrs!(At::Value => v),
+                            IF!(rsp_settings.dsd_output == mode => attrs!(At::Selected => "")),
+                            v
+                        ]
+                    }),
+                    input_ev(Ev::Change, move |v| Msg::InputRspDsdOutputChange(
+                        DsdOutput::from_str(v.as_str()).expect("msg")
+                    )),
+                ],
+            ],
+        ],
+        label!["HTTP stream (/api/stream)", C!["label", "has-text-white", "mt-5"]],
+        div![
+            C!["control"],
+            div![
+                C!["select"],
+                select![
+                    HttpStreamMode::iter().map(|mode| {
+                        let v: &str = mode.into();
+                        option![
+                            attrs!(At::Value => v),
+                            IF!(rsp_settings.http_stream_mode == mode => attrs!(At::Selected => "")),
+                            v
+                        ]
+                    }),
+                    input_ev(Ev::Change, move |v| Msg::InputRspHttpStreamModeChange(
+                        HttpStreamMode::from_str(v.as_str()).expect("msg")
+                    )),
+                ],
+            ],
+        ]
     ]
 }
 